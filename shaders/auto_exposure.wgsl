@@ -0,0 +1,108 @@
+struct Uniform {
+    pos: vec3<f32>,
+    frame: u32,
+    resolution: vec2<f32>,
+    mouse: vec2<f32>,
+    mouse_pressed: u32,
+    time: f32,
+    time_delta: f32,
+};
+
+@group(0) @binding(0)
+var<uniform> un: Uniform;
+
+@group(1) @binding(0)
+var src_texture: texture_2d<f32>;
+
+struct Params {
+    min_ev: f32,
+    max_ev: f32,
+    speed: f32,
+};
+
+struct Exposure {
+    ev: f32,
+    initialized: u32,
+};
+
+@group(2) @binding(0)
+var<uniform> params: Params;
+@group(2) @binding(1)
+var<storage, read_write> histogram: array<atomic<u32>, 256>;
+@group(2) @binding(2)
+var<storage, read_write> exposure: Exposure;
+
+const BINS: u32 = 256u;
+// Exposure brings the average luminance of the frame to this value.
+const MIDDLE_GREY: f32 = 0.18;
+
+var<workgroup> local_bins: array<atomic<u32>, 256>;
+var<workgroup> weighted: array<f32, 256>;
+var<workgroup> counts: array<u32, 256>;
+
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3(0.2126, 0.7152, 0.0722));
+}
+
+// Bin 0 collects pixels too dark to measure, the rest cover [min_ev, max_ev].
+fn bin(lum: f32) -> u32 {
+    if lum < exp2(params.min_ev) {
+        return 0u;
+    }
+    let t = saturate((log2(lum) - params.min_ev) / (params.max_ev - params.min_ev));
+    return u32(t * f32(BINS - 2u)) + 1u;
+}
+
+@compute @workgroup_size(16, 16, 1)
+fn histogram_main(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32,
+) {
+    atomicStore(&local_bins[local_index], 0u);
+    workgroupBarrier();
+
+    if all(global_id.xy < textureDimensions(src_texture)) {
+        let color = textureLoad(src_texture, global_id.xy, 0).rgb;
+        atomicAdd(&local_bins[bin(luminance(color))], 1u);
+    }
+    workgroupBarrier();
+
+    atomicAdd(&histogram[local_index], atomicLoad(&local_bins[local_index]));
+}
+
+@compute @workgroup_size(256, 1, 1)
+fn average_main(@builtin(local_invocation_index) local_index: u32) {
+    // Clears the histogram for the next frame while reading it.
+    let count = select(atomicExchange(&histogram[local_index], 0u), 0u, local_index == 0u);
+    weighted[local_index] = f32(count) * f32(local_index);
+    counts[local_index] = count;
+    workgroupBarrier();
+
+    for (var stride = BINS / 2u; stride > 0u; stride = stride / 2u) {
+        if local_index < stride {
+            weighted[local_index] += weighted[local_index + stride];
+            counts[local_index] += counts[local_index + stride];
+        }
+        workgroupBarrier();
+    }
+
+    if local_index == 0u {
+        // Frames with every pixel in bin 0, like an empty volume on a black background,
+        // count as sitting at min_ev instead of freezing the exposure.
+        var average_ev = params.min_ev;
+        if counts[0] > 0u {
+            // Dividing by the pixel count keeps the result independent of the resolution.
+            let mean_bin = weighted[0] / f32(counts[0]);
+            let t = (mean_bin - 0.5) / f32(BINS - 2u);
+            average_ev = mix(params.min_ev, params.max_ev, saturate(t));
+        }
+        let target_ev = log2(MIDDLE_GREY) - average_ev;
+        if exposure.initialized == 0u {
+            exposure.ev = target_ev;
+            exposure.initialized = 1u;
+        } else {
+            let blend = 1.0 - exp(-un.time_delta * params.speed);
+            exposure.ev = mix(exposure.ev, target_ev, blend);
+        }
+    }
+}
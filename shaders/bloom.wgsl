@@ -0,0 +1,126 @@
+@group(0) @binding(0)
+var src_texture: texture_2d<f32>;
+
+struct Params {
+    threshold: f32,
+    knee: f32,
+    intensity: f32,
+    radius: f32,
+    mip_count: u32,
+};
+
+@group(1) @binding(0)
+var<uniform> params: Params;
+@group(1) @binding(1)
+var src_sampler: sampler;
+
+// Only bound by `composite`, the top of the upsampled chain.
+@group(2) @binding(0)
+var bloom_texture: texture_2d<f32>;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    let vertex_idx = i32(in_vertex_index);
+    let uv = vec2<f32>(f32((vertex_idx << 1u) & 2), f32(vertex_idx & 2));
+    let position = vec4<f32>(uv.x * 2.0 + -1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    return VertexOutput(position, uv);
+}
+
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3(0.2126, 0.7152, 0.0722));
+}
+
+// Keeps what is brighter than the threshold, with a quadratic knee instead of a hard cut.
+fn soft_threshold(color: vec3<f32>) -> vec3<f32> {
+    let brightness = max(color.r, max(color.g, color.b));
+    var soft = clamp(brightness - params.threshold + params.knee, 0.0, 2.0 * params.knee);
+    soft = soft * soft / (4.0 * params.knee + 1e-5);
+    let contribution = max(soft, brightness - params.threshold) / max(brightness, 1e-5);
+    return color * contribution;
+}
+
+// Weights a group of taps down by its brightness so single hot pixels do not flicker.
+fn karis_average(a: vec3<f32>, b: vec3<f32>, c: vec3<f32>, d: vec3<f32>) -> vec4<f32> {
+    let average = (a + b + c + d) * 0.25;
+    let weight = 1.0 / (1.0 + luminance(average));
+    return vec4(average * weight, weight);
+}
+
+fn tap(uv: vec2<f32>) -> vec3<f32> {
+    return textureSampleLevel(src_texture, src_sampler, uv, 0.0).rgb;
+}
+
+// 13 taps from Jimenez, "Next Generation Post Processing in Call of Duty: Advanced Warfare".
+fn downsample13(uv: vec2<f32>, first: bool) -> vec3<f32> {
+    let t = 1.0 / vec2<f32>(textureDimensions(src_texture));
+    let a = tap(uv + t * vec2(-2.0, -2.0));
+    let b = tap(uv + t * vec2(0.0, -2.0));
+    let c = tap(uv + t * vec2(2.0, -2.0));
+    let d = tap(uv + t * vec2(-2.0, 0.0));
+    let e = tap(uv);
+    let f = tap(uv + t * vec2(2.0, 0.0));
+    let g = tap(uv + t * vec2(-2.0, 2.0));
+    let h = tap(uv + t * vec2(0.0, 2.0));
+    let i = tap(uv + t * vec2(2.0, 2.0));
+    let j = tap(uv + t * vec2(-1.0, -1.0));
+    let k = tap(uv + t * vec2(1.0, -1.0));
+    let l = tap(uv + t * vec2(-1.0, 1.0));
+    let m = tap(uv + t * vec2(1.0, 1.0));
+
+    if first {
+        let groups = karis_average(j, k, l, m) * 0.5
+            + karis_average(a, b, d, e) * 0.125
+            + karis_average(b, c, e, f) * 0.125
+            + karis_average(d, e, g, h) * 0.125
+            + karis_average(e, f, h, i) * 0.125;
+        return soft_threshold(groups.rgb / groups.w);
+    }
+    return e * 0.125
+        + (a + c + g + i) * 0.03125
+        + (b + d + f + h) * 0.0625
+        + (j + k + l + m) * 0.125;
+}
+
+@fragment
+fn downsample_first(vin: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4(downsample13(vin.uv, true), 1.0);
+}
+
+@fragment
+fn downsample(vin: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4(downsample13(vin.uv, false), 1.0);
+}
+
+// 3x3 tent whose spread in source texels is the radius, larger radii give wider glow.
+fn tent(tex: texture_2d<f32>, uv: vec2<f32>) -> vec3<f32> {
+    let t = params.radius / vec2<f32>(textureDimensions(tex));
+    var sum = textureSampleLevel(tex, src_sampler, uv, 0.0).rgb * 4.0;
+    sum += textureSampleLevel(tex, src_sampler, uv + t * vec2(0.0, -1.0), 0.0).rgb * 2.0;
+    sum += textureSampleLevel(tex, src_sampler, uv + t * vec2(-1.0, 0.0), 0.0).rgb * 2.0;
+    sum += textureSampleLevel(tex, src_sampler, uv + t * vec2(1.0, 0.0), 0.0).rgb * 2.0;
+    sum += textureSampleLevel(tex, src_sampler, uv + t * vec2(0.0, 1.0), 0.0).rgb * 2.0;
+    sum += textureSampleLevel(tex, src_sampler, uv + t * vec2(-1.0, -1.0), 0.0).rgb;
+    sum += textureSampleLevel(tex, src_sampler, uv + t * vec2(1.0, -1.0), 0.0).rgb;
+    sum += textureSampleLevel(tex, src_sampler, uv + t * vec2(-1.0, 1.0), 0.0).rgb;
+    sum += textureSampleLevel(tex, src_sampler, uv + t * vec2(1.0, 1.0), 0.0).rgb;
+    return sum / 16.0;
+}
+
+// Blended additively into the next larger mip.
+@fragment
+fn upsample(vin: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4(tent(src_texture, vin.uv), 1.0);
+}
+
+// Every mip summed up along the way, averaging them keeps the energy of the bright parts.
+@fragment
+fn composite(vin: VertexOutput) -> @location(0) vec4<f32> {
+    let scene = textureSampleLevel(src_texture, src_sampler, vin.uv, 0.0);
+    let bloom = tent(bloom_texture, vin.uv) / f32(params.mip_count);
+    return vec4(scene.rgb + bloom * params.intensity, scene.a);
+}
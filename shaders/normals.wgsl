@@ -0,0 +1,113 @@
+@group(0) @binding(0)
+var density: texture_3d<f32>;
+@group(0) @binding(1)
+var normal_tex: texture_storage_3d<rgba16float, write>;
+
+// Voxels to compute, from `min` up to but excluding `max`, and the kernel to use.
+struct Params {
+    min: vec3<u32>,
+    kernel: u32,
+    max: vec3<u32>,
+    // Half the kernel size, 1 or 2.
+    radius: u32,
+};
+
+@group(0) @binding(2)
+var<uniform> params: Params;
+
+// Mirrors `NormalKernel` in `normals.rs`.
+const KERNEL_CENTRAL: u32 = 0u;
+const KERNEL_SOBEL: u32 = 1u;
+
+fn alpha(p: vec3<i32>, dims: vec3<i32>) -> f32 {
+    return textureLoad(density, clamp(p, vec3(0), dims - 1), 0).a;
+}
+
+// Derivative taps at offset `i`, the two point difference or the five point stencil.
+fn central_weight(i: i32) -> f32 {
+    if params.radius == 1u {
+        return f32(i);
+    }
+    switch i {
+        case -2: { return 1.0; }
+        case -1: { return -8.0; }
+        case 1: { return 8.0; }
+        case 2: { return -1.0; }
+        default: { return 0.0; }
+    }
+}
+
+// Sobel derivative taps, [-1 0 1] or [-1 -2 0 2 1].
+fn sobel_derivative(i: i32) -> f32 {
+    if params.radius == 1u {
+        return f32(i);
+    }
+    return f32(sign(i) * (3 - abs(i)));
+}
+
+// Sobel smoothing taps, [1 2 1] or [1 4 6 4 1].
+fn sobel_smoothing(i: i32) -> f32 {
+    if params.radius == 1u {
+        return f32(2 - abs(i));
+    }
+    switch abs(i) {
+        case 0: { return 6.0; }
+        case 1: { return 4.0; }
+        default: { return 1.0; }
+    }
+}
+
+fn central_gradient(p: vec3<i32>, dims: vec3<i32>) -> vec3<f32> {
+    let r = i32(params.radius);
+    var grad = vec3(0.0);
+    for (var i = -r; i <= r; i++) {
+        let w = central_weight(i);
+        grad += w * vec3(
+            alpha(p + vec3(i, 0, 0), dims),
+            alpha(p + vec3(0, i, 0), dims),
+            alpha(p + vec3(0, 0, i), dims),
+        );
+    }
+    return grad;
+}
+
+// Every tap of the cube adds to all three axes, differentiating along one and smoothing along the others.
+fn sobel_gradient(p: vec3<i32>, dims: vec3<i32>) -> vec3<f32> {
+    let r = i32(params.radius);
+    var grad = vec3(0.0);
+    for (var z = -r; z <= r; z++) {
+        for (var y = -r; y <= r; y++) {
+            for (var x = -r; x <= r; x++) {
+                let a = alpha(p + vec3(x, y, z), dims);
+                let d = vec3(sobel_derivative(x), sobel_derivative(y), sobel_derivative(z));
+                let s = vec3(sobel_smoothing(x), sobel_smoothing(y), sobel_smoothing(z));
+                grad += a * vec3(d.x * s.y * s.z, s.x * d.y * s.z, s.x * s.y * d.z);
+            }
+        }
+    }
+    return grad;
+}
+
+// Gradient of the opacity, pointing toward denser voxels.
+@compute @workgroup_size(8, 8, 8)
+fn cs_main(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let global_id = invocation_id + params.min;
+    if any(global_id >= params.max) {
+        return;
+    }
+    let dims = vec3<i32>(textureDimensions(density));
+    let p = vec3<i32>(global_id);
+
+    var grad: vec3<f32>;
+    if params.kernel == KERNEL_SOBEL {
+        grad = sobel_gradient(p, dims);
+    } else {
+        grad = central_gradient(p, dims);
+    }
+    let len = length(grad);
+    var normal = vec3(0.);
+    if (len > 1e-6) {
+        normal = grad / len;
+    }
+    textureStore(normal_tex, global_id, vec4<f32>(normal, length(normal)));
+}
@@ -0,0 +1,116 @@
+struct Uniform {
+    pos: vec3<f32>,
+    frame: u32,
+    resolution: vec2<f32>,
+    mouse: vec2<f32>,
+    mouse_pressed: u32,
+    time: f32,
+    time_delta: f32,
+};
+
+@group(0) @binding(0)
+var<uniform> un: Uniform;
+
+@group(1) @binding(0)
+var src_texture: texture_2d<f32>;
+
+struct Params {
+    vignette_intensity: f32,
+    vignette_radius: f32,
+    vignette_smoothness: f32,
+    grain_intensity: f32,
+    grain_size: f32,
+    aberration_strength: f32,
+    lut_strength: f32,
+};
+
+@group(2) @binding(0)
+var<uniform> params: Params;
+@group(2) @binding(1)
+var src_sampler: sampler;
+
+struct LutDomain {
+    min: vec4<f32>,
+    max: vec4<f32>,
+};
+
+@group(3) @binding(0)
+var lut_texture: texture_3d<f32>;
+@group(3) @binding(1)
+var lut_sampler: sampler;
+@group(3) @binding(2)
+var<uniform> lut_domain: LutDomain;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    let vertex_idx = i32(in_vertex_index);
+    let uv = vec2<f32>(f32((vertex_idx << 1u) & 2), f32(vertex_idx & 2));
+    let position = vec4<f32>(uv.x * 2.0 + -1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    return VertexOutput(position, uv);
+}
+
+fn pcg(v: u32) -> u32 {
+    let state = v * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// Darkens towards the corners, `radius` and `smoothness` are in units of the half diagonal.
+@fragment
+fn vignette(vin: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(src_texture, src_sampler, vin.uv);
+    let dist = length(vin.uv - 0.5) * sqrt(2.0);
+    let edge = smoothstep(params.vignette_radius, params.vignette_radius + params.vignette_smoothness, dist);
+    return vec4(color.rgb * (1.0 - params.vignette_intensity * edge), color.a);
+}
+
+// Multiplicative noise so it scales with the HDR values, new every frame.
+@fragment
+fn film_grain(vin: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(src_texture, src_sampler, vin.uv);
+    let cell = vec2<u32>(vin.position.xy / params.grain_size);
+    let seed = pcg(cell.x + pcg(cell.y + pcg(un.frame)));
+    let noise = f32(seed) / 4294967295.0 - 0.5;
+    return vec4(color.rgb * max(1.0 + params.grain_intensity * noise, 0.0), color.a);
+}
+
+// Splits red and blue apart along the direction from the center, growing towards the edges.
+@fragment
+fn chromatic_aberration(vin: VertexOutput) -> @location(0) vec4<f32> {
+    let offset = (vin.uv - 0.5) * params.aberration_strength;
+    let r = textureSample(src_texture, src_sampler, vin.uv + offset).r;
+    let ga = textureSample(src_texture, src_sampler, vin.uv).ga;
+    let b = textureSample(src_texture, src_sampler, vin.uv - offset).b;
+    return vec4(r, ga.x, b, ga.y);
+}
+
+// The sRGB curve extended past 1, so HDR values survive the round trip.
+fn srgb_encode(x: vec3<f32>) -> vec3<f32> {
+    return select(1.055 * pow(x, vec3(1.0 / 2.4)) - 0.055, 12.92 * x, x <= vec3(0.0031308));
+}
+
+fn srgb_decode(x: vec3<f32>) -> vec3<f32> {
+    return select(pow((x + 0.055) / 1.055, vec3(2.4)), x / 12.92, x <= vec3(0.04045));
+}
+
+// Looks up the sRGB encoded color like the grading of the present pass, whatever lies
+// outside the LUT domain is carried over so highlights still reach the tone mapping.
+@fragment
+fn lut(vin: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(src_texture, src_sampler, vin.uv);
+    let encoded = srgb_encode(max(color.rgb, vec3(0.0)));
+    let extent = lut_domain.max.xyz - lut_domain.min.xyz;
+    let coords = saturate((encoded - lut_domain.min.xyz) / extent);
+    let size = f32(textureDimensions(lut_texture).x);
+    // Texel centers, so 0 and 1 hit the first and last entries.
+    let uvw = coords * (size - 1.0) / size + 0.5 / size;
+    let graded = textureSampleLevel(lut_texture, lut_sampler, uvw, 0.0).rgb;
+    let outside = encoded - (lut_domain.min.xyz + coords * extent);
+    let mixed = mix(encoded, graded + outside, params.lut_strength);
+    return vec4(srgb_decode(max(mixed, vec3(0.0))), color.a);
+}
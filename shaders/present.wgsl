@@ -20,6 +20,65 @@ var src_texture: texture_2d<f32>;
 @group(2) @binding(0)
 var src_sampler: sampler;
 
+struct Params {
+    exposure: f32,
+    tonemap: u32,
+    upscale: u32,
+    white_point: f32,
+    auto_exposure: u32,
+    output: u32,
+    lut_strength: f32,
+};
+
+struct Exposure {
+    ev: f32,
+    initialized: u32,
+};
+
+@group(3) @binding(0)
+var<uniform> params: Params;
+@group(3) @binding(1)
+var<uniform> measured: Exposure;
+
+struct LutDomain {
+    min: vec4<f32>,
+    max: vec4<f32>,
+};
+
+@group(4) @binding(0)
+var lut_texture: texture_3d<f32>;
+@group(4) @binding(1)
+var lut_sampler: sampler;
+@group(4) @binding(2)
+var<uniform> lut_domain: LutDomain;
+
+const TONEMAP_ACES: u32 = 0u;
+const TONEMAP_NONE: u32 = 1u;
+const TONEMAP_REINHARD: u32 = 2u;
+const TONEMAP_REINHARD_EXTENDED: u32 = 3u;
+const TONEMAP_UNCHARTED2: u32 = 4u;
+const TONEMAP_AGX: u32 = 5u;
+const TONEMAP_NEUTRAL: u32 = 6u;
+
+// How the surface expects its colors.
+const OUTPUT_UNORM: u32 = 0u;
+const OUTPUT_SRGB: u32 = 1u;
+const OUTPUT_HDR: u32 = 2u;
+
+const UPSCALE_NEAREST: u32 = 0u;
+const UPSCALE_BILINEAR: u32 = 1u;
+const UPSCALE_QUADRATIC: u32 = 2u;
+const UPSCALE_BICUBIC: u32 = 3u;
+const UPSCALE_LANCZOS: u32 = 4u;
+const UPSCALE_CAS: u32 = 5u;
+
+fn srgb_to_linear(col: vec3<f32>) -> vec3<f32> {
+    let selector = ceil(col - 0.04045);
+    let under = col / 12.92;
+    let over = pow((col + 0.055) / 1.055, vec3<f32>(2.4));
+    return mix(under, over, selector);
+}
+
 fn linear_to_srgb(col: vec4<f32>) -> vec4<f32> {
     let color_linear = col.rgb;
     let selector = ceil(color_linear - 0.0031308);
@@ -34,12 +93,117 @@ fn ACESFilm(x: vec3<f32>) -> vec3<f32> {
     return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), vec3(0.0), vec3(1.0));
 }
 
+fn reinhard_extended(x: vec3<f32>, white: f32) -> vec3<f32> {
+    return x * (1.0 + x / (white * white)) / (1.0 + x);
+}
+
+// http://filmicworlds.com/blog/filmic-tonemapping-operators/
+fn hable(x: vec3<f32>) -> vec3<f32> {
+    let a = 0.15;
+    let b = 0.50;
+    let c = 0.10;
+    let d = 0.20;
+    let e = 0.02;
+    let f = 0.30;
+    return ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f;
+}
+
+fn uncharted2(x: vec3<f32>, white: f32) -> vec3<f32> {
+    return hable(2.0 * x) / hable(vec3(white));
+}
+
+// https://iolite-engine.com/blog_posts/minimal_agx_implementation
+fn agx_contrast(x: vec3<f32>) -> vec3<f32> {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x
+        + 0.4298 * x2 + 0.1191 * x - 0.00232;
+}
+
+fn agx(x: vec3<f32>) -> vec3<f32> {
+    let inset = mat3x3<f32>(
+        float3(0.842479062253094, 0.0423282422610123, 0.0423756549057051),
+        float3(0.0784335999999992, 0.878468636469772, 0.0784336),
+        float3(0.0792237451477643, 0.0791661274605434, 0.879142973793104),
+    );
+    let outset = mat3x3<f32>(
+        float3(1.19687900512017, -0.0528968517574562, -0.0529716355144438),
+        float3(-0.0980208811401368, 1.15190312990417, -0.0980434501171241),
+        float3(-0.0990297440797205, -0.0989611768448433, 1.15107367264116),
+    );
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+    var v = inset * x;
+    v = clamp(log2(max(v, vec3(1e-10))), vec3(min_ev), vec3(max_ev));
+    v = (v - min_ev) / (max_ev - min_ev);
+    v = outset * agx_contrast(v);
+    return clamp(pow(max(v, vec3(0.0)), vec3(2.2)), vec3(0.0), vec3(1.0));
+}
+
+// https://github.com/KhronosGroup/ToneMapping/tree/main/PBR_Neutral
+fn pbr_neutral(color: vec3<f32>) -> vec3<f32> {
+    let start_compression = 0.8 - 0.04;
+    let desaturation = 0.15;
+
+    let x = min(color.r, min(color.g, color.b));
+    let offset = select(0.04, x - 6.25 * x * x, x < 0.08);
+    let c = color - offset;
+    let peak = max(c.r, max(c.g, c.b));
+    if peak < start_compression {
+        return clamp(c, vec3(0.0), vec3(1.0));
+    }
+
+    let d = 1.0 - start_compression;
+    let new_peak = 1.0 - d * d / (peak + d - start_compression);
+    let compressed = c * (new_peak / peak);
+    let g = 1.0 - 1.0 / (desaturation * (peak - new_peak) + 1.0);
+    return clamp(mix(compressed, vec3(new_peak), g), vec3(0.0), vec3(1.0));
+}
+
+fn expose(x: vec3<f32>) -> vec3<f32> {
+    let auto_ev = select(0.0, measured.ev, params.auto_exposure != 0u);
+    return max(x * exp2(params.exposure + auto_ev), vec3(0.0));
+}
+
+fn tonemap(x: vec3<f32>) -> vec3<f32> {
+    let exposed = expose(x);
+    switch params.tonemap {
+        case TONEMAP_NONE: {
+            return clamp(exposed, vec3(0.0), vec3(1.0));
+        }
+        case TONEMAP_REINHARD: {
+            return exposed / (1.0 + exposed);
+        }
+        case TONEMAP_REINHARD_EXTENDED: {
+            return clamp(reinhard_extended(exposed, params.white_point), vec3(0.0), vec3(1.0));
+        }
+        case TONEMAP_UNCHARTED2: {
+            return clamp(uncharted2(exposed, params.white_point), vec3(0.0), vec3(1.0));
+        }
+        case TONEMAP_AGX: {
+            return agx(exposed);
+        }
+        case TONEMAP_NEUTRAL: {
+            return pbr_neutral(exposed);
+        }
+        default: {
+            return ACESFilm(exposed);
+        }
+    }
+}
+
 fn tex_sample(tex: texture_2d<f32>, uv: vec2<f32>) -> float4 {
     return textureSample(tex, src_sampler, uv);
 }
 
+fn texture_nearest(tex: texture_2d<f32>, uv: vec2<f32>) -> vec4<f32> {
+    let tex_size = vec2<i32>(textureDimensions(tex));
+    let texel = clamp(vec2<i32>(floor(uv * vec2<f32>(tex_size))), vec2(0), tex_size - 1);
+    return textureLoad(tex, texel, 0);
+}
+
 fn texture_quadratic(tex: texture_2d<f32>, uv: vec2<f32>) -> vec4<f32> {
-    let tex_size = f32(textureDimensions(tex).x);
+    let tex_size = vec2<f32>(textureDimensions(tex));
     var p = uv * tex_size;
     let i = floor(p);
     var f = fract(p);
@@ -48,8 +212,8 @@ fn texture_quadratic(tex: texture_2d<f32>, uv: vec2<f32>) -> vec4<f32> {
     f = f * f * (3.0 - 2.0 * f); // optional for extra sweet
     let w = 0.5 / tex_size;
     var res = mix(
-        mix(tex_sample(tex, p + float2(0., 0.)), tex_sample(tex, p + float2(w, 0.0)), f.x),
-        mix(tex_sample(tex, p + float2(0., w)), tex_sample(tex, p + float2(w, w)), f.x),
+        mix(tex_sample(tex, p + float2(0., 0.)), tex_sample(tex, p + float2(w.x, 0.0)), f.x),
+        mix(tex_sample(tex, p + float2(0., w.y)), tex_sample(tex, p + w), f.x),
         f.y
     );
     return res;
@@ -89,6 +253,74 @@ fn texture_bicubic(tex: texture_2d<f32>, p: vec2<f32>) -> vec4<f32> {
     return g0(fuv.y) * (g0x * tex_sample(tex, p0) + g1x * tex_sample(tex, p1)) + g1(fuv.y) * (g0x * tex_sample(tex, p2) + g1x * tex_sample(tex, p3));
 }
 
+fn lanczos2(x: f32) -> f32 {
+    let PI = 3.14159265;
+    if (abs(x) < 1e-5) {
+        return 1.;
+    }
+    if (abs(x) >= 2.) {
+        return 0.;
+    }
+    let px = PI * x;
+    return 2. * sin(px) * sin(px * 0.5) / (px * px);
+}
+
+// Separable 4x4 Lanczos-2, sharper than the B-spline but it rings around hard edges.
+fn texture_lanczos(tex: texture_2d<f32>, uv: vec2<f32>) -> vec4<f32> {
+    let tex_size = vec2<i32>(textureDimensions(tex));
+    let p = uv * vec2<f32>(tex_size) - 0.5;
+    let base = vec2<i32>(floor(p));
+    let f = p - floor(p);
+    var sum = vec4(0.);
+    var weight_sum = 0.;
+    for (var y = -1; y <= 2; y++) {
+        let wy = lanczos2(f32(y) - f.y);
+        for (var x = -1; x <= 2; x++) {
+            let w = lanczos2(f32(x) - f.x) * wy;
+            let texel = clamp(base + vec2(x, y), vec2(0), tex_size - 1);
+            sum += textureLoad(tex, texel, 0) * w;
+            weight_sum += w;
+        }
+    }
+    return max(sum / weight_sum, vec4(0.));
+}
+
+// Contrast adaptive sharpening after AMD FidelityFX CAS, works on tone mapped colors
+// because its sharpening limits assume values in [0, 1].
+fn cas(tex: texture_2d<f32>, uv: vec2<f32>) -> vec3<f32> {
+    let texel = 1. / vec2<f32>(textureDimensions(tex));
+    let c = tonemap(tex_sample(tex, uv).rgb);
+    let n = tonemap(tex_sample(tex, uv + vec2(0., -texel.y)).rgb);
+    let s = tonemap(tex_sample(tex, uv + vec2(0., texel.y)).rgb);
+    let w = tonemap(tex_sample(tex, uv + vec2(-texel.x, 0.)).rgb);
+    let e = tonemap(tex_sample(tex, uv + vec2(texel.x, 0.)).rgb);
+    let mn = min(c, min(min(n, s), min(w, e)));
+    let mx = max(c, max(max(n, s), max(w, e)));
+    let amplitude = sqrt(clamp(min(mn, 1. - mx) / max(mx, vec3(1e-5)), vec3(0.), vec3(1.)));
+    // Sharpness of 0.5 between the -1/8 and -1/5 extremes of the original.
+    let weight = amplitude * (-1. / mix(8., 5., 0.5));
+    return clamp((c + (n + s + w + e) * weight) / (1. + 4. * weight), vec3(0.), vec3(1.));
+}
+
+fn upscale(tex: texture_2d<f32>, uv: vec2<f32>) -> vec4<f32> {
+    switch params.upscale {
+        case UPSCALE_NEAREST: {
+            return texture_nearest(tex, uv);
+        }
+        case UPSCALE_QUADRATIC: {
+            return texture_quadratic(tex, uv);
+        }
+        case UPSCALE_BICUBIC: {
+            return texture_bicubic(tex, uv);
+        }
+        case UPSCALE_LANCZOS: {
+            return texture_lanczos(tex, uv);
+        }
+        default: {
+            return tex_sample(tex, uv);
+        }
+    }
+}
 
 struct VertexOutput {
     @builtin(position) position: vec4<f32>,
@@ -108,17 +340,52 @@ struct FragmentOutput {
     @location(1) secnd: vec4<f32>,
 };
 
-@fragment
-fn fs_main(vin: VertexOutput) -> FragmentOutput {
-    let col_tex = tex_sample(src_texture, vin.uv);
-    // let col_tex = texture_quadratic(src_texture, vin.uv);
-    // let col_tex = texture_bicubic(src_texture, vin.uv);
-    let col_vec = vec4(ACESFilm(col_tex.rgb), col_tex.a);
-    let col = linear_to_srgb(col_vec);
-    return FragmentOutput(col, col);
+// Looks up the sRGB encoded color in the LUT with trilinear filtering,
+// grades are authored for display encoded values.
+fn grade(ldr: vec3<f32>) -> vec3<f32> {
+    if (params.lut_strength <= 0.0) {
+        return ldr;
+    }
+    let encoded = linear_to_srgb(vec4(ldr, 1.0)).rgb;
+    let coords = saturate((encoded - lut_domain.min.xyz) / (lut_domain.max.xyz - lut_domain.min.xyz));
+    let size = f32(textureDimensions(lut_texture).x);
+    // Texel centers, so 0 and 1 hit the first and last entries.
+    let uvw = coords * (size - 1.0) / size + 0.5 / size;
+    let graded = textureSampleLevel(lut_texture, lut_sampler, uvw, 0.0).rgb;
+    return srgb_to_linear(saturate(mix(encoded, graded, params.lut_strength)));
+}
+
+// `ldr` is tone mapped and graded linear color, float and sRGB surfaces take it
+// as is and unorm ones get the sRGB curve. Captures always get the encoded color.
+fn encode_outputs(ldr: vec4<f32>) -> FragmentOutput {
+    let encoded = linear_to_srgb(ldr);
+    switch params.output {
+        case OUTPUT_SRGB, OUTPUT_HDR: {
+            return FragmentOutput(ldr, encoded);
+        }
+        default: {
+            return FragmentOutput(encoded, encoded);
+        }
+    }
 }
 
 @fragment
-fn fs_main_raw(vin: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(src_texture, src_sampler, vin.uv);
+fn fs_main(vin: VertexOutput) -> FragmentOutput {
+    var col_tex: vec4<f32>;
+    if (all(vec2<f32>(textureDimensions(src_texture)) == un.resolution)) {
+        // Filters only matter when the backbuffer and the window differ in size.
+        col_tex = texture_nearest(src_texture, vin.uv);
+    } else {
+        col_tex = upscale(src_texture, vin.uv);
+    }
+    var ldr: vec4<f32>;
+    if (params.upscale == UPSCALE_CAS) {
+        // CAS sharpens the tone mapped neighbours instead of the upscaled sample.
+        ldr = vec4(cas(src_texture, vin.uv), 1.);
+    } else {
+        ldr = vec4(tonemap(col_tex.rgb), col_tex.a);
+    }
+    ldr = vec4(grade(ldr.rgb), ldr.a);
+    return encode_outputs(ldr);
 }
+
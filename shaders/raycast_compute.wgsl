@@ -14,6 +14,18 @@ struct Camera {
 	inv_proj: mat4x4<f32>,
 };
 
+struct Params {
+	clear_color: vec4<f32>,
+	light_dir: vec3<f32>,
+	step_scale: f32,
+	box_extent: vec3<f32>,
+	opacity_cutoff: f32,
+	modulate: u32,
+	// Frames already averaged into `accumulation`, zero restarts the average.
+	sample_index: u32,
+	volume_filter: u32,
+};
+
 struct Offset {
 	x: f32,
 	y: f32
@@ -24,12 +36,20 @@ var<uniform> un: Uniform;
 @group(1) @binding(0)
 var<uniform> cam: Camera;
 @group(2) @binding(0)
-var volume: texture_storage_3d<rgba16float, read_write>;
+var volume: texture_3d<f32>;
 @group(2) @binding(1)
-var volume_normal: texture_storage_3d<rgba16float, read_write>;
+var volume_normal: texture_3d<f32>;
+@group(2) @binding(2)
+var volume_sampler: sampler;
 @group(3) @binding(0)
 var out_tex: texture_storage_2d<rgba16float, write>;
+@group(3) @binding(1)
+var<storage, read_write> accumulation: array<vec4<f32>>;
 @group(4) @binding(0)
+var<uniform> params: Params;
+@group(4) @binding(1)
+var transfer_function: texture_1d<f32>;
+@group(5) @binding(0)
 var<storage> dyn_offset: Offset;
 
 var<private> tmin: f32 = 0.;
@@ -39,9 +59,14 @@ const NUM_STEPS: i32 = 100;
 const MIN_DIST: f32 = 0.0;
 const MAX_DIST: f32 = 5.0;
 
+// Mirrors `VolumeFilter` in `raycast.rs`.
+const FILTER_NEAREST: u32 = 0u;
+const FILTER_TRILINEAR: u32 = 1u;
+const FILTER_TRICUBIC: u32 = 2u;
+
 fn intersect_box(orig: vec3<f32>, dir: vec3<f32>) -> vec2<f32> {
-    let box_min = vec3(-1.0);
-    let box_max = vec3(1.0);
+    let box_min = -params.box_extent;
+    let box_max = params.box_extent;
     let inv_dir = 1.0 / dir;
     let tmin_tmp = (box_min - orig) * inv_dir;
     let tmax_tmp = (box_max - orig) * inv_dir;
@@ -52,6 +77,34 @@ fn intersect_box(orig: vec3<f32>, dir: vec3<f32>) -> vec2<f32> {
     return vec2<f32>(t0, t1);
 }
 
+// Linear lookup, compute shaders can't use `textureSample` and 1D textures lack `textureSampleLevel`.
+fn classify(density: f32) -> vec4<f32> {
+    let last = textureDimensions(transfer_function) - 1u;
+    let x = clamp(density, 0., 1.) * f32(last);
+    let i = u32(x);
+    let a = textureLoad(transfer_function, i, 0);
+    let b = textureLoad(transfer_function, min(i + 1u, last), 0);
+    return mix(a, b, fract(x));
+}
+
+fn pcg(v: u32) -> u32 {
+    let state = v * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// Three uniform numbers in [0, 1) per pixel and sample, all zero for the first sample
+// so that a single frame looks the same as without accumulation.
+fn jitter(pixel: vec2<u32>) -> vec3<f32> {
+    if (params.sample_index == 0u) {
+        return vec3(0.);
+    }
+    let a = pcg(pixel.x ^ pcg(pixel.y ^ pcg(params.sample_index)));
+    let b = pcg(a);
+    let c = pcg(b);
+    return vec3<f32>(vec3(a, b, c) >> vec3(8u)) / 16777216.;
+}
+
 fn get_cam(eye: vec3<f32>, tar: vec3<f32>) -> mat3x3<f32> {
     let zaxis = normalize(tar - eye);
     let xaxis = normalize(cross(zaxis, vec3(0., 1., 0.)));
@@ -59,24 +112,70 @@ fn get_cam(eye: vec3<f32>, tar: vec3<f32>) -> mat3x3<f32> {
     return mat3x3(xaxis, yaxis, zaxis);
 }
 
-fn get_col2(eye: vec3<f32>, dir: vec3<f32>, tmin: f32, tmax: f32, clear_color: vec4<f32>) -> vec4<f32> {
+// Cubic B-spline from eight trilinear taps, each placed between two voxels so that the
+// hardware weights add up to the spline weights (GPU Gems 2, chapter 20).
+fn sample_tricubic(tex: texture_3d<f32>, uvw: vec3<f32>) -> vec4<f32> {
+    let size = vec3<f32>(textureDimensions(tex));
+    let coord = uvw * size - 0.5;
+    let index = floor(coord);
+    let f = coord - index;
+    let f2 = f * f;
+    let f3 = f2 * f;
+    let w0 = (1. - 3. * f + 3. * f2 - f3) / 6.;
+    let w1 = (4. - 6. * f2 + 3. * f3) / 6.;
+    let w2 = (1. + 3. * f + 3. * f2 - 3. * f3) / 6.;
+    let w3 = f3 / 6.;
+    let g0 = w0 + w1;
+    let g1 = w2 + w3;
+    let h0 = (index - 0.5 + w1 / g0) / size;
+    let h1 = (index + 1.5 + w3 / g1) / size;
+
+    var color = vec4(0.);
+    for (var i = 0u; i < 8u; i++) {
+        let corner = vec3((i & 1u) != 0u, (i & 2u) != 0u, (i & 4u) != 0u);
+        let g = select(g0, g1, corner);
+        color += g.x * g.y * g.z * textureSampleLevel(tex, volume_sampler, select(h0, h1, corner), 0.);
+    }
+    return color;
+}
+
+// Reads `tex` at the point `p` of the box with the filter picked in `params`.
+fn sample_volume(tex: texture_3d<f32>, p: vec3<f32>) -> vec4<f32> {
+    let uvw = (p / params.box_extent + 1.) * 0.5;
+    switch params.volume_filter {
+        case FILTER_TRILINEAR: {
+            return textureSampleLevel(tex, volume_sampler, uvw, 0.);
+        }
+        case FILTER_TRICUBIC: {
+            return sample_tricubic(tex, uvw);
+        }
+        default: {
+            let block_size = vec3<f32>(textureDimensions(tex));
+            return textureLoad(tex, vec3<i32>((p / params.box_extent + 1.) * (block_size / 2.)), 0);
+        }
+    }
+}
+
+fn get_col2(eye: vec3<f32>, dir: vec3<f32>, tmin: f32, tmax: f32, clear_color: vec4<f32>, start: f32) -> vec4<f32> {
     var color = vec4(clear_color.rgb, 0.1);
-    let light = vec3(0., -1., 0.);
+    let light = normalize(params.light_dir);
     let block_size = vec3<f32>(textureDimensions(volume));
-    let dt_vec = 1.0 / (block_size * abs(dir));
-    let dt_scale = 1.0;
+    let dt_vec = params.box_extent / (block_size * abs(dir));
+    let dt_scale = params.step_scale;
     let dt = dt_scale * max(min(dt_vec.x, min(dt_vec.y, dt_vec.z)), 0.01);
-    for (var t = tmin; t < tmax; t = t + dt) {
+    // Offsetting the first sample by a fraction of a step hides the banding of fixed steps.
+    for (var t = tmin + start * dt; t < tmax; t = t + dt) {
         var p = eye + t * dir;
-        let samp = vec3<i32>((p + 1.) * (block_size / 2.));
-        let vol_content = textureLoad(volume, samp);
-        let normal = textureLoad(volume_normal, samp);
+        let vol_content = sample_volume(volume, p);
+        let normal = sample_volume(volume_normal, p);
         var shade = vec3(max(0., dot(light, normal.rgb)));
 
-        var vol_color = vol_content.rgb;
-
-        var vol_alpha = pow(vol_content.a, 3.0);
-        vol_alpha = smoothstep(0.0, 0.7, vol_alpha);
+        let tf = classify(vol_content.a);
+        var vol_color = tf.rgb;
+        if (params.modulate != 0u) {
+            vol_color *= vol_content.rgb;
+        }
+        var vol_alpha = tf.a;
 
         var directional = 3.0 * vec3(1., .1, .13) * max(dot(normal.xyz, normalize(vec3(-2., -2., -1.))), .0);
         directional *= smoothstep(.3, 1.5, dot(p, normalize(vec3(1., 1., -1.))));
@@ -89,7 +188,7 @@ fn get_col2(eye: vec3<f32>, dir: vec3<f32>, tmin: f32, tmax: f32, clear_color: v
         tmp += clear_color.rgb * clear_color.a * (1.0 - vol_alpha);
         color = vec4(tmp, color.a);
         color.a = color.a + (1.0 - color.a) * vol_alpha * (1. - clear_color.a);
-        if (color.a >= 0.95) {
+        if (color.a >= params.opacity_cutoff) {
 			break;
         }
     }
@@ -99,7 +198,9 @@ fn get_col2(eye: vec3<f32>, dir: vec3<f32>, tmin: f32, tmax: f32, clear_color: v
 fn render(global_id: vec2<u32>, offset_x: f32, offset_y: f32) -> vec4<f32> {
     let time = un.time * 0.5;
 
-    let coord = vec2<f32>(global_id) + vec2(offset_x, offset_y);
+    let pixel = global_id + vec2<u32>(vec2(offset_x, offset_y));
+    let rand = jitter(pixel);
+    let coord = vec2<f32>(pixel) + select(vec2(0.), rand.xy - 0.5, params.sample_index > 0u);
     let dims = vec2<f32>(textureDimensions(out_tex));
     let aspect_ratio = dims.y / dims.x;
 
@@ -115,14 +216,14 @@ fn render(global_id: vec2<u32>, offset_x: f32, offset_y: f32) -> vec4<f32> {
     let eye = view_pos.xyz / view_pos.w;
     let dir = normalize(view_tang.xyz / view_tang.w - eye);
 
-    let clear_color = vec4<f32>(0.023, 0.02, 0.02, 0.0);
+    let clear_color = params.clear_color;
 
     var color = vec4(0.);
     if (any(vec2<f32>(global_id.xy) < dims)) {
         var t_hit = intersect_box(eye, dir);
         if (t_hit.x < t_hit.y) {
             t_hit.x = max(t_hit.x, 0.0);
-            color = vec4(get_col2(eye, dir, t_hit.x, t_hit.y, clear_color).rgb, 1.);
+            color = vec4(get_col2(eye, dir, t_hit.x, t_hit.y, clear_color, rand.z).rgb, 1.);
         } else {
             color = vec4(clear_color.rgb, 1.);
         }
@@ -130,15 +231,30 @@ fn render(global_id: vec2<u32>, offset_x: f32, offset_y: f32) -> vec4<f32> {
     return color;
 }
 
+// Blends `color` into the running average of `pixel` and returns the average.
+fn accumulate(pixel: vec2<u32>, color: vec4<f32>) -> vec4<f32> {
+    let dims = textureDimensions(out_tex);
+    if (any(pixel >= dims)) {
+        return color;
+    }
+    let index = pixel.y * dims.x + pixel.x;
+    var average = color;
+    if (params.sample_index > 0u) {
+        average = mix(accumulation[index], color, 1. / f32(params.sample_index + 1u));
+    }
+    accumulation[index] = average;
+    return average;
+}
+
 @compute @workgroup_size(8, 8, 1)
 fn single(@builtin(global_invocation_id) global_id: vec3<u32>) {
     let color = render(global_id.xy, 0., 0.);
-    textureStore(out_tex, global_id.xy, color);
+    textureStore(out_tex, global_id.xy, accumulate(global_id.xy, color));
 }
 
 @compute @workgroup_size(16, 16, 1)
 fn tile(@builtin(global_invocation_id) global_id: vec3<u32>) {
     let color = render(global_id.xy, dyn_offset.x, dyn_offset.y);
-    let offset = vec2<u32>(vec2(dyn_offset.x, dyn_offset.y));
-    textureStore(out_tex, global_id.xy + offset, color);
+    let pixel = global_id.xy + vec2<u32>(vec2(dyn_offset.x, dyn_offset.y));
+    textureStore(out_tex, pixel, accumulate(pixel, color));
 }
@@ -11,9 +11,38 @@ struct Uniform {
 @group(0) @binding(0)
 var<uniform> un: Uniform;
 @group(1) @binding(0)
-var xor_tex: texture_storage_3d<rgba16float, read_write>;
-@group(1) @binding(1)
-var normal_tex: texture_storage_3d<rgba16float, read_write>;
+var xor_tex: texture_storage_3d<rgba16float, write>;
+
+struct Params {
+    noise_frequency: f32,
+    noise_octaves: u32,
+    xor_frequency: f32,
+    xor_resolution: f32,
+    mandelbulb_power: f32,
+    mandelbulb_iterations: u32,
+    menger_iterations: u32,
+    gyroid_frequency: f32,
+    gyroid_thickness: f32,
+};
+
+@group(2) @binding(0)
+var<uniform> params: Params;
+
+// Voxels being generated, from `min` up to but excluding `max`.
+struct Region {
+    min: vec3<u32>,
+    max: vec3<u32>,
+};
+
+@group(2) @binding(1)
+var<uniform> region: Region;
+
+// Mirrors `Generator` in `xor_compute.rs`.
+const GENERATOR_NOISE: u32 = 0u;
+const GENERATOR_XOR: u32 = 1u;
+const GENERATOR_MANDELBULB: u32 = 2u;
+const GENERATOR_MENGER: u32 = 3u;
+const GENERATOR_GYROID: u32 = 4u;
 
 fn hash(h: f32) -> f32 {
     return fract(sin(h) * 43758.5453123);
@@ -35,18 +64,19 @@ fn noise(x: vec3<f32>) -> f32 {
 fn fbm(p_par: vec3<f32>) -> f32 {
     var p = p_par;
     var f = 0.0;
-    f = 0.5000 * noise(p);
-    p = p * 2.01;
-    f += 0.2500 * noise(p);
-    p = p * 2.02;
-    f += 0.1250 * noise(p);
+    var amplitude = 0.5;
+    for (var i = 0u; i < params.noise_octaves; i++) {
+        f += amplitude * noise(p);
+        amplitude *= 0.5;
+        p = p * (2.01 + 0.01 * f32(i));
+    }
     return f;
 }
 
-fn volume(coord: vec3<f32>) -> vec4<f32> {
+fn xor_volume(coord: vec3<f32>) -> vec4<f32> {
     let t = un.time;
-    let pos = (coord + vec3(1., sin(t * 1.) * 0.1, 21.)) * 32.;
-    let res = 25.;
+    let pos = (coord + vec3(1., sin(t * 1.) * 0.1, 21.)) * params.xor_frequency;
+    let res = params.xor_resolution;
     let val = f32(i32(pos.x * res) & i32(pos.y * res) & i32(pos.z * res)) / res;
     let alpha = val * smoothstep(0.7, 0.0, length(coord));
     return vec4(val, val, val, alpha);
@@ -54,25 +84,125 @@ fn volume(coord: vec3<f32>) -> vec4<f32> {
 
 fn noise_volume(coord: vec3<f32>) -> vec4<f32> {
     let t = un.time;
-    let pos = (coord + vec3(1., sin(t * 1.) * 0.1, 21.)) * 32.;
+    let pos = (coord + vec3(1., sin(t * 1.) * 0.1, 21.)) * params.noise_frequency;
     let val = fbm(pos);
     let alpha = val * smoothstep(0.5, 0.25, length(coord));
     return vec4(val, val, val, alpha);
 }
 
-fn gradient(pos: vec3<f32>, eps_par: f32) -> vec3<f32> {
-    let eps = vec2(eps_par, 0.);
-    let k = mat3x3<f32>(pos, pos, pos) - mat3x3<f32>(eps.xyy, eps.yxy, eps.yyx);
-    return normalize(vec3(noise_volume(pos).a) - vec3(noise_volume(k[0]).a, noise_volume(k[1]).a, noise_volume(k[2]).a));
+// Distance estimated shell of the power N bulb, shaded by how close its orbit comes to the origin.
+fn mandelbulb_volume(coord: vec3<f32>) -> vec4<f32> {
+    let c = coord * 2.6;
+    let power = params.mandelbulb_power;
+    var z = c;
+    var dr = 1.0;
+    var r = length(z);
+    var trap = r;
+    for (var i = 0u; i < params.mandelbulb_iterations; i++) {
+        if r > 2.0 {
+            break;
+        }
+        let theta = acos(clamp(z.z / max(r, 1e-6), -1.0, 1.0)) * power;
+        let phi = atan2(z.y, z.x) * power;
+        dr = pow(r, power - 1.0) * power * dr + 1.0;
+        z = pow(r, power) * vec3(sin(theta) * cos(phi), sin(phi) * sin(theta), cos(theta)) + c;
+        r = length(z);
+        trap = min(trap, r);
+    }
+    let dist = 0.5 * log(max(r, 1e-6)) * r / dr / 2.6;
+    let val = clamp(trap, 0.0, 1.0);
+    let alpha = 1.0 - smoothstep(0.0, 0.005, dist);
+    return vec4(val, val, val, alpha);
 }
 
-@compute @workgroup_size(8, 8, 8)
-fn cs_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+fn box_distance(p: vec3<f32>, b: vec3<f32>) -> f32 {
+    let q = abs(p) - b;
+    return length(max(q, vec3(0.0))) + min(max(q.x, max(q.y, q.z)), 0.0);
+}
+
+// Quilez's Menger sponge, a unit box with a cross carved out at every level.
+fn menger_volume(coord: vec3<f32>) -> vec4<f32> {
+    let p = coord * 2.2;
+    var dist = box_distance(p, vec3(1.0));
+    var s = 1.0;
+    for (var i = 0u; i < params.menger_iterations; i++) {
+        let a = p * s - 2.0 * floor(p * s / 2.0) - 1.0;
+        s *= 3.0;
+        let r = abs(1.0 - 3.0 * abs(a));
+        let da = max(r.x, r.y);
+        let db = max(r.y, r.z);
+        let dc = max(r.z, r.x);
+        dist = max(dist, (min(da, min(db, dc)) - 1.0) / s);
+    }
+    let val = 1.0 - clamp(length(p) / sqrt(3.0), 0.0, 1.0) * 0.5;
+    let alpha = 1.0 - smoothstep(-0.005, 0.005, dist);
+    return vec4(val, val, val, alpha);
+}
+
+// Triply periodic gyroid surface thickened into a sheet, cut to a sphere.
+fn gyroid_volume(coord: vec3<f32>) -> vec4<f32> {
+    let p = coord * params.gyroid_frequency;
+    let g = dot(sin(p), cos(p.yzx));
+    let sheet = 1.0 - smoothstep(0.0, params.gyroid_thickness, abs(g));
+    let val = 0.5 + 0.5 * g / 1.5;
+    let alpha = sheet * smoothstep(0.5, 0.45, length(coord));
+    return vec4(val, val, val, alpha);
+}
+
+fn generator_volume(generator: u32, coord: vec3<f32>) -> vec4<f32> {
+    switch generator {
+        case GENERATOR_XOR: {
+            return xor_volume(coord);
+        }
+        case GENERATOR_MANDELBULB: {
+            return mandelbulb_volume(coord);
+        }
+        case GENERATOR_MENGER: {
+            return menger_volume(coord);
+        }
+        case GENERATOR_GYROID: {
+            return gyroid_volume(coord);
+        }
+        default: {
+            return noise_volume(coord);
+        }
+    }
+}
+
+// `invocation_id` counts from the corner of the region. Normals come from `normals.wgsl` afterwards.
+fn generate(invocation_id: vec3<u32>, generator: u32) {
+    let global_id = invocation_id + region.min;
+    if any(global_id >= region.max) {
+        return;
+    }
     let dims = vec3<f32>(textureDimensions(xor_tex));
     var coord = (vec3<f32>(global_id) - dims / 2.) / dims;
-    let vol = noise_volume(coord);
-    let normal = gradient(coord, 0.0001);
+    let vol = generator_volume(generator, coord);
 
     textureStore(xor_tex, global_id, vec4<f32>(vol.rgb / 2., vol.a));
-    textureStore(normal_tex, global_id, vec4<f32>(normal, length(normal)));
+}
+
+@compute @workgroup_size(8, 8, 8)
+fn cs_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    generate(global_id, GENERATOR_NOISE);
+}
+
+@compute @workgroup_size(8, 8, 8)
+fn cs_xor(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    generate(global_id, GENERATOR_XOR);
+}
+
+@compute @workgroup_size(8, 8, 8)
+fn cs_mandelbulb(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    generate(global_id, GENERATOR_MANDELBULB);
+}
+
+@compute @workgroup_size(8, 8, 8)
+fn cs_menger(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    generate(global_id, GENERATOR_MENGER);
+}
+
+@compute @workgroup_size(8, 8, 8)
+fn cs_gyroid(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    generate(global_id, GENERATOR_GYROID);
 }
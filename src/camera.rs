@@ -61,8 +61,10 @@ impl CameraBinding {
         Self { buffer, bind_group }
     }
 
-    pub(crate) fn update(&mut self, queue: &wgpu::Queue, camera: &mut Camera) {
-        if camera.updated {
+    /// Uploads `camera` if it changed since the last call and reports whether it did.
+    pub(crate) fn update(&mut self, queue: &wgpu::Queue, camera: &mut Camera) -> bool {
+        let updated = camera.updated;
+        if updated {
             queue.write_buffer(
                 &self.buffer,
                 0,
@@ -70,6 +72,7 @@ impl CameraBinding {
             );
             camera.updated = false;
         }
+        updated
     }
 }
 
@@ -82,6 +85,7 @@ pub(crate) struct Camera {
     pub(crate) yaw: f32,
     pub(crate) up: Vec3,
     pub(crate) aspect: f32,
+    pub(crate) fovy: f32,
 
     updated: bool,
 }
@@ -101,8 +105,9 @@ impl Camera {
             target,
             up: Self::UP,
             aspect,
+            fovy: Self::FOVY,
 
-            updated: false,
+            updated: true,
         };
         camera.fix_eye();
         camera
@@ -110,7 +115,7 @@ impl Camera {
 
     pub(crate) fn build_projection_view_matrix(&self) -> Mat4 {
         let view = Mat4::look_at_rh(self.eye, self.target, self.up);
-        let proj = Mat4::perspective_rh(Self::FOVY, self.aspect, Self::ZNEAR, Self::ZFAR);
+        let proj = Mat4::perspective_rh(self.fovy, self.aspect, Self::ZNEAR, Self::ZFAR);
         proj * view
     }
 
@@ -147,6 +152,12 @@ impl Camera {
         self.set_yaw(self.yaw + delta);
     }
 
+    /// Sets the vertical field of view in radians.
+    pub(crate) fn set_fovy(&mut self, fovy: f32) {
+        self.fovy = fovy.clamp(0.01, std::f32::consts::PI - 0.01);
+        self.updated = true;
+    }
+
     fn fix_eye(&mut self) {
         let pitch_cos = self.pitch.cos();
         self.eye = self.target
@@ -0,0 +1,692 @@
+use crate::loaders::{Endian, VoxelType};
+use crate::pipelines::normals::NormalKernel;
+use crate::pipelines::post::PostEffect;
+use crate::pipelines::present::{ToneMapping, Upscaling};
+use crate::pipelines::raycast::{EntryPoint, VolumeFilter};
+use crate::pipelines::xor_compute::Generator;
+use crate::recorder::Recording;
+
+use std::path::PathBuf;
+
+const HELP: &str = "\
+Vokselis XOR: volume raymarching playground
+
+USAGE:
+    vokselis_xor_standalone [OPTIONS]
+
+WINDOW:
+    --window-size <WxH>        Initial window size [default: 1280x720]
+    --present-mode <MODE>      fifo, fifo-relaxed, mailbox, immediate,
+                               auto-vsync or auto-no-vsync [default: fifo]
+    --surface-format <FORMAT>  bgra8unorm, rgba8unorm, bgra8unorm-srgb,
+                               rgba8unorm-srgb or rgba16float for HDR
+                               [default: rgba16float if supported, then sRGB]
+
+RENDERING:
+    --scene <PATH>             TOML file with camera, volume, raycast and
+                               tone mapping settings
+    --shader-dir <PATH>        Folder the shaders are hot-reloaded from
+                               [default: shaders of the source checkout]
+    --render-scale <SCALE>     Raycast backbuffer size relative to the window,
+                               between 0.25 and 4 [default: 1]
+    --target-frame-time <MS>   Adjust the render scale every few frames so the
+                               raycast takes about MS on the GPU, needs
+                               timestamp queries
+    --upscale <FILTER>         nearest, bilinear, quadratic, bicubic, lanczos or
+                               cas, U cycles through them [default: bilinear]
+    --min-render-scale <SCALE> Lower bound of the adjusted scale [default: 0.25]
+    --max-render-scale <SCALE> Upper bound of the adjusted scale [default: 2]
+    --tonemap <OPERATOR>       aces, none, reinhard, reinhard-extended, uncharted2,
+                               agx or neutral, T cycles through them
+                               [default: aces]
+    --exposure <EV>            Exposure in stops, - and = adjust it [default: 0]
+    --white-point <VALUE>      Linear value mapped to white by reinhard-extended
+                               and uncharted2 [default: 11.2]
+    --auto-exposure            Adapt the exposure to the brightness of the frame,
+                               on top of --exposure, E toggles it
+    --lut <PATH>               Color grading .cube file, repeat it to load several
+                               and cycle through them with L, overrides the scene
+    --lut-strength <AMOUNT>    Blend of the graded colors between 0 and 1,
+                               [ and ] adjust it [default: 1]
+    --post <EFFECTS>           Comma separated post-processing chain of vignette,
+                               film-grain, chromatic-aberration, bloom and lut in
+                               the order they run, or none, overrides the scene
+    --generator <NAME>         noise, xor, mandelbulb, menger or gyroid, G cycles
+                               through them, overrides the scene [default: noise]
+    --animate <N>              Regenerate the volume every N frames so it follows
+                               the time, overrides the scene
+    --dirty-box <MIN,MAX>      Part of the volume --animate regenerates, as six
+                               fractions x0,y0,z0,x1,y1,z1 of its size
+                               [default: 0,0,0,1,1,1]
+    --volume-size <N|WxHxD>    Size of the generated volume or of a .raw file,
+                               overrides the scene [default: 256]
+    --volume <PATH>            Load a .raw, .nrrd, .nhdr, .vox, .nii, .nii.gz or
+                               .hdr volume instead of generating one
+    --voxel-type <TYPE>        Voxels of a .raw file: u8, i8, u16, i16, u32, i32,
+                               f32 or f64 [default: u8]
+    --endian <ORDER>           little or big byte order of a .raw file
+                               [default: little]
+    --normals <KERNEL>         central or sobel gradient filter for the normals,
+                               N cycles through kernels and sizes, overrides
+                               the scene [default: central]
+    --normals-size <N>         Side of the normals kernel, 3 or 5 [default: 3]
+    --transfer-function <PATH> JSON transfer function, F5 reloads it
+    --entry-point <NAME>       Raycast shader entry point: single, or tile to
+                               spread each frame over several [default: single]
+    --tile-budget <MS>         GPU time per frame of the tile entry point
+                               [default: 8]
+    --volume-filter <FILTER>   nearest, trilinear or tricubic reads of the
+                               volume, F cycles through them, overrides the
+                               scene [default: trilinear]
+
+ADAPTER:
+    --backend <NAME>           vulkan, metal, dx12, gl, primary or all
+                               [default: primary, all when headless]
+    --power <PREFERENCE>       high or low [default: high]
+    --adapter <NAME>           Use the first adapter whose name contains NAME
+    --fallback-adapter         Force wgpu's fallback (software) adapter
+
+OUTPUT:
+    --headless <PATH>          Render without a window and save the frame as PNG
+    --samples <N>              Jittered frames averaged into the headless frame
+                               [default: 1]
+    --record <DIR>             Save every frame as numbered PNG and EXR files
+    --fps <FPS>                Fixed timestep used while recording [default: 60]
+    --frames <N>               Number of frames to record [default: 60]
+    --export-vox <PATH>        Save the volume as a MagicaVoxel file and exit
+    --save-transfer-function <PATH>
+                               Save the active transfer function as JSON and exit
+
+    -h, --help                 Print this help
+";
+
+/// Settings parsed from the command line.
+#[derive(Debug, Clone)]
+pub(crate) struct Args {
+    pub(crate) window_size: (u32, u32),
+    pub(crate) present_mode: wgpu::PresentMode,
+    /// `None` picks the best format the surface supports.
+    pub(crate) surface_format: Option<wgpu::TextureFormat>,
+
+    pub(crate) scene: Option<PathBuf>,
+    /// `None` watches the shaders of the source checkout.
+    pub(crate) shader_dir: Option<PathBuf>,
+    /// Backbuffer pixels per window pixel along each axis.
+    pub(crate) render_scale: f32,
+    /// Enables dynamic resolution between the two scales below.
+    pub(crate) target_frame_time: Option<f32>,
+    pub(crate) min_render_scale: f32,
+    pub(crate) max_render_scale: f32,
+    /// Present pass settings, each overriding the scene.
+    pub(crate) upscale: Option<Upscaling>,
+    pub(crate) tonemap: Option<ToneMapping>,
+    pub(crate) exposure: Option<f32>,
+    pub(crate) white_point: Option<f32>,
+    /// Enables auto exposure even if the scene does not.
+    pub(crate) auto_exposure: bool,
+    pub(crate) post: Option<Vec<PostEffect>>,
+    /// Empty keeps the LUTs of the scene.
+    pub(crate) luts: Vec<PathBuf>,
+    pub(crate) lut_strength: Option<f32>,
+    /// `None` keeps the generator from the scene.
+    pub(crate) generator: Option<Generator>,
+    /// Frames between regenerations, `None` keeps the animation settings of the scene.
+    pub(crate) animate: Option<u32>,
+    pub(crate) dirty_box: Option<([f32; 3], [f32; 3])>,
+    /// `None` keeps the size from the scene.
+    pub(crate) volume_size: Option<(u32, u32, u32)>,
+    /// File, voxel type and byte order, each overriding the scene.
+    pub(crate) volume: Option<PathBuf>,
+    pub(crate) voxel_type: Option<VoxelType>,
+    pub(crate) endian: Option<Endian>,
+    /// Normals kernel and its size, each overriding the scene.
+    pub(crate) normals: Option<NormalKernel>,
+    pub(crate) normals_size: Option<u32>,
+    pub(crate) transfer_function: Option<PathBuf>,
+    /// Entry point and tile budget in milliseconds, each overriding the scene.
+    pub(crate) entry_point: Option<EntryPoint>,
+    pub(crate) tile_budget: Option<f32>,
+    /// `None` keeps the filter from the scene.
+    pub(crate) volume_filter: Option<VolumeFilter>,
+
+    /// `None` picks the default for the kind of context being created.
+    pub(crate) backends: Option<wgpu::Backends>,
+    pub(crate) power_preference: wgpu::PowerPreference,
+    pub(crate) adapter: Option<String>,
+    pub(crate) force_fallback_adapter: bool,
+
+    pub(crate) headless: Option<PathBuf>,
+    pub(crate) samples: u32,
+    pub(crate) recording: Option<Recording>,
+    pub(crate) export_vox: Option<PathBuf>,
+    pub(crate) save_transfer_function: Option<PathBuf>,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Self {
+            window_size: (1280, 720),
+            present_mode: wgpu::PresentMode::Fifo,
+            surface_format: None,
+
+            scene: None,
+            shader_dir: None,
+            render_scale: 1.,
+            target_frame_time: None,
+            min_render_scale: 0.25,
+            max_render_scale: 2.,
+            upscale: None,
+            tonemap: None,
+            exposure: None,
+            white_point: None,
+            auto_exposure: false,
+            post: None,
+            luts: Vec::new(),
+            lut_strength: None,
+            generator: None,
+            animate: None,
+            dirty_box: None,
+            volume_size: None,
+            volume: None,
+            voxel_type: None,
+            endian: None,
+            normals: None,
+            normals_size: None,
+            transfer_function: None,
+            entry_point: None,
+            tile_budget: None,
+            volume_filter: None,
+
+            backends: None,
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            adapter: None,
+            force_fallback_adapter: false,
+
+            headless: None,
+            samples: 1,
+            recording: None,
+            export_vox: None,
+            save_transfer_function: None,
+        }
+    }
+}
+
+impl Args {
+    /// Parses the process arguments, prints the help and exits on `--help`.
+    pub(crate) fn from_env() -> Result<Self, String> {
+        let mut pargs = pico_args::Arguments::from_env();
+        if pargs.contains(["-h", "--help"]) {
+            print!("{}", HELP);
+            std::process::exit(0);
+        }
+        Self::from_arguments(pargs)
+    }
+
+    fn from_arguments(mut pargs: pico_args::Arguments) -> Result<Self, String> {
+        let recording = parse_recording(&mut pargs)?;
+        let args = Self::parse(&mut pargs, recording).map_err(|e| e.to_string())?;
+
+        if args.min_render_scale > args.max_render_scale {
+            return Err(format!(
+                "--min-render-scale {} is above --max-render-scale {}",
+                args.min_render_scale, args.max_render_scale
+            ));
+        }
+
+        let rest = pargs.finish();
+        if !rest.is_empty() {
+            return Err(format!(
+                "Unknown arguments: {:?}, see --help for usage",
+                rest
+            ));
+        }
+        Ok(args)
+    }
+
+    fn parse(
+        pargs: &mut pico_args::Arguments,
+        recording: Option<Recording>,
+    ) -> Result<Self, pico_args::Error> {
+        let default = Self::default();
+        Ok(Self {
+            window_size: pargs
+                .opt_value_from_fn("--window-size", parse_size)?
+                .unwrap_or(default.window_size),
+            present_mode: pargs
+                .opt_value_from_fn("--present-mode", parse_present_mode)?
+                .unwrap_or(default.present_mode),
+            surface_format: pargs.opt_value_from_fn("--surface-format", parse_surface_format)?,
+
+            scene: pargs.opt_value_from_os_str("--scene", to_path)?,
+            shader_dir: pargs.opt_value_from_os_str("--shader-dir", to_path)?,
+            render_scale: pargs
+                .opt_value_from_fn("--render-scale", parse_render_scale)?
+                .unwrap_or(default.render_scale),
+            target_frame_time: pargs
+                .opt_value_from_fn("--target-frame-time", parse_milliseconds)?,
+            min_render_scale: pargs
+                .opt_value_from_fn("--min-render-scale", parse_render_scale)?
+                .unwrap_or(default.min_render_scale),
+            max_render_scale: pargs
+                .opt_value_from_fn("--max-render-scale", parse_render_scale)?
+                .unwrap_or(default.max_render_scale),
+            upscale: pargs.opt_value_from_fn("--upscale", parse_upscaling)?,
+            tonemap: pargs.opt_value_from_fn("--tonemap", parse_tonemap)?,
+            exposure: pargs.opt_value_from_fn("--exposure", parse_exposure)?,
+            white_point: pargs.opt_value_from_fn("--white-point", parse_white_point)?,
+            auto_exposure: pargs.contains("--auto-exposure"),
+            post: pargs.opt_value_from_fn("--post", parse_post_chain)?,
+            luts: pargs.values_from_os_str("--lut", to_path)?,
+            lut_strength: pargs.opt_value_from_fn("--lut-strength", parse_lut_strength)?,
+            generator: pargs.opt_value_from_fn("--generator", parse_generator)?,
+            animate: pargs.opt_value_from_fn("--animate", parse_positive)?,
+            dirty_box: pargs.opt_value_from_fn("--dirty-box", parse_dirty_box)?,
+            volume_size: pargs.opt_value_from_fn("--volume-size", parse_volume_size)?,
+            volume: pargs.opt_value_from_os_str("--volume", to_path)?,
+            voxel_type: pargs.opt_value_from_fn("--voxel-type", parse_voxel_type)?,
+            endian: pargs.opt_value_from_fn("--endian", parse_endian)?,
+            normals: pargs.opt_value_from_fn("--normals", parse_normal_kernel)?,
+            normals_size: pargs.opt_value_from_fn("--normals-size", parse_normals_size)?,
+            transfer_function: pargs.opt_value_from_os_str("--transfer-function", to_path)?,
+            entry_point: pargs.opt_value_from_fn("--entry-point", parse_entry_point)?,
+            tile_budget: pargs.opt_value_from_fn("--tile-budget", parse_milliseconds)?,
+            volume_filter: pargs.opt_value_from_fn("--volume-filter", parse_volume_filter)?,
+
+            backends: pargs.opt_value_from_fn("--backend", parse_backend)?,
+            power_preference: pargs
+                .opt_value_from_fn("--power", parse_power_preference)?
+                .unwrap_or(default.power_preference),
+            adapter: pargs.opt_value_from_str("--adapter")?,
+            force_fallback_adapter: pargs.contains("--fallback-adapter"),
+
+            headless: pargs.opt_value_from_os_str("--headless", to_path)?,
+            samples: pargs
+                .opt_value_from_fn("--samples", parse_positive)?
+                .unwrap_or(default.samples),
+            recording,
+            export_vox: pargs.opt_value_from_os_str("--export-vox", to_path)?,
+            save_transfer_function: pargs
+                .opt_value_from_os_str("--save-transfer-function", to_path)?,
+        })
+    }
+}
+
+fn parse_recording(pargs: &mut pico_args::Arguments) -> Result<Option<Recording>, String> {
+    let parse_err = |e: pico_args::Error| e.to_string();
+    let dir: Option<PathBuf> = pargs
+        .opt_value_from_os_str("--record", to_path)
+        .map_err(parse_err)?;
+    let fps = pargs
+        .opt_value_from_fn("--fps", parse_fps)
+        .map_err(parse_err)?;
+    let frames = pargs
+        .opt_value_from_fn("--frames", parse_positive)
+        .map_err(parse_err)?;
+    match dir {
+        Some(dir) => Ok(Some(Recording {
+            dir,
+            fps: fps.unwrap_or(60.),
+            frames: frames.unwrap_or(60),
+        })),
+        None if fps.is_some() || frames.is_some() => {
+            Err("--fps and --frames only make sense together with --record".into())
+        }
+        None => Ok(None),
+    }
+}
+
+fn to_path(s: &std::ffi::OsStr) -> Result<PathBuf, &'static str> {
+    Ok(s.into())
+}
+
+fn parse_positive(s: &str) -> Result<u32, String> {
+    match s.parse() {
+        Ok(0) | Err(_) => Err(format!("expected a positive integer, got `{}`", s)),
+        Ok(n) => Ok(n),
+    }
+}
+
+fn parse_fps(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(fps) if fps.is_finite() && fps > 0. => Ok(fps),
+        _ => Err(format!("expected a positive frame rate, got `{}`", s)),
+    }
+}
+
+fn parse_size(s: &str) -> Result<(u32, u32), String> {
+    let (width, height) = s
+        .split_once('x')
+        .ok_or(format!("expected WIDTHxHEIGHT, got `{}`", s))?;
+    Ok((parse_positive(width)?, parse_positive(height)?))
+}
+
+fn parse_volume_size(s: &str) -> Result<(u32, u32, u32), String> {
+    let dims = s
+        .split('x')
+        .map(parse_positive)
+        .collect::<Result<Vec<_>, _>>()?;
+    match dims[..] {
+        [n] => Ok((n, n, n)),
+        [width, height, depth] => Ok((width, height, depth)),
+        _ => Err(format!("expected N or WIDTHxHEIGHTxDEPTH, got `{}`", s)),
+    }
+}
+
+fn parse_dirty_box(s: &str) -> Result<([f32; 3], [f32; 3]), String> {
+    let err = || {
+        format!(
+            "expected x0,y0,z0,x1,y1,z1 with 0 <= min < max <= 1, got `{}`",
+            s
+        )
+    };
+    let values = s
+        .split(',')
+        .map(|v| v.trim().parse::<f32>().map_err(|_| err()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let [x0, y0, z0, x1, y1, z1] = values[..] else {
+        return Err(err());
+    };
+    let (min, max) = ([x0, y0, z0], [x1, y1, z1]);
+    if !(0..3).all(|i| 0. <= min[i] && min[i] < max[i] && max[i] <= 1.) {
+        return Err(err());
+    }
+    Ok((min, max))
+}
+
+fn parse_render_scale(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(scale) if (0.25..=4.).contains(&scale) => Ok(scale),
+        _ => Err(format!("expected a scale between 0.25 and 4, got `{}`", s)),
+    }
+}
+
+fn parse_upscaling(s: &str) -> Result<Upscaling, String> {
+    Ok(match s {
+        "nearest" => Upscaling::Nearest,
+        "bilinear" => Upscaling::Bilinear,
+        "quadratic" => Upscaling::Quadratic,
+        "bicubic" => Upscaling::Bicubic,
+        "lanczos" => Upscaling::Lanczos,
+        "cas" => Upscaling::Cas,
+        _ => return Err(format!("unknown upscaling filter `{}`", s)),
+    })
+}
+
+fn parse_lut_strength(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(strength) if (0. ..=1.).contains(&strength) => Ok(strength),
+        _ => Err(format!("expected a strength between 0 and 1, got `{}`", s)),
+    }
+}
+
+fn parse_post_chain(s: &str) -> Result<Vec<PostEffect>, String> {
+    if s == "none" {
+        return Ok(Vec::new());
+    }
+    s.split(',')
+        .map(|name| match name.trim() {
+            "vignette" => Ok(PostEffect::Vignette),
+            "film-grain" => Ok(PostEffect::FilmGrain),
+            "chromatic-aberration" => Ok(PostEffect::ChromaticAberration),
+            "bloom" => Ok(PostEffect::Bloom),
+            "lut" => Ok(PostEffect::Lut),
+            name => Err(format!("unknown post effect `{}`", name)),
+        })
+        .collect()
+}
+
+fn parse_tonemap(s: &str) -> Result<ToneMapping, String> {
+    Ok(match s {
+        "aces" => ToneMapping::Aces,
+        "none" => ToneMapping::None,
+        "reinhard" => ToneMapping::Reinhard,
+        "reinhard-extended" => ToneMapping::ReinhardExtended,
+        "uncharted2" => ToneMapping::Uncharted2,
+        "agx" => ToneMapping::Agx,
+        "neutral" => ToneMapping::Neutral,
+        _ => return Err(format!("unknown tone mapping operator `{}`", s)),
+    })
+}
+
+fn parse_exposure(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(ev) if ev.is_finite() => Ok(ev),
+        _ => Err(format!("expected an exposure in stops, got `{}`", s)),
+    }
+}
+
+fn parse_white_point(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(white) if white.is_finite() && white > 0. => Ok(white),
+        _ => Err(format!("expected a positive white point, got `{}`", s)),
+    }
+}
+
+fn parse_milliseconds(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(ms) if ms.is_finite() && ms > 0. => Ok(ms),
+        _ => Err(format!(
+            "expected a positive number of milliseconds, got `{}`",
+            s
+        )),
+    }
+}
+
+fn parse_entry_point(s: &str) -> Result<EntryPoint, String> {
+    Ok(match s {
+        "single" => EntryPoint::Single,
+        "tile" => EntryPoint::Tile,
+        _ => return Err(format!("expected `single` or `tile`, got `{}`", s)),
+    })
+}
+
+fn parse_volume_filter(s: &str) -> Result<VolumeFilter, String> {
+    Ok(match s {
+        "nearest" => VolumeFilter::Nearest,
+        "trilinear" => VolumeFilter::Trilinear,
+        "tricubic" => VolumeFilter::Tricubic,
+        _ => {
+            return Err(format!(
+                "expected `nearest`, `trilinear` or `tricubic`, got `{}`",
+                s
+            ))
+        }
+    })
+}
+
+fn parse_generator(s: &str) -> Result<Generator, String> {
+    Ok(match s {
+        "noise" => Generator::Noise,
+        "xor" => Generator::Xor,
+        "mandelbulb" => Generator::Mandelbulb,
+        "menger" => Generator::Menger,
+        "gyroid" => Generator::Gyroid,
+        _ => {
+            return Err(format!(
+                "expected `noise`, `xor`, `mandelbulb`, `menger` or `gyroid`, got `{}`",
+                s
+            ))
+        }
+    })
+}
+
+fn parse_normal_kernel(s: &str) -> Result<NormalKernel, String> {
+    Ok(match s {
+        "central" => NormalKernel::Central,
+        "sobel" => NormalKernel::Sobel,
+        _ => return Err(format!("expected `central` or `sobel`, got `{}`", s)),
+    })
+}
+
+fn parse_normals_size(s: &str) -> Result<u32, String> {
+    match s {
+        "3" => Ok(3),
+        "5" => Ok(5),
+        _ => Err(format!("expected 3 or 5, got `{}`", s)),
+    }
+}
+
+fn parse_voxel_type(s: &str) -> Result<VoxelType, String> {
+    Ok(match s {
+        "u8" => VoxelType::U8,
+        "i8" => VoxelType::I8,
+        "u16" => VoxelType::U16,
+        "i16" => VoxelType::I16,
+        "u32" => VoxelType::U32,
+        "i32" => VoxelType::I32,
+        "f32" => VoxelType::F32,
+        "f64" => VoxelType::F64,
+        _ => return Err(format!("unknown voxel type `{}`", s)),
+    })
+}
+
+fn parse_endian(s: &str) -> Result<Endian, String> {
+    Ok(match s {
+        "little" => Endian::Little,
+        "big" => Endian::Big,
+        _ => return Err(format!("expected `little` or `big`, got `{}`", s)),
+    })
+}
+
+fn parse_present_mode(s: &str) -> Result<wgpu::PresentMode, String> {
+    Ok(match s {
+        "fifo" => wgpu::PresentMode::Fifo,
+        "fifo-relaxed" => wgpu::PresentMode::FifoRelaxed,
+        "mailbox" => wgpu::PresentMode::Mailbox,
+        "immediate" => wgpu::PresentMode::Immediate,
+        "auto-vsync" => wgpu::PresentMode::AutoVsync,
+        "auto-no-vsync" => wgpu::PresentMode::AutoNoVsync,
+        _ => return Err(format!("unknown present mode `{}`", s)),
+    })
+}
+
+fn parse_surface_format(s: &str) -> Result<wgpu::TextureFormat, String> {
+    Ok(match s {
+        "bgra8unorm" => wgpu::TextureFormat::Bgra8Unorm,
+        "rgba8unorm" => wgpu::TextureFormat::Rgba8Unorm,
+        "bgra8unorm-srgb" => wgpu::TextureFormat::Bgra8UnormSrgb,
+        "rgba8unorm-srgb" => wgpu::TextureFormat::Rgba8UnormSrgb,
+        "rgba16float" => wgpu::TextureFormat::Rgba16Float,
+        _ => return Err(format!("unsupported surface format `{}`", s)),
+    })
+}
+
+fn parse_backend(s: &str) -> Result<wgpu::Backends, String> {
+    Ok(match s {
+        "vulkan" => wgpu::Backends::VULKAN,
+        "metal" => wgpu::Backends::METAL,
+        "dx12" => wgpu::Backends::DX12,
+        "gl" => wgpu::Backends::GL,
+        "primary" => wgpu::Backends::PRIMARY,
+        "all" => wgpu::Backends::all(),
+        _ => return Err(format!("unknown backend `{}`", s)),
+    })
+}
+
+fn parse_power_preference(s: &str) -> Result<wgpu::PowerPreference, String> {
+    Ok(match s {
+        "high" => wgpu::PowerPreference::HighPerformance,
+        "low" => wgpu::PowerPreference::LowPower,
+        _ => return Err(format!("expected `high` or `low`, got `{}`", s)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Args, String> {
+        Args::from_arguments(pico_args::Arguments::from_vec(
+            args.iter().map(Into::into).collect(),
+        ))
+    }
+
+    #[test]
+    fn positive_integers() {
+        assert_eq!(parse_positive("1"), Ok(1));
+        assert_eq!(parse_positive("4096"), Ok(4096));
+        for s in ["0", "-1", "1.5", "", "ten", "99999999999"] {
+            assert!(parse_positive(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn window_sizes() {
+        assert_eq!(parse_size("1280x720"), Ok((1280, 720)));
+        for s in ["1280", "1280x", "0x720", "1280x720x1", "1280*720"] {
+            assert!(parse_size(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn volume_sizes() {
+        assert_eq!(parse_volume_size("64"), Ok((64, 64, 64)));
+        assert_eq!(parse_volume_size("32x64x128"), Ok((32, 64, 128)));
+        for s in ["32x64", "32x64x128x1", "0", "32x0x32", "x"] {
+            assert!(parse_volume_size(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn dirty_boxes() {
+        assert_eq!(
+            parse_dirty_box("0, 0.25, 0.5, 1, 0.75, 0.75"),
+            Ok(([0., 0.25, 0.5], [1., 0.75, 0.75]))
+        );
+        for s in [
+            "0,0,0,1,1",
+            "0,0,0,1,1,1,1",
+            "0.5,0,0,0.5,1,1",
+            "0.6,0,0,0.5,1,1",
+            "-0.1,0,0,1,1,1",
+            "0,0,0,1,1,1.5",
+            "0,0,0,1,1,nan",
+            "0,0,zero,1,1,1",
+        ] {
+            assert!(parse_dirty_box(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn render_scales() {
+        assert_eq!(parse_render_scale("0.25"), Ok(0.25));
+        assert_eq!(parse_render_scale("4"), Ok(4.));
+        for s in ["0.2", "4.5", "0", "-1", "nan", "inf", "half"] {
+            assert!(parse_render_scale(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn post_chains() {
+        assert_eq!(
+            parse_post_chain("bloom, lut,vignette"),
+            Ok(vec![
+                PostEffect::Bloom,
+                PostEffect::Lut,
+                PostEffect::Vignette
+            ])
+        );
+        assert_eq!(parse_post_chain("none"), Ok(Vec::new()));
+        assert!(parse_post_chain("film_grain").is_err());
+    }
+
+    #[test]
+    fn render_scale_bounds_must_be_ordered() {
+        let args = parse_args(&["--min-render-scale", "0.5", "--max-render-scale", "0.5"]).unwrap();
+        assert_eq!((args.min_render_scale, args.max_render_scale), (0.5, 0.5));
+
+        let err = parse_args(&["--min-render-scale", "2", "--max-render-scale", "1"]).unwrap_err();
+        assert!(err.contains("--min-render-scale"), "{}", err);
+        // The default maximum of 2 applies when only the minimum is given.
+        assert!(parse_args(&["--min-render-scale", "3"]).is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_arguments() {
+        let args = parse_args(&[]).unwrap();
+        assert_eq!(args.window_size, Args::default().window_size);
+        assert!(parse_args(&["--window-size", "0x0"]).is_err());
+        assert!(parse_args(&["--fps", "30"]).is_err());
+        assert!(parse_args(&["--no-such-flag"]).is_err());
+    }
+}
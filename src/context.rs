@@ -5,7 +5,11 @@ pub(crate) use global_ubo::GlobalUniformBinding;
 pub(crate) use global_ubo::Uniform;
 pub(crate) use hdr_backbuffer::HdrBackBuffer;
 
+use crate::cli::Args;
+use crate::pipelines::auto_exposure::AutoExposure;
+use crate::pipelines::post::PostChain;
 use crate::pipelines::present::PresentPipeline;
+use crate::utils::capture::{self, Capture};
 use crate::utils::frame_counter::FrameCounter;
 use crate::utils::input::Input;
 use crate::{Camera, CameraBinding};
@@ -13,40 +17,65 @@ use crate::{Camera, CameraBinding};
 use wgpu::StoreOp;
 use winit::{dpi::PhysicalSize, window::Window};
 
-use std::{sync::Arc, time::Instant};
+use std::{path::Path, sync::Arc, time::Instant};
+
+/// Source of the time fed into [`Uniform`].
+pub(crate) enum Timeline {
+    /// Wall clock time since the start.
+    Realtime(Instant),
+    /// Advances by exactly `step` seconds per frame so renders are reproducible.
+    Fixed { step: f32, frame: u32 },
+}
 
 pub(crate) struct Context {
     #[allow(dead_code)]
     adapter: wgpu::Adapter,
     pub(crate) device: Arc<wgpu::Device>,
     pub(crate) queue: wgpu::Queue,
-    surface: wgpu::Surface<'static>,
+    /// `None` when rendering headless, frames go to `offscreen_texture` instead.
+    surface: Option<wgpu::Surface<'static>>,
     pub(crate) surface_config: wgpu::SurfaceConfiguration,
+    offscreen_texture: Option<wgpu::Texture>,
     pub(crate) camera: Camera,
     pub(crate) camera_binding: CameraBinding,
+    /// Whether the last [`Context::update`] uploaded a moved camera.
+    pub(crate) camera_changed: bool,
 
+    /// Follows the window size times `render_scale`.
     pub(crate) render_backbuffer: HdrBackBuffer,
+    render_scale: f32,
 
-    rgb_texture: wgpu::Texture,
+    /// Second present target that captures read back, `None` when headless
+    /// since the offscreen texture already holds the encoded frame.
+    rgb_texture: Option<wgpu::Texture>,
 
     pub(crate) width: u32,
     pub(crate) height: u32,
 
-    timeline: Instant,
+    timeline: Timeline,
 
     pub(crate) global_uniform: Uniform,
     pub(crate) global_uniform_binding: GlobalUniformBinding,
 
-    present_pipeline: PresentPipeline,
+    /// Effects between the backbuffer and the present pass.
+    pub(crate) post_chain: PostChain,
+    pub(crate) present_pipeline: PresentPipeline,
+    /// Only runs when the present params ask for it.
+    pub(crate) auto_exposure: AutoExposure,
 }
 
 impl Context {
     /// Create a new window with a given `window`
-    pub(crate) async fn new(window: Arc<Window>, camera: Camera) -> Result<Self, String> {
+    pub(crate) async fn new(
+        window: Arc<Window>,
+        camera: Camera,
+        args: &Args,
+    ) -> Result<Self, String> {
         // Create new instance using first-tier backend of WGPU
         // One of Vulkan + Metal + DX12 + Browser WebGPU
+        let backends = args.backends.unwrap_or(wgpu::Backends::PRIMARY);
         let instance_desc = wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::PRIMARY,
+            backends,
             ..Default::default()
         };
         let instance = wgpu::Instance::new(instance_desc);
@@ -56,19 +85,69 @@ impl Context {
         let surface = instance.create_surface(window.clone()).unwrap();
 
         // Get a handle to a physical device
-        let adapter: wgpu::Adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::HighPerformance,
-                force_fallback_adapter: false,
-                compatible_surface: Some(&surface),
-            })
-            .await
-            .ok_or("Failed to create device adapter.".to_string())?;
+        let adapter = request_adapter(&instance, backends, args, Some(&surface)).await?;
+
+        let PhysicalSize { width, height } = window.inner_size();
+        Self::with_adapter(adapter, Some(surface), width, height, camera, args).await
+    }
 
+    /// Create a context without a window, frames are rendered into an offscreen texture.
+    ///
+    /// Falls back to a software adapter when there is no GPU around.
+    pub(crate) async fn new_headless(camera: Camera, args: &Args) -> Result<Self, String> {
+        let backends = args.backends.unwrap_or(wgpu::Backends::all());
+        let instance_desc = wgpu::InstanceDescriptor {
+            backends,
+            ..Default::default()
+        };
+        let instance = wgpu::Instance::new(instance_desc);
+
+        let adapter = match request_adapter(&instance, backends, args, None).await {
+            Ok(adapter) => adapter,
+            Err(_) if args.adapter.is_none() => {
+                let args = Args {
+                    force_fallback_adapter: true,
+                    ..args.clone()
+                };
+                request_adapter(&instance, backends, &args, None).await?
+            }
+            Err(err) => return Err(err),
+        };
+        eprintln!("Headless adapter: {:?}", adapter.get_info());
+
+        let (width, height) = args.window_size;
+        Self::with_adapter(adapter, None, width, height, camera, args).await
+    }
+
+    async fn with_adapter(
+        adapter: wgpu::Adapter,
+        surface: Option<wgpu::Surface<'static>>,
+        width: u32,
+        height: u32,
+        camera: Camera,
+        args: &Args,
+    ) -> Result<Self, String> {
         // Use default features and limits for your machine
         let features = adapter.features();
         let limits = adapter.limits();
-        let surface_format = wgpu::TextureFormat::Bgra8Unorm;
+        let surface_format = match &surface {
+            Some(surface) => negotiate_surface_format(surface, &adapter, args)?,
+            // Captures read the offscreen texture back, so it gets the encoded LDR frame.
+            None => wgpu::TextureFormat::Rgba8Unorm,
+        };
+        if let Some(surface) = &surface {
+            let caps = surface.get_capabilities(&adapter);
+            let is_auto = matches!(
+                args.present_mode,
+                wgpu::PresentMode::AutoVsync | wgpu::PresentMode::AutoNoVsync
+            );
+            if !is_auto && !caps.present_modes.contains(&args.present_mode) {
+                return Err(format!(
+                    "Present mode {:?} is not supported, available: {:?}",
+                    args.present_mode, caps.present_modes
+                ));
+            }
+        }
 
         // Create the logical device and command queue
         let (device, queue) = adapter
@@ -84,39 +163,70 @@ impl Context {
             .map_err(|e| e.to_string())?;
         let device = Arc::new(device);
 
-        let PhysicalSize { width, height } = window.inner_size();
         let surface_config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             format: surface_format,
             width,
             height,
-            present_mode: wgpu::PresentMode::Fifo,
+            present_mode: args.present_mode,
             desired_maximum_frame_latency: 2,
             alpha_mode: wgpu::CompositeAlphaMode::Auto,
             view_formats: vec![],
         };
-        surface.configure(&device, &surface_config);
+        let offscreen_texture = match &surface {
+            Some(surface) => {
+                surface.configure(&device, &surface_config);
+                None
+            }
+            None => Some(create_offscreen_framebuffer(&device, &surface_config)),
+        };
 
-        let render_backbuffer = HdrBackBuffer::new(&device, HdrBackBuffer::DEFAULT_RESOLUTION);
-        let rgb_texture = create_rgb_framebuffer(&device, &surface_config);
+        let render_scale = fit_render_scale(&device, width, height, args.render_scale);
+        let render_backbuffer =
+            HdrBackBuffer::new(&device, scaled_size(&device, width, height, render_scale));
+        let rgb_texture = surface
+            .as_ref()
+            .map(|_| create_rgb_framebuffer(&device, &surface_config));
+        let post_chain = PostChain::new(
+            &device,
+            &queue,
+            wgpu::include_wgsl!("../shaders/post.wgsl"),
+            wgpu::include_wgsl!("../shaders/bloom.wgsl"),
+            render_backbuffer.size(),
+        );
 
         let present_shader = wgpu::include_wgsl!("../shaders/present.wgsl");
-        let present_pipeline = PresentPipeline::new(&device, surface_format, present_shader);
+        let auto_exposure = AutoExposure::new(
+            &device,
+            wgpu::include_wgsl!("../shaders/auto_exposure.wgsl"),
+        );
+        let present_pipeline = PresentPipeline::new(
+            &device,
+            &queue,
+            surface_format,
+            rgb_texture.is_some(),
+            present_shader,
+            &auto_exposure,
+        );
 
         Ok(Self {
             camera,
             camera_binding: CameraBinding::new(&device),
+            camera_changed: true,
 
             rgb_texture,
 
             render_backbuffer,
+            render_scale,
 
             width,
             height,
 
-            timeline: Instant::now(),
+            timeline: Timeline::Realtime(Instant::now()),
 
+            post_chain,
             present_pipeline,
+            auto_exposure,
 
             global_uniform: Uniform::default(),
             global_uniform_binding: GlobalUniformBinding::new(&device),
@@ -126,83 +236,317 @@ impl Context {
             queue,
             surface,
             surface_config,
+            offscreen_texture,
         })
     }
 
+    /// Switches to a fixed timestep of `1 / fps` seconds, starting again from zero.
+    pub(crate) fn set_fixed_timestep(&mut self, fps: f32) {
+        self.timeline = Timeline::Fixed {
+            step: 1. / fps,
+            frame: 0,
+        };
+    }
+
     pub(crate) fn update(&mut self, frame_counter: &FrameCounter, input: &Input) {
-        self.global_uniform.time = self.timeline.elapsed().as_secs_f32();
-        self.global_uniform.time_delta = frame_counter.time_delta();
-        self.global_uniform.frame = frame_counter.frame_count;
+        match &mut self.timeline {
+            Timeline::Realtime(start) => {
+                self.global_uniform.time = start.elapsed().as_secs_f32();
+                self.global_uniform.time_delta = frame_counter.time_delta();
+                self.global_uniform.frame = frame_counter.frame_count;
+            }
+            Timeline::Fixed { step, frame } => {
+                self.global_uniform.time = *frame as f32 * *step;
+                self.global_uniform.time_delta = *step;
+                self.global_uniform.frame = *frame;
+                *frame += 1;
+            }
+        }
         self.global_uniform.resolution = [self.width as _, self.height as _];
         input.process_position(&mut self.global_uniform);
 
         self.global_uniform_binding
             .update(&self.queue, &self.global_uniform);
 
-        self.camera_binding.update(&self.queue, &mut self.camera);
+        self.camera_changed = self.camera_binding.update(&self.queue, &mut self.camera);
     }
 
-    pub(crate) fn resize(&mut self, width: u32, height: u32) {
+    /// Returns whether the backbuffer was recreated, which drops the accumulated frames.
+    pub(crate) fn resize(&mut self, width: u32, height: u32) -> bool {
         self.width = width;
         self.height = height;
         self.surface_config.height = height;
         self.surface_config.width = width;
-        self.surface.configure(&self.device, &self.surface_config);
-
-        self.rgb_texture = create_rgb_framebuffer(&self.device, &self.surface_config);
+        match &self.surface {
+            Some(surface) => {
+                surface.configure(&self.device, &self.surface_config);
+                self.rgb_texture = Some(create_rgb_framebuffer(&self.device, &self.surface_config));
+            }
+            None => {
+                self.offscreen_texture = Some(create_offscreen_framebuffer(
+                    &self.device,
+                    &self.surface_config,
+                ))
+            }
+        }
+        let reallocated = self.fit_backbuffer();
 
         self.camera.set_aspect(width, height);
+        reallocated
+    }
+
+    pub(crate) fn render_scale(&self) -> f32 {
+        self.render_scale
+    }
+
+    /// Changes the backbuffer size relative to the window, recreating it if needed.
+    /// The scale is lowered when the accumulation buffer would not fit the device.
+    pub(crate) fn set_render_scale(&mut self, scale: f32) {
+        self.render_scale = fit_render_scale(&self.device, self.width, self.height, scale);
+        self.fit_backbuffer();
+    }
+
+    fn fit_backbuffer(&mut self) -> bool {
+        let size = scaled_size(&self.device, self.width, self.height, self.render_scale);
+        if self.render_backbuffer.size() == size {
+            return false;
+        }
+        self.render_backbuffer = HdrBackBuffer::new(&self.device, size);
+        self.post_chain.resize(&self.device, &self.queue, size);
+        true
     }
 
     pub(crate) fn render(&self) -> Result<(), wgpu::SurfaceError> {
-        let frame = self.surface.get_current_texture()?;
+        let Some(surface) = &self.surface else {
+            self.render_offscreen();
+            return Ok(());
+        };
+        let frame = surface.get_current_texture()?;
         let frame_view = frame.texture.create_view(&Default::default());
 
+        self.present(&frame_view);
+
+        frame.present();
+
+        Ok(())
+    }
+
+    /// Runs the present pass into the offscreen texture of a headless context.
+    pub(crate) fn render_offscreen(&self) {
+        if let Some(texture) = &self.offscreen_texture {
+            self.present(&texture.create_view(&Default::default()));
+        }
+    }
+
+    fn present(&self, frame_view: &wgpu::TextureView) {
         let mut encoder = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                 label: Some("Present Encoder"),
             });
 
-        let rgb = self.rgb_texture.create_view(&Default::default());
-        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            label: Some("Present Pass"),
-            color_attachments: &[
-                Some(wgpu::RenderPassColorAttachment {
-                    view: &frame_view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                        store: StoreOp::Store,
-                    },
-                }),
+        if self.present_pipeline.params().auto_exposure {
+            self.auto_exposure.record(
+                &mut encoder,
+                &self.global_uniform_binding,
+                &self.render_backbuffer,
+            );
+        }
+        let post_output = self.post_chain.record(
+            &mut encoder,
+            &self.global_uniform_binding,
+            &self.render_backbuffer,
+        );
+
+        let rgb = self
+            .rgb_texture
+            .as_ref()
+            .map(|texture| texture.create_view(&Default::default()));
+        let color_attachments: Vec<_> = std::iter::once(frame_view)
+            .chain(rgb.as_ref())
+            .map(|view| {
                 Some(wgpu::RenderPassColorAttachment {
-                    view: &rgb,
+                    view,
                     resolve_target: None,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
                         store: StoreOp::Store,
                     },
-                }),
-            ],
+                })
+            })
+            .collect();
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Present Pass"),
+            color_attachments: &color_attachments,
             ..Default::default()
         });
 
-        self.present_pipeline.record(
-            &mut rpass,
-            &self.global_uniform_binding,
-            &self.render_backbuffer.render_bind_group,
-        );
+        self.present_pipeline
+            .record(&mut rpass, &self.global_uniform_binding, post_output);
         drop(rpass);
 
         self.queue.submit(Some(encoder.finish()));
+    }
 
-        frame.present();
+    /// The texture holding the sRGB encoded frame after the present pass.
+    fn ldr_texture(&self) -> &wgpu::Texture {
+        self.rgb_texture
+            .as_ref()
+            .or(self.offscreen_texture.as_ref())
+            .expect("Context has either a surface or an offscreen texture")
+    }
 
+    /// Reads back the tone mapped frame.
+    pub(crate) fn capture_rgb(&self) -> Result<Capture, String> {
+        let mut captures =
+            capture::read_textures(&self.device, &self.queue, &[self.ldr_texture()])?;
+        Ok(captures.remove(0))
+    }
+
+    /// Reads back both the tone mapped frame and the raw HDR backbuffer.
+    pub(crate) fn capture_frame(&self) -> Result<(Capture, Capture), String> {
+        let textures = [self.ldr_texture(), &self.render_backbuffer.texture];
+        let mut captures = capture::read_textures(&self.device, &self.queue, &textures)?;
+        let hdr = captures.remove(1);
+        let ldr = captures.remove(0);
+        Ok((ldr, hdr))
+    }
+
+    /// Saves the current frame as a timestamped PNG plus an EXR of the HDR backbuffer.
+    pub(crate) fn save_screenshot(&self, dir: impl AsRef<Path>) -> Result<(), String> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        let (ldr, hdr) = self.capture_frame()?;
+        let stem = format!("screenshot-{}", capture::timestamp());
+        let ldr_path = dir.join(&stem).with_extension("png");
+        let hdr_path = dir.join(&stem).with_extension("exr");
+        ldr.save_png(&ldr_path)?;
+        hdr.save_exr(&hdr_path)?;
+        eprintln!(
+            "Saved screenshot to {} and {}",
+            ldr_path.display(),
+            hdr_path.display()
+        );
         Ok(())
     }
 }
 
+/// Picks an adapter by name if one was requested, or lets wgpu choose otherwise.
+async fn request_adapter(
+    instance: &wgpu::Instance,
+    backends: wgpu::Backends,
+    args: &Args,
+    surface: Option<&wgpu::Surface<'static>>,
+) -> Result<wgpu::Adapter, String> {
+    if let Some(name) = &args.adapter {
+        let adapters = instance.enumerate_adapters(backends);
+        let names: Vec<_> = adapters.iter().map(|a| a.get_info().name).collect();
+        return adapters
+            .into_iter()
+            .filter(|adapter| surface.map_or(true, |s| adapter.is_surface_supported(s)))
+            .find(|adapter| adapter.get_info().name.contains(name.as_str()))
+            .ok_or(format!(
+                "No adapter matching `{}`, available: {:?}",
+                name, names
+            ));
+    }
+
+    instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: args.power_preference,
+            force_fallback_adapter: args.force_fallback_adapter,
+            compatible_surface: surface,
+        })
+        .await
+        .ok_or("Failed to create device adapter.".to_string())
+}
+
+/// The requested format if the surface supports it, otherwise the best one it offers:
+/// a float format for HDR, then an sRGB one, then whatever comes first.
+fn negotiate_surface_format(
+    surface: &wgpu::Surface<'static>,
+    adapter: &wgpu::Adapter,
+    args: &Args,
+) -> Result<wgpu::TextureFormat, String> {
+    let formats = surface.get_capabilities(adapter).formats;
+    let format = match args.surface_format {
+        Some(format) if formats.contains(&format) => format,
+        Some(format) => {
+            return Err(format!(
+                "Surface format {:?} is not supported, available: {:?}",
+                format, formats
+            ))
+        }
+        None => formats
+            .iter()
+            .find(|&&format| format == wgpu::TextureFormat::Rgba16Float)
+            .or_else(|| formats.iter().find(|format| format.is_srgb()))
+            .or(formats.first())
+            .copied()
+            .ok_or("Surface supports no formats")?,
+    };
+    eprintln!("Surface format: {:?}", format);
+    Ok(format)
+}
+
+/// Lowers `scale` until the accumulation buffer of the scaled window fits the device limits.
+fn fit_render_scale(device: &wgpu::Device, width: u32, height: u32, scale: f32) -> f32 {
+    let max_pixels = HdrBackBuffer::max_pixels(&device.limits()) as f64;
+    let pixels = f64::from(width) * f64::from(height) * f64::from(scale).powi(2);
+    if pixels <= max_pixels {
+        return scale;
+    }
+    // Rounds down to the 1/32 steps of the dynamic resolution.
+    let fit = (max_pixels / pixels).sqrt() * f64::from(scale);
+    ((fit * 32.).floor().max(1.) / 32.) as f32
+}
+
+/// Size of the backbuffer for a `width` by `height` window.
+fn scaled_size(device: &wgpu::Device, width: u32, height: u32, scale: f32) -> (u32, u32) {
+    let limits = device.limits();
+    let max = limits.max_texture_dimension_2d;
+    let scale = |len: u32| ((len as f32 * scale).round() as u32).clamp(1, max);
+    fit_pixels(
+        (scale(width), scale(height)),
+        HdrBackBuffer::max_pixels(&limits),
+    )
+}
+
+/// Shrinks both sides by the same factor until the size has at most `max_pixels` pixels.
+fn fit_pixels((width, height): (u32, u32), max_pixels: u64) -> (u32, u32) {
+    let pixels = u64::from(width) * u64::from(height);
+    if pixels <= max_pixels {
+        return (width, height);
+    }
+    let factor = (max_pixels as f64 / pixels as f64).sqrt();
+    let shrink = |len: u32| ((f64::from(len) * factor).floor() as u32).max(1);
+    let (width, height) = (shrink(width), shrink(height));
+    // A side stuck at one pixel leaves the other one to absorb the rest.
+    let width = u64::from(width).min(max_pixels / u64::from(height)).max(1) as u32;
+    let height = u64::from(height).min(max_pixels / u64::from(width)).max(1) as u32;
+    (width, height)
+}
+
+fn create_offscreen_framebuffer(
+    device: &wgpu::Device,
+    config: &wgpu::SurfaceConfiguration,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Offscreen Frame Texture"),
+        format: config.format,
+        size: wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    })
+}
+
 fn create_rgb_framebuffer(
     device: &wgpu::Device,
     config: &wgpu::SurfaceConfiguration,
@@ -225,3 +569,28 @@ fn create_rgb_framebuffer(
 
     device.create_texture(multisampled_frame_descriptor)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_within_the_pixel_budget_are_kept() {
+        assert_eq!(fit_pixels((1920, 1080), 1920 * 1080), (1920, 1080));
+        assert_eq!(fit_pixels((1, 1), 1), (1, 1));
+    }
+
+    #[test]
+    fn large_sizes_shrink_evenly_into_the_budget() {
+        assert_eq!(fit_pixels((4000, 2000), 2_000_000), (2000, 1000));
+        let (width, height) = fit_pixels((7680, 4320), 1 << 22);
+        assert!(u64::from(width) * u64::from(height) <= 1 << 22);
+        assert_eq!(width / 16, height / 9);
+    }
+
+    #[test]
+    fn sides_never_shrink_to_zero() {
+        assert_eq!(fit_pixels((100_000, 1), 10), (10, 1));
+        assert_eq!(fit_pixels((1, 100_000), 10), (1, 10));
+    }
+}
@@ -1,31 +1,45 @@
 pub(crate) struct HdrBackBuffer {
+    pub(crate) texture: wgpu::Texture,
     pub(crate) render_bind_group: wgpu::BindGroup,
     pub(crate) storage_bind_group: wgpu::BindGroup,
 }
 
 impl HdrBackBuffer {
     pub(crate) const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
-    pub(crate) const DEFAULT_RESOLUTION: (u32, u32) = (1280, 720);
+    /// Bytes per pixel of the accumulation buffer, one `vec4<f32>`.
+    const ACCUMULATION_STRIDE: u64 = 16;
     pub(crate) const DESC_COMPUTE: wgpu::BindGroupLayoutDescriptor<'static> =
         wgpu::BindGroupLayoutDescriptor {
             label: Some("Storage Texture Layout"),
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                ty: wgpu::BindingType::StorageTexture {
-                    access: wgpu::StorageTextureAccess::WriteOnly,
-                    format: Self::FORMAT,
-                    view_dimension: wgpu::TextureViewDimension::D2,
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: Self::FORMAT,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
                 },
-                count: None,
-            }],
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
         };
     pub(crate) const DESC_RENDER: wgpu::BindGroupLayoutDescriptor<'static> =
         wgpu::BindGroupLayoutDescriptor {
             label: Some("BackBuffer: Render Bind Group Layout"),
             entries: &[wgpu::BindGroupLayoutEntry {
                 binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT.union(wgpu::ShaderStages::COMPUTE),
                 ty: wgpu::BindingType::Texture {
                     sample_type: wgpu::TextureSampleType::Float { filterable: true },
                     view_dimension: wgpu::TextureViewDimension::D2,
@@ -35,8 +49,14 @@ impl HdrBackBuffer {
             }],
         };
 
+    /// Largest pixel count whose accumulation buffer the device can create and bind.
+    pub(crate) fn max_pixels(limits: &wgpu::Limits) -> u64 {
+        u64::from(limits.max_storage_buffer_binding_size).min(limits.max_buffer_size)
+            / Self::ACCUMULATION_STRIDE
+    }
+
     pub(crate) fn new(device: &wgpu::Device, (width, height): (u32, u32)) -> Self {
-        let texture_view = {
+        let (texture, texture_view) = {
             let size = wgpu::Extent3d {
                 width,
                 height,
@@ -55,33 +75,53 @@ impl HdrBackBuffer {
                     | wgpu::TextureUsages::COPY_SRC,
                 view_formats: &[],
             });
-            texture.create_view(&Default::default())
+            let view = texture.create_view(&Default::default());
+            (texture, view)
         };
 
-        let binding_resource = &[wgpu::BindGroupEntry {
+        // Running average of the jittered frames, one `vec4<f32>` per pixel.
+        let accumulation = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("BackBuffer: Accumulation Buffer"),
+            size: u64::from(width) * u64::from(height) * Self::ACCUMULATION_STRIDE,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let binding_resource = wgpu::BindGroupEntry {
             binding: 0,
             resource: wgpu::BindingResource::TextureView(&texture_view),
-        }];
+        };
         let render_bind_group = {
             let render_bind_group_layout = device.create_bind_group_layout(&Self::DESC_RENDER);
             device.create_bind_group(&wgpu::BindGroupDescriptor {
                 label: Some("BackBuffer: Render Bind Group"),
                 layout: &render_bind_group_layout,
-                entries: binding_resource,
+                entries: &[binding_resource.clone()],
             })
         };
         let storage_bind_group = {
             let storage_bind_group_layout = device.create_bind_group_layout(&Self::DESC_COMPUTE);
             device.create_bind_group(&wgpu::BindGroupDescriptor {
-                label: Some("BackBuffer: Render Bind Group"),
+                label: Some("BackBuffer: Storage Bind Group"),
                 layout: &storage_bind_group_layout,
-                entries: binding_resource,
+                entries: &[
+                    binding_resource,
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: accumulation.as_entire_binding(),
+                    },
+                ],
             })
         };
 
         Self {
+            texture,
             render_bind_group,
             storage_bind_group,
         }
     }
+
+    pub(crate) fn size(&self) -> (u32, u32) {
+        (self.texture.width(), self.texture.height())
+    }
 }
@@ -0,0 +1,253 @@
+pub(crate) mod nifti;
+pub(crate) mod nrrd;
+pub(crate) mod raw;
+pub(crate) mod vox;
+
+use half::f16;
+use serde::Deserialize;
+
+use std::path::Path;
+
+/// Scalar type of the voxels stored in a file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum VoxelType {
+    #[default]
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    F32,
+    F64,
+}
+
+impl VoxelType {
+    pub(crate) const fn size(self) -> usize {
+        match self {
+            Self::U8 | Self::I8 => 1,
+            Self::U16 | Self::I16 => 2,
+            Self::U32 | Self::I32 | Self::F32 => 4,
+            Self::F64 => 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Endian {
+    #[default]
+    Little,
+    Big,
+}
+
+/// How to interpret a headerless `.raw` file.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RawLayout {
+    pub(crate) size: (u32, u32, u32),
+    pub(crate) voxel_type: VoxelType,
+    pub(crate) endian: Endian,
+}
+
+/// Voxels ready to be uploaded into the Rgba16Float volume texture,
+/// x varies fastest, then y, then z.
+pub(crate) struct Volume {
+    pub(crate) size: (u32, u32, u32),
+    pub(crate) voxels: Vec<[f16; 4]>,
+    /// Physical size of a voxel along each axis, only the ratios matter.
+    pub(crate) spacing: [f32; 3],
+}
+
+impl Volume {
+    pub(crate) fn new(size: (u32, u32, u32), voxels: Vec<[f16; 4]>) -> Self {
+        Self {
+            size,
+            voxels,
+            spacing: [1.; 3],
+        }
+    }
+
+    /// Stretches `values` to [0, 1] and stores them in every channel.
+    pub(crate) fn from_scalars(size: (u32, u32, u32), values: &[f32]) -> Self {
+        let (min, max) = values
+            .iter()
+            .filter(|v| v.is_finite())
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), &v| {
+                (min.min(v), max.max(v))
+            });
+        let range = if max > min { max - min } else { 1. };
+        let voxels = values
+            .iter()
+            .map(|&v| {
+                let v = if v.is_finite() {
+                    f16::from_f32(((v - min) / range).clamp(0., 1.))
+                } else {
+                    f16::ZERO
+                };
+                [v; 4]
+            })
+            .collect();
+        Self::new(size, voxels)
+    }
+
+    /// Half extents of the box the volume is drawn in, the longest side spans [-1, 1].
+    pub(crate) fn box_extent(&self) -> [f32; 3] {
+        let (width, height, depth) = self.size;
+        let physical = [
+            width as f32 * self.spacing[0],
+            height as f32 * self.spacing[1],
+            depth as f32 * self.spacing[2],
+        ];
+        let longest = physical.into_iter().fold(0., f32::max);
+        physical.map(|side| side / longest)
+    }
+
+    /// Bytes of the `z`-th slice in the layout of the volume texture.
+    pub(crate) fn slice_bytes(&self, z: u32) -> Vec<u8> {
+        let (width, height, _) = self.size;
+        let len = (width * height) as usize;
+        let start = z as usize * len;
+        self.voxels[start..start + len]
+            .iter()
+            .flatten()
+            .flat_map(|c| c.to_le_bytes())
+            .collect()
+    }
+}
+
+/// Loads a volume, picking the format from the extension of `path`.
+/// `raw` describes files that carry no header, `max_dimension` is the largest
+/// side of a 3D texture on the device.
+pub(crate) fn load(path: &Path, raw: &RawLayout, max_dimension: u32) -> Result<Volume, String> {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    let name = name.strip_suffix(".gz").unwrap_or(&name);
+    let extension = name.rsplit_once('.').map(|(_, ext)| ext);
+    let volume = match extension {
+        Some("raw") => raw::load(path, raw),
+        Some("nrrd") | Some("nhdr") => nrrd::load(path),
+        Some("vox") => vox::load(path, max_dimension),
+        Some("nii") | Some("hdr") => nifti::load(path),
+        _ => Err(
+            "unknown volume format, expected .raw, .nrrd, .nhdr, .vox, .nii, .nii.gz or .hdr"
+                .into(),
+        ),
+    }
+    .map_err(|e| format!("{}: {}", path.display(), e))?;
+    eprintln!(
+        "Loaded {} ({}x{}x{})",
+        path.display(),
+        volume.size.0,
+        volume.size.1,
+        volume.size.2
+    );
+    Ok(volume)
+}
+
+/// Converts tightly packed voxels of `voxel_type` to floats.
+fn decode(bytes: &[u8], voxel_type: VoxelType, endian: Endian) -> Vec<f32> {
+    fn convert<const N: usize>(
+        bytes: &[u8],
+        endian: Endian,
+        f: impl Fn([u8; N]) -> f32,
+    ) -> Vec<f32> {
+        bytes
+            .chunks_exact(N)
+            .map(|chunk| {
+                let mut word: [u8; N] = std::array::from_fn(|i| chunk[i]);
+                if endian == Endian::Big {
+                    word.reverse();
+                }
+                f(word)
+            })
+            .collect()
+    }
+
+    match voxel_type {
+        VoxelType::U8 => convert(bytes, endian, |[b]| b as f32),
+        VoxelType::I8 => convert(bytes, endian, |b| i8::from_le_bytes(b) as f32),
+        VoxelType::U16 => convert(bytes, endian, |b| u16::from_le_bytes(b) as f32),
+        VoxelType::I16 => convert(bytes, endian, |b| i16::from_le_bytes(b) as f32),
+        VoxelType::U32 => convert(bytes, endian, |b| u32::from_le_bytes(b) as f32),
+        VoxelType::I32 => convert(bytes, endian, |b| i32::from_le_bytes(b) as f32),
+        VoxelType::F32 => convert(bytes, endian, f32::from_le_bytes),
+        VoxelType::F64 => convert(bytes, endian, |b| f64::from_le_bytes(b) as f32),
+    }
+}
+
+/// Checks that `bytes` holds exactly the voxels of `size` and decodes them.
+fn decode_exact(
+    bytes: &[u8],
+    size: (u32, u32, u32),
+    voxel_type: VoxelType,
+    endian: Endian,
+) -> Result<Volume, String> {
+    let (width, height, depth) = size;
+    if width == 0 || height == 0 || depth == 0 {
+        return Err(format!("invalid size {}x{}x{}", width, height, depth));
+    }
+    let expected = width as usize * height as usize * depth as usize * voxel_type.size();
+    if bytes.len() != expected {
+        return Err(format!(
+            "expected {} bytes for {}x{}x{} {:?} voxels, found {}",
+            expected,
+            width,
+            height,
+            depth,
+            voxel_type,
+            bytes.len()
+        ));
+    }
+    Ok(Volume::from_scalars(
+        size,
+        &decode(bytes, voxel_type, endian),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_both_byte_orders() {
+        assert_eq!(decode(&[1, 2], VoxelType::U16, Endian::Little), [513.]);
+        assert_eq!(decode(&[1, 2], VoxelType::U16, Endian::Big), [258.]);
+        assert_eq!(decode(&[0xff, 0xfe], VoxelType::I16, Endian::Big), [-2.]);
+        assert_eq!(decode(&[0x80], VoxelType::I8, Endian::Big), [-128.]);
+        let bytes = 1.5f64.to_be_bytes();
+        assert_eq!(decode(&bytes, VoxelType::F64, Endian::Big), [1.5]);
+        let bytes: Vec<u8> = [7u32, 9].iter().flat_map(|v| v.to_le_bytes()).collect();
+        assert_eq!(decode(&bytes, VoxelType::U32, Endian::Little), [7., 9.]);
+    }
+
+    #[test]
+    fn scalars_are_stretched_to_unit_range() {
+        let volume = Volume::from_scalars((4, 1, 1), &[-10., 0., 10., f32::NAN]);
+        let values: Vec<f32> = volume.voxels.iter().map(|v| v[0].to_f32()).collect();
+        assert_eq!(values, [0., 0.5, 1., 0.]);
+        assert!(volume.voxels.iter().all(|v| v.iter().all(|&c| c == v[0])));
+
+        // A constant volume must not divide by zero.
+        let flat = Volume::from_scalars((2, 1, 1), &[3., 3.]);
+        assert!(flat.voxels.iter().all(|v| v[0].to_f32() == 0.));
+    }
+
+    #[test]
+    fn decode_exact_checks_the_length() {
+        assert!(decode_exact(&[0; 8], (2, 2, 2), VoxelType::U8, Endian::Little).is_ok());
+        assert!(decode_exact(&[0; 7], (2, 2, 2), VoxelType::U8, Endian::Little).is_err());
+        assert!(decode_exact(&[0; 8], (2, 2, 2), VoxelType::U16, Endian::Little).is_err());
+        assert!(decode_exact(&[], (0, 2, 2), VoxelType::U8, Endian::Little).is_err());
+    }
+
+    #[test]
+    fn box_extent_keeps_physical_proportions() {
+        let mut volume = Volume::from_scalars((4, 2, 2), &[0.; 16]);
+        assert_eq!(volume.box_extent(), [1., 0.5, 0.5]);
+        volume.spacing = [1., 2., 4.];
+        assert_eq!(volume.box_extent(), [0.5, 0.5, 1.]);
+    }
+}
@@ -0,0 +1,635 @@
+//! NIfTI-1 and NIfTI-2 volumes, single `.nii` files or `.hdr`/`.img` pairs, optionally gzipped.
+//!
+//! Voxels are reoriented from the qform/sform affine so that the texture
+//! axes follow the patient: x to the right, y up (superior) and z to the
+//! back (posterior), which keeps the volume right handed.
+
+use super::{decode, Endian, Volume, VoxelType};
+
+use flate2::read::GzDecoder;
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+const NIFTI1_HEADER_SIZE: usize = 348;
+const NIFTI2_HEADER_SIZE: usize = 540;
+
+pub(crate) fn load(path: &Path) -> Result<Volume, String> {
+    let bytes = read_maybe_gzip(path)?;
+    let header = Header::parse(&bytes)?;
+
+    let data = if header.single_file {
+        let offset = header.vox_offset;
+        bytes
+            .get(offset..)
+            .ok_or(format!("vox_offset {} is past the end of the file", offset))?
+            .to_vec()
+    } else {
+        let image = image_path(path);
+        read_maybe_gzip(&image).map_err(|e| format!("{}: {}", image.display(), e))?
+    };
+
+    let values = header.values(&data)?;
+    let orientation = Orientation::from_affine(&header.affine());
+    let (size, values) = orientation.reorient(header.dims, &values);
+    let mut volume = Volume::from_scalars(size, &values);
+    volume.spacing = orientation.spacing;
+    Ok(volume)
+}
+
+fn read_maybe_gzip(path: &Path) -> Result<Vec<u8>, String> {
+    let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+    if !bytes.starts_with(&[0x1f, 0x8b]) {
+        return Ok(bytes);
+    }
+    let mut inflated = Vec::new();
+    GzDecoder::new(&bytes[..])
+        .read_to_end(&mut inflated)
+        .map_err(|e| format!("gzip: {}", e))?;
+    Ok(inflated)
+}
+
+/// `brain.hdr` keeps its voxels in `brain.img`, `brain.hdr.gz` in `brain.img.gz`.
+fn image_path(header: &Path) -> PathBuf {
+    let name = header
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let image = match name.strip_suffix(".hdr.gz") {
+        Some(stem) => format!("{}.img.gz", stem),
+        None => format!("{}.img", name.trim_end_matches(".hdr")),
+    };
+    header.with_file_name(image)
+}
+
+struct Header {
+    single_file: bool,
+    endian: Endian,
+    dims: [usize; 3],
+    voxel_type: VoxelType,
+    pixdim: [f32; 4],
+    vox_offset: usize,
+    scl_slope: f32,
+    scl_inter: f32,
+    qform_code: i32,
+    sform_code: i32,
+    quatern: [f32; 3],
+    srow: [[f32; 4]; 3],
+}
+
+/// Reads numbers at byte offsets of a header in either byte order.
+struct Fields<'a> {
+    bytes: &'a [u8],
+    endian: Endian,
+}
+
+impl Fields<'_> {
+    fn word<const N: usize>(&self, offset: usize) -> [u8; N] {
+        let mut word: [u8; N] = std::array::from_fn(|i| self.bytes[offset + i]);
+        if self.endian == Endian::Big {
+            word.reverse();
+        }
+        word
+    }
+
+    fn i16(&self, offset: usize) -> i16 {
+        i16::from_le_bytes(self.word(offset))
+    }
+
+    fn i32(&self, offset: usize) -> i32 {
+        i32::from_le_bytes(self.word(offset))
+    }
+
+    fn i64(&self, offset: usize) -> i64 {
+        i64::from_le_bytes(self.word(offset))
+    }
+
+    fn f32(&self, offset: usize) -> f32 {
+        f32::from_le_bytes(self.word(offset))
+    }
+
+    fn f64(&self, offset: usize) -> f32 {
+        f64::from_le_bytes(self.word(offset)) as f32
+    }
+}
+
+impl Header {
+    fn parse(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < NIFTI1_HEADER_SIZE {
+            return Err("file too small for a NIfTI header".into());
+        }
+        let sizeof_hdr = [0, 1, 2, 3].map(|i| bytes[i]);
+        let (endian, version) = match (
+            i32::from_le_bytes(sizeof_hdr) as usize,
+            i32::from_be_bytes(sizeof_hdr) as usize,
+        ) {
+            (NIFTI1_HEADER_SIZE, _) => (Endian::Little, 1),
+            (_, NIFTI1_HEADER_SIZE) => (Endian::Big, 1),
+            (NIFTI2_HEADER_SIZE, _) => (Endian::Little, 2),
+            (_, NIFTI2_HEADER_SIZE) => (Endian::Big, 2),
+            _ => return Err("not a NIfTI-1 or NIfTI-2 header".into()),
+        };
+        if version == 2 && bytes.len() < NIFTI2_HEADER_SIZE {
+            return Err("file too small for a NIfTI-2 header".into());
+        }
+        let f = Fields { bytes, endian };
+
+        let (magic, datatype, dims, pixdim, vox_offset, scl) = if version == 1 {
+            let dims: [i64; 4] = std::array::from_fn(|i| f.i16(40 + 2 * i) as i64);
+            (
+                &bytes[344..347],
+                f.i16(70),
+                dims,
+                std::array::from_fn(|i| f.f32(76 + 4 * i)),
+                f.f32(108) as i64,
+                [f.f32(112), f.f32(116)],
+            )
+        } else {
+            let dims: [i64; 4] = std::array::from_fn(|i| f.i64(16 + 8 * i));
+            (
+                &bytes[4..7],
+                f.i16(12),
+                dims,
+                std::array::from_fn(|i| f.f64(104 + 8 * i)),
+                f.i64(168),
+                [f.f64(176), f.f64(184)],
+            )
+        };
+        let single_file = match magic {
+            b"n+1" | b"n+2" => true,
+            b"ni1" | b"ni2" => false,
+            _ => return Err("missing NIfTI magic".into()),
+        };
+
+        let rank = dims[0];
+        if !(3..=7).contains(&rank) {
+            return Err(format!("expected a 3D volume, got {} dimensions", rank));
+        }
+        let mut size = [0; 3];
+        for (axis, dim) in size.iter_mut().enumerate() {
+            // Texture sizes are u32, larger dimensions could never be shown.
+            *dim = u32::try_from(dims[axis + 1])
+                .ok()
+                .filter(|&d| d > 0)
+                .map(|d| d as usize)
+                .ok_or(format!("invalid dimension {}", dims[axis + 1]))?;
+        }
+
+        let voxel_type = match datatype {
+            2 => VoxelType::U8,
+            4 => VoxelType::I16,
+            8 => VoxelType::I32,
+            16 => VoxelType::F32,
+            64 => VoxelType::F64,
+            256 => VoxelType::I8,
+            512 => VoxelType::U16,
+            768 => VoxelType::U32,
+            _ => return Err(format!("unsupported datatype {}", datatype)),
+        };
+
+        let (qform_code, sform_code, quatern, srow) = if version == 1 {
+            (
+                f.i16(252) as i32,
+                f.i16(254) as i32,
+                std::array::from_fn(|i| f.f32(256 + 4 * i)),
+                std::array::from_fn(|row| std::array::from_fn(|i| f.f32(280 + 16 * row + 4 * i))),
+            )
+        } else {
+            (
+                f.i32(344),
+                f.i32(348),
+                std::array::from_fn(|i| f.f64(352 + 8 * i)),
+                std::array::from_fn(|row| std::array::from_fn(|i| f.f64(400 + 32 * row + 8 * i))),
+            )
+        };
+
+        Ok(Self {
+            single_file,
+            endian,
+            dims: size,
+            voxel_type,
+            pixdim,
+            vox_offset: usize::try_from(vox_offset).map_err(|_| "negative vox_offset")?,
+            scl_slope: scl[0],
+            scl_inter: scl[1],
+            qform_code,
+            sform_code,
+            quatern,
+            srow,
+        })
+    }
+
+    /// Decodes the first volume in `data` and applies the intensity scaling,
+    /// a negative slope inverts the intensities before they get normalized.
+    fn values(&self, data: &[u8]) -> Result<Vec<f32>, String> {
+        let [width, height, depth] = self.dims;
+        // Only the first volume of a time series is shown.
+        let len = width
+            .checked_mul(height)
+            .and_then(|len| len.checked_mul(depth))
+            .and_then(|len| len.checked_mul(self.voxel_type.size()))
+            .ok_or(format!(
+                "volume of {}x{}x{} voxels is too large",
+                width, height, depth
+            ))?;
+        let data = data.get(..len).ok_or(format!(
+            "expected at least {} bytes of voxel data, found {}",
+            len,
+            data.len()
+        ))?;
+        let mut values = decode(data, self.voxel_type, self.endian);
+        // A zero slope means the scaling is unused.
+        if self.scl_slope != 0. && self.scl_slope.is_finite() {
+            for v in &mut values {
+                *v = *v * self.scl_slope + self.scl_inter;
+            }
+        }
+        Ok(values)
+    }
+
+    /// Voxel to world (RAS) matrix, rows are world axes and columns voxel axes.
+    /// The sform wins over the qform, with neither only the spacing is known.
+    fn affine(&self) -> [[f32; 3]; 3] {
+        let spacing = [1, 2, 3].map(|i| {
+            let d = self.pixdim[i].abs();
+            if d.is_finite() && d > 0. {
+                d
+            } else {
+                1.
+            }
+        });
+        if self.sform_code > 0 {
+            return self.srow.map(|row| [row[0], row[1], row[2]]);
+        }
+        if self.qform_code <= 0 {
+            return [
+                [spacing[0], 0., 0.],
+                [0., spacing[1], 0.],
+                [0., 0., spacing[2]],
+            ];
+        }
+
+        let [b, c, d] = self.quatern;
+        let a = (1. - (b * b + c * c + d * d)).max(0.).sqrt();
+        let rotation = [
+            [
+                a * a + b * b - c * c - d * d,
+                2. * (b * c - a * d),
+                2. * (b * d + a * c),
+            ],
+            [
+                2. * (b * c + a * d),
+                a * a + c * c - b * b - d * d,
+                2. * (c * d - a * b),
+            ],
+            [
+                2. * (b * d - a * c),
+                2. * (c * d + a * b),
+                a * a + d * d - c * c - b * b,
+            ],
+        ];
+        let qfac = if self.pixdim[0] < 0. { -1. } else { 1. };
+        let scale = [spacing[0], spacing[1], qfac * spacing[2]];
+        rotation.map(|row| std::array::from_fn(|j| row[j] * scale[j]))
+    }
+}
+
+/// Where each voxel axis of the file ends up in the texture.
+struct Orientation {
+    /// Texture axis fed by each file axis.
+    axis: [usize; 3],
+    /// Whether the file axis runs against the texture axis.
+    flip: [bool; 3],
+    /// Voxel spacing along each texture axis.
+    spacing: [f32; 3],
+}
+
+impl Orientation {
+    fn from_affine(affine: &[[f32; 3]; 3]) -> Self {
+        // World axis (R, A, S) to texture axis and whether it is mirrored.
+        const TEXTURE: [(usize, bool); 3] = [(0, false), (2, true), (1, false)];
+
+        let mut axis = [0; 3];
+        let mut flip = [false; 3];
+        let mut spacing = [1.; 3];
+        let mut taken = [false; 3];
+        // Assign the voxel axes with the most pronounced direction first.
+        let mut order = [0, 1, 2];
+        let dominance = |j: usize| {
+            let column = [0, 1, 2].map(|i| affine[i][j].abs());
+            let norm = column.iter().map(|v| v * v).sum::<f32>().sqrt();
+            column.into_iter().fold(0., f32::max) / norm.max(f32::EPSILON)
+        };
+        order.sort_by(|&a, &b| dominance(b).total_cmp(&dominance(a)));
+
+        for j in order {
+            let world = (0..3)
+                .filter(|&i| !taken[i])
+                .max_by(|&a, &b| affine[a][j].abs().total_cmp(&affine[b][j].abs()))
+                .unwrap_or(j);
+            taken[world] = true;
+
+            let (texture, mirrored) = TEXTURE[world];
+            axis[j] = texture;
+            flip[j] = (affine[world][j] < 0.) != mirrored;
+            let norm = (0..3).map(|i| affine[i][j].powi(2)).sum::<f32>().sqrt();
+            spacing[texture] = if norm.is_finite() && norm > 0. {
+                norm
+            } else {
+                1.
+            };
+        }
+        Self {
+            axis,
+            flip,
+            spacing,
+        }
+    }
+
+    /// Shuffles `values`, laid out along the file axes, into texture order.
+    fn reorient(&self, dims: [usize; 3], values: &[f32]) -> ((u32, u32, u32), Vec<f32>) {
+        let mut size = [0; 3];
+        for j in 0..3 {
+            size[self.axis[j]] = dims[j];
+        }
+        let mut out = vec![0.; values.len()];
+        let mut index = 0;
+        for k in 0..dims[2] {
+            for j in 0..dims[1] {
+                for i in 0..dims[0] {
+                    let mut texel = [0; 3];
+                    for (axis, coord) in [i, j, k].into_iter().enumerate() {
+                        let coord = if self.flip[axis] {
+                            dims[axis] - 1 - coord
+                        } else {
+                            coord
+                        };
+                        texel[self.axis[axis]] = coord;
+                    }
+                    out[(texel[2] * size[1] + texel[1]) * size[0] + texel[0]] = values[index];
+                    index += 1;
+                }
+            }
+        }
+        ((size[0] as u32, size[1] as u32, size[2] as u32), out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::utils::test_dir::TestDir;
+
+    /// Writes header fields at byte offsets, the inverse of [`Fields`].
+    struct Builder {
+        bytes: Vec<u8>,
+        endian: Endian,
+        version: u32,
+    }
+
+    impl Builder {
+        /// A header of `dims` u8 voxels with no spatial transform.
+        fn new(version: u32, endian: Endian, dims: [i64; 3]) -> Self {
+            let size = if version == 1 {
+                NIFTI1_HEADER_SIZE
+            } else {
+                NIFTI2_HEADER_SIZE
+            };
+            // Four extension bytes separate the header from the voxels.
+            let mut header = Self {
+                bytes: vec![0; size + 4],
+                endian,
+                version,
+            };
+            header.put(0, (size as i32).to_le_bytes());
+            header.dims(3, dims);
+            header.datatype(2);
+            if version == 1 {
+                header.bytes[344..348].copy_from_slice(b"n+1\0");
+                header.put(108, ((size + 4) as f32).to_le_bytes());
+            } else {
+                header.bytes[4..12].copy_from_slice(b"n+2\0\r\n\x1a\n");
+                header.put(168, ((size + 4) as i64).to_le_bytes());
+            }
+            header
+        }
+
+        fn put<const N: usize>(&mut self, offset: usize, mut word: [u8; N]) {
+            if self.endian == Endian::Big {
+                word.reverse();
+            }
+            self.bytes[offset..offset + N].copy_from_slice(&word);
+        }
+
+        fn float(&mut self, v1: usize, v2: usize, value: f32) {
+            if self.version == 1 {
+                self.put(v1, value.to_le_bytes());
+            } else {
+                self.put(v2, (value as f64).to_le_bytes());
+            }
+        }
+
+        fn dims(&mut self, rank: i64, dims: [i64; 3]) {
+            for (i, dim) in [rank].into_iter().chain(dims).enumerate() {
+                if self.version == 1 {
+                    self.put(40 + 2 * i, (dim as i16).to_le_bytes());
+                } else {
+                    self.put(16 + 8 * i, dim.to_le_bytes());
+                }
+            }
+        }
+
+        fn datatype(&mut self, datatype: i16) {
+            let offset = if self.version == 1 { 70 } else { 12 };
+            self.put(offset, datatype.to_le_bytes());
+        }
+
+        fn pixdim(&mut self, pixdim: [f32; 4]) {
+            for (i, d) in pixdim.into_iter().enumerate() {
+                self.float(76 + 4 * i, 104 + 8 * i, d);
+            }
+        }
+
+        fn qform(&mut self, quatern: [f32; 3]) {
+            if self.version == 1 {
+                self.put(252, 1i16.to_le_bytes());
+            } else {
+                self.put(344, 1i32.to_le_bytes());
+            }
+            for (i, q) in quatern.into_iter().enumerate() {
+                self.float(256 + 4 * i, 352 + 8 * i, q);
+            }
+        }
+
+        fn sform(&mut self, srow: [[f32; 4]; 3]) {
+            if self.version == 1 {
+                self.put(254, 1i16.to_le_bytes());
+            } else {
+                self.put(348, 1i32.to_le_bytes());
+            }
+            for (row, values) in srow.into_iter().enumerate() {
+                for (i, v) in values.into_iter().enumerate() {
+                    self.float(280 + 16 * row + 4 * i, 400 + 32 * row + 8 * i, v);
+                }
+            }
+        }
+
+        fn parse(&self) -> Result<Header, String> {
+            Header::parse(&self.bytes)
+        }
+    }
+
+    const VARIANTS: [(u32, Endian); 4] = [
+        (1, Endian::Little),
+        (1, Endian::Big),
+        (2, Endian::Little),
+        (2, Endian::Big),
+    ];
+
+    #[test]
+    fn parses_both_versions_in_both_byte_orders() {
+        for (version, endian) in VARIANTS {
+            let mut builder = Builder::new(version, endian, [4, 3, 2]);
+            builder.datatype(512);
+            builder.pixdim([1., 0.5, 2., 3.]);
+            let header = builder.parse().unwrap();
+            assert_eq!(header.endian, endian, "NIfTI-{}", version);
+            assert_eq!(header.dims, [4, 3, 2]);
+            assert_eq!(header.voxel_type, VoxelType::U16);
+            assert_eq!(header.pixdim, [1., 0.5, 2., 3.]);
+            assert_eq!(header.vox_offset, builder.bytes.len());
+            assert!(header.single_file);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_headers() {
+        for (version, endian) in VARIANTS {
+            let mut builder = Builder::new(version, endian, [4, 3, 2]);
+            builder.dims(2, [4, 3, 1]);
+            assert!(builder.parse().is_err());
+
+            let mut builder = Builder::new(version, endian, [4, 0, 2]);
+            assert!(builder.parse().is_err());
+            builder.dims(3, [4, 3, -2]);
+            assert!(builder.parse().is_err());
+            builder.dims(3, [4, 3, 1 << 40]);
+            assert!(builder.parse().is_err());
+
+            let mut builder = Builder::new(version, endian, [4, 3, 2]);
+            builder.datatype(128);
+            assert!(builder.parse().is_err());
+        }
+        assert!(Header::parse(&[0; NIFTI1_HEADER_SIZE]).is_err());
+    }
+
+    #[test]
+    fn sform_wins_over_qform() {
+        for (version, endian) in VARIANTS {
+            let mut builder = Builder::new(version, endian, [2, 2, 2]);
+            builder.pixdim([1., 1., 1., 1.]);
+            builder.qform([0., 0., 1.]);
+            builder.sform([[-2., 0., 0., 0.], [0., 3., 0., 0.], [0., 0., 4., 0.]]);
+            let affine = builder.parse().unwrap().affine();
+            assert_eq!(affine, [[-2., 0., 0.], [0., 3., 0.], [0., 0., 4.]]);
+        }
+    }
+
+    #[test]
+    fn qform_rotates_and_scales() {
+        for (version, endian) in VARIANTS {
+            // Half a turn around the z axis, with the third axis mirrored by qfac.
+            let mut builder = Builder::new(version, endian, [2, 2, 2]);
+            builder.pixdim([-1., 2., 3., 4.]);
+            builder.qform([0., 0., 1.]);
+            let affine = builder.parse().unwrap().affine();
+            assert_eq!(affine, [[-2., 0., 0.], [0., -3., 0.], [0., 0., -4.]]);
+        }
+    }
+
+    #[test]
+    fn spacing_alone_without_transforms() {
+        let mut builder = Builder::new(1, Endian::Little, [2, 2, 2]);
+        builder.pixdim([1., 2., 0., f32::NAN]);
+        let affine = builder.parse().unwrap().affine();
+        assert_eq!(affine, [[2., 0., 0.], [0., 1., 0.], [0., 0., 1.]]);
+    }
+
+    #[test]
+    fn orientation_follows_the_patient() {
+        // RAS: anterior goes to the back of the texture, superior up.
+        let ras = Orientation::from_affine(&[[1., 0., 0.], [0., 2., 0.], [0., 0., 3.]]);
+        assert_eq!(ras.axis, [0, 2, 1]);
+        assert_eq!(ras.flip, [false, true, false]);
+        assert_eq!(ras.spacing, [1., 3., 2.]);
+
+        // LPS, the DICOM convention, mirrors x and y.
+        let lps = Orientation::from_affine(&[[-1., 0., 0.], [0., -1., 0.], [0., 0., 1.]]);
+        assert_eq!(lps.axis, [0, 2, 1]);
+        assert_eq!(lps.flip, [true, false, false]);
+
+        // Slices stored along the anterior axis.
+        let coronal = Orientation::from_affine(&[[1., 0., 0.], [0., 0., 1.], [0., 1., 0.]]);
+        assert_eq!(coronal.axis, [0, 1, 2]);
+        assert_eq!(coronal.flip, [false, false, true]);
+    }
+
+    #[test]
+    fn reorient_flips_and_swaps_axes() {
+        let orientation = Orientation {
+            axis: [1, 0, 2],
+            flip: [true, false, false],
+            spacing: [1.; 3],
+        };
+        // 3x2x1 in the file becomes 2x3x1 with the first file axis reversed.
+        let values = [0., 1., 2., 3., 4., 5.];
+        let (size, out) = orientation.reorient([3, 2, 1], &values);
+        assert_eq!(size, (2, 3, 1));
+        assert_eq!(out, [2., 5., 1., 4., 0., 3.]);
+    }
+
+    #[test]
+    fn intensity_scaling_applies_before_normalizing() {
+        for (version, endian) in VARIANTS {
+            let mut builder = Builder::new(version, endian, [3, 1, 1]);
+            builder.float(112, 176, -1.);
+            builder.float(116, 184, 4.);
+            let values = builder.parse().unwrap().values(&[0, 2, 4]).unwrap();
+            assert_eq!(values, [4., 2., 0.], "NIfTI-{}", version);
+            // The brightest stored voxel ends up the darkest.
+            let volume = Volume::from_scalars((3, 1, 1), &values);
+            let alpha: Vec<f32> = volume.voxels.iter().map(|v| v[3].to_f32()).collect();
+            assert_eq!(alpha, [1., 0.5, 0.]);
+
+            // A zero or broken slope leaves the values alone.
+            for slope in [0., f32::NAN] {
+                builder.float(112, 176, slope);
+                let values = builder.parse().unwrap().values(&[0, 2, 4]).unwrap();
+                assert_eq!(values, [0., 2., 4.]);
+            }
+        }
+    }
+
+    #[test]
+    fn loads_big_endian_file() {
+        let mut builder = Builder::new(1, Endian::Big, [2, 2, 1]);
+        builder.datatype(512);
+        builder.pixdim([1., 1., 1., 1.]);
+        let mut bytes = builder.bytes;
+        for v in [0u16, 100, 200, 300] {
+            bytes.extend_from_slice(&v.to_be_bytes());
+        }
+        let dir = TestDir::new("nifti_big_endian", &[("volume.nii", &bytes)]);
+        let volume = load(&dir.join("volume.nii"));
+
+        // The anterior file axis runs against the texture z axis.
+        let volume = volume.unwrap();
+        assert_eq!(volume.size, (2, 1, 2));
+        let alpha: Vec<f32> = volume.voxels.iter().map(|v| v[3].to_f32()).collect();
+        let expected = [2. / 3., 1., 0., 1. / 3.];
+        for (a, b) in alpha.iter().zip(expected) {
+            assert!((a - b).abs() < 1e-3, "{:?}", alpha);
+        }
+    }
+}
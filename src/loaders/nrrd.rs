@@ -0,0 +1,267 @@
+use super::{decode_exact, Endian, Volume, VoxelType};
+
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Reads a `.nrrd` file with attached data or a `.nhdr` header pointing at a data file.
+/// Only three dimensional volumes with `raw` encoding are supported,
+/// the `spacings` field sets the voxel aspect ratio.
+pub(crate) fn load(path: &Path) -> Result<Volume, String> {
+    let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+    let (fields, data_start) = parse_header(&bytes)?;
+    let field = |name: &str| {
+        fields
+            .get(name)
+            .map(String::as_str)
+            .ok_or(format!("missing `{}` field", name))
+    };
+
+    let voxel_type = parse_type(field("type")?)?;
+    let dimension: u32 = field("dimension")?
+        .parse()
+        .map_err(|_| "invalid `dimension` field".to_owned())?;
+    let sizes = field("sizes")?
+        .split_whitespace()
+        .map(|s| s.parse::<u32>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| "invalid `sizes` field".to_owned())?;
+    let size = match (dimension, &sizes[..]) {
+        (3, &[width, height, depth]) => (width, height, depth),
+        _ => {
+            return Err(format!(
+                "only 3D volumes are supported, got sizes {:?}",
+                sizes
+            ))
+        }
+    };
+    match field("encoding")? {
+        "raw" => {}
+        encoding => return Err(format!("unsupported encoding `{}`", encoding)),
+    }
+    let endian = match fields.get("endian").map(String::as_str) {
+        None if voxel_type.size() == 1 => Endian::Little,
+        None => return Err("missing `endian` field".into()),
+        Some("little") => Endian::Little,
+        Some("big") => Endian::Big,
+        Some(endian) => return Err(format!("unknown endian `{}`", endian)),
+    };
+
+    let data_file = fields.get("data file").or_else(|| fields.get("datafile"));
+    let mut volume = match data_file {
+        Some(file) => {
+            let data_path = path.parent().unwrap_or(Path::new(".")).join(file);
+            let data =
+                std::fs::read(&data_path).map_err(|e| format!("{}: {}", data_path.display(), e))?;
+            decode_exact(&data, size, voxel_type, endian)?
+        }
+        None => decode_exact(&bytes[data_start..], size, voxel_type, endian)?,
+    };
+    if let Some(spacings) = fields.get("spacings") {
+        volume.spacing = parse_spacings(spacings)?;
+    }
+    Ok(volume)
+}
+
+fn parse_spacings(s: &str) -> Result<[f32; 3], String> {
+    let spacings = s
+        .split_whitespace()
+        .map(|v| v.parse::<f32>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| format!("invalid `spacings` field `{}`", s))?;
+    match spacings[..] {
+        [x, y, z] if [x, y, z].iter().all(|v| v.is_finite() && *v > 0.) => Ok([x, y, z]),
+        _ => Err(format!("invalid `spacings` field `{}`", s)),
+    }
+}
+
+/// Splits the header into lowercase field names and values,
+/// returns them with the offset where the attached data begins.
+fn parse_header(bytes: &[u8]) -> Result<(HashMap<String, String>, usize), String> {
+    if !bytes.starts_with(b"NRRD000") {
+        return Err("missing NRRD magic".into());
+    }
+
+    let mut fields = HashMap::new();
+    let mut offset = 0;
+    for (number, line) in bytes.split(|&b| b == b'\n').enumerate() {
+        offset += line.len() + 1;
+        let line = std::str::from_utf8(line)
+            .map_err(|_| format!("line {}: header is not valid UTF-8", number + 1))?
+            .trim_end_matches('\r');
+        if number == 0 || line.starts_with('#') {
+            continue;
+        }
+        if line.is_empty() {
+            return Ok((fields, offset.min(bytes.len())));
+        }
+        // `key:=value` pairs carry free-form metadata we have no use for.
+        if line.contains(":=") {
+            continue;
+        }
+        let (name, value) = line
+            .split_once(": ")
+            .ok_or(format!("line {}: expected `field: value`", number + 1))?;
+        fields.insert(name.to_ascii_lowercase(), value.trim().to_owned());
+    }
+    // A detached header may end without a blank line.
+    Ok((fields, bytes.len()))
+}
+
+fn parse_type(s: &str) -> Result<VoxelType, String> {
+    Ok(match s {
+        "uchar" | "unsigned char" | "uint8" | "uint8_t" => VoxelType::U8,
+        "signed char" | "int8" | "int8_t" => VoxelType::I8,
+        "ushort" | "unsigned short" | "unsigned short int" | "uint16" | "uint16_t" => {
+            VoxelType::U16
+        }
+        "short" | "short int" | "signed short" | "signed short int" | "int16" | "int16_t" => {
+            VoxelType::I16
+        }
+        "uint" | "unsigned int" | "uint32" | "uint32_t" => VoxelType::U32,
+        "int" | "signed int" | "int32" | "int32_t" => VoxelType::I32,
+        "float" => VoxelType::F32,
+        "double" => VoxelType::F64,
+        _ => return Err(format!("unsupported voxel type `{}`", s)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::utils::test_dir::TestDir;
+
+    /// Writes `files` into a folder of their own and loads the first one.
+    fn load_files(name: &str, files: &[(&str, &[u8])]) -> Result<Volume, String> {
+        load(&TestDir::new(name, files).join(files[0].0))
+    }
+
+    fn attached(header: &str, data: &[u8]) -> Vec<u8> {
+        let mut bytes = header.as_bytes().to_vec();
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    fn alpha(volume: &Volume) -> Vec<f32> {
+        volume.voxels.iter().map(|v| v[3].to_f32()).collect()
+    }
+
+    #[test]
+    fn parses_header_fields() {
+        let bytes =
+            b"NRRD0004\r\n# comment\r\nType: uchar\r\nspace:=ignored\r\nsizes: 1 2 3\r\n\r\nDATA";
+        let (fields, data_start) = parse_header(bytes).unwrap();
+        assert_eq!(fields["type"], "uchar");
+        assert_eq!(fields["sizes"], "1 2 3");
+        assert!(!fields.contains_key("space"));
+        assert_eq!(&bytes[data_start..], b"DATA");
+
+        assert!(parse_header(b"P6\n").is_err());
+        assert!(parse_header(b"NRRD0004\nsizes 1 2 3\n\n").is_err());
+        // Detached headers may stop without a blank line.
+        let bytes = b"NRRD0005\ntype: float";
+        let (fields, data_start) = parse_header(bytes).unwrap();
+        assert_eq!(fields["type"], "float");
+        assert_eq!(data_start, bytes.len());
+    }
+
+    #[test]
+    fn loads_attached_raw_data() {
+        let header = "NRRD0004\ntype: uint16\ndimension: 3\nsizes: 2 1 2\nendian: big\nencoding: raw\nspacings: 1 2 0.5\n\n";
+        let data: Vec<u8> = [0u16, 1000, 3000, 4000]
+            .iter()
+            .flat_map(|v| v.to_be_bytes())
+            .collect();
+        let volume = load_files(
+            "nrrd_attached",
+            &[("volume.nrrd", &attached(header, &data))],
+        )
+        .unwrap();
+        assert_eq!(volume.size, (2, 1, 2));
+        assert_eq!(volume.spacing, [1., 2., 0.5]);
+        let expected = [0., 0.25, 0.75, 1.];
+        for (a, b) in alpha(&volume).iter().zip(expected) {
+            assert!((a - b).abs() < 1e-3, "{:?}", alpha(&volume));
+        }
+    }
+
+    #[test]
+    fn single_byte_types_need_no_endian() {
+        let header = "NRRD0004\ntype: uchar\ndimension: 3\nsizes: 2 1 1\nencoding: raw\n\n";
+        let volume = load_files(
+            "nrrd_no_endian",
+            &[("volume.nrrd", &attached(header, &[0, 255]))],
+        );
+        assert_eq!(alpha(&volume.unwrap()), [0., 1.]);
+
+        let header = "NRRD0004\ntype: ushort\ndimension: 3\nsizes: 1 1 1\nencoding: raw\n\n";
+        let err = load_files(
+            "nrrd_missing_endian",
+            &[("volume.nrrd", &attached(header, &[0, 0]))],
+        );
+        assert!(err.is_err());
+    }
+
+    #[test]
+    fn loads_detached_data_file() {
+        let header = "NRRD0004\ntype: float\ndimension: 3\nsizes: 1 1 2\nendian: little\nencoding: raw\ndata file: volume.raw\n";
+        let data: Vec<u8> = [2f32, 4.].iter().flat_map(|v| v.to_le_bytes()).collect();
+        let volume = load_files(
+            "nrrd_detached",
+            &[("volume.nhdr", header.as_bytes()), ("volume.raw", &data)],
+        )
+        .unwrap();
+        assert_eq!(volume.size, (1, 1, 2));
+        assert_eq!(alpha(&volume), [0., 1.]);
+
+        let missing = load_files(
+            "nrrd_detached_missing",
+            &[("volume.nhdr", header.as_bytes())],
+        );
+        assert!(missing.err().unwrap().contains("volume.raw"));
+    }
+
+    #[test]
+    fn rejects_unsupported_volumes() {
+        let cases = [
+            (
+                "type: uchar\ndimension: 3\nsizes: 1 1 1\nencoding: gzip\n",
+                "encoding",
+            ),
+            (
+                "type: uchar\ndimension: 2\nsizes: 1 1\nencoding: raw\n",
+                "3D",
+            ),
+            (
+                "type: uchar\ndimension: 4\nsizes: 1 1 1 1\nencoding: raw\n",
+                "3D",
+            ),
+            (
+                "type: block\ndimension: 3\nsizes: 1 1 1\nencoding: raw\n",
+                "type",
+            ),
+            (
+                "type: uchar\ndimension: 3\nsizes: 1 1 1\nencoding: raw\nspacings: 1 0 1\n",
+                "spacings",
+            ),
+            ("type: uchar\ndimension: 3\nencoding: raw\n", "sizes"),
+        ];
+        for (i, (fields, expected)) in cases.into_iter().enumerate() {
+            let bytes = attached(&format!("NRRD0004\n{}\n", fields), &[0]);
+            let err = load_files(
+                &format!("nrrd_unsupported_{}", i),
+                &[("volume.nrrd", &bytes)],
+            )
+            .err()
+            .unwrap();
+            assert!(err.contains(expected), "{}", err);
+        }
+    }
+
+    #[test]
+    fn rejects_wrong_data_length() {
+        let header = "NRRD0004\ntype: uchar\ndimension: 3\nsizes: 2 2 2\nencoding: raw\n\n";
+        let bytes = attached(header, &[0; 7]);
+        assert!(load_files("nrrd_short", &[("volume.nrrd", &bytes)]).is_err());
+    }
+}
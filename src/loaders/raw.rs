@@ -0,0 +1,9 @@
+use super::{decode_exact, RawLayout, Volume};
+
+use std::path::Path;
+
+/// Reads a headerless file whose dimensions, type and byte order come from `layout`.
+pub(crate) fn load(path: &Path, layout: &RawLayout) -> Result<Volume, String> {
+    let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+    decode_exact(&bytes, layout.size, layout.voxel_type, layout.endian)
+}
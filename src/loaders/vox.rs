@@ -0,0 +1,831 @@
+//! MagicaVoxel `.vox` files.
+//!
+//! MagicaVoxel is z-up, voxel `(x, y, z)` of a model lands on texel
+//! `(x, z, depth - 1 - y)` so the volume keeps its handedness.
+//! Palette colors are sRGB and get converted to linear, the palette alpha
+//! becomes the voxel alpha.
+
+use super::Volume;
+
+use half::f16;
+
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Models can't be larger than this along any axis, bigger volumes are split.
+const MODEL_SIZE: u32 = 256;
+/// Largest scene extent accepted when assembling the models of a file.
+const MAX_EXTENT: u32 = 2048;
+/// Most voxels a scene may assemble into, 2 GiB once converted to Rgba16Float.
+const MAX_VOXELS: usize = 1 << 28;
+/// Usable palette entries, index 0 means empty.
+const PALETTE_LEN: usize = 255;
+
+/// `max_dimension` is the largest side of a 3D texture on the device.
+pub(crate) fn load(path: &Path, max_dimension: u32) -> Result<Volume, String> {
+    let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+    read(&bytes, max_dimension)
+}
+
+pub(crate) fn save(path: &Path, volume: &Volume) -> Result<(), String> {
+    std::fs::write(path, write(volume)?).map_err(|e| format!("{}: {}", path.display(), e))?;
+    eprintln!("Saved volume to {}", path.display());
+    Ok(())
+}
+
+struct Model {
+    size: [u32; 3],
+    /// `x, y, z, color index` in MagicaVoxel coordinates.
+    voxels: Vec<[u8; 4]>,
+}
+
+/// Rotation and translation of an `nTRN` node, the rotation is a signed permutation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Transform {
+    rotation: [[i32; 3]; 3],
+    translation: [i64; 3],
+}
+
+impl Transform {
+    const IDENTITY: Self = Self {
+        rotation: [[1, 0, 0], [0, 1, 0], [0, 0, 1]],
+        translation: [0; 3],
+    };
+
+    fn apply(&self, p: [i64; 3]) -> [i64; 3] {
+        std::array::from_fn(|i| {
+            let row = self.rotation[i];
+            (0..3).map(|j| row[j] as i64 * p[j]).sum::<i64>() + self.translation[i]
+        })
+    }
+
+    /// Applies `child` first and `self` after it.
+    fn then(&self, child: &Self) -> Self {
+        Self {
+            rotation: std::array::from_fn(|i| {
+                std::array::from_fn(|j| {
+                    (0..3)
+                        .map(|k| self.rotation[i][k] * child.rotation[k][j])
+                        .sum()
+                })
+            }),
+            translation: self.apply(child.translation),
+        }
+    }
+}
+
+enum Node {
+    Transform { child: i32, transform: Transform },
+    Group { children: Vec<i32> },
+    Shape { models: Vec<i32> },
+}
+
+/// Little endian cursor over the content of a chunk.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() < len {
+            return Err("unexpected end of file".into());
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        let b = self.take(4)?;
+        Ok(i32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn count(&mut self) -> Result<usize, String> {
+        usize::try_from(self.i32()?).map_err(|_| "negative count".to_owned())
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.count()?;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+
+    fn dict(&mut self) -> Result<HashMap<String, String>, String> {
+        let len = self.count()?;
+        (0..len)
+            .map(|_| Ok((self.string()?, self.string()?)))
+            .collect()
+    }
+}
+
+pub(crate) fn read(bytes: &[u8], max_dimension: u32) -> Result<Volume, String> {
+    let mut reader = Reader { bytes };
+    if reader.take(4)? != b"VOX " {
+        return Err("missing VOX magic".into());
+    }
+    let version = reader.i32()?;
+    if version != 150 && version != 200 {
+        return Err(format!("unsupported version {}", version));
+    }
+    if reader.take(4)? != b"MAIN" {
+        return Err("missing MAIN chunk".into());
+    }
+    let main_len = reader.count()?;
+    reader.take(main_len)?;
+    let children_len = reader.count()?;
+    let mut chunks = Reader {
+        bytes: reader.take(children_len)?,
+    };
+
+    let mut models = Vec::new();
+    let mut size = None;
+    let mut nodes = HashMap::new();
+    let mut palette = default_palette();
+    while !chunks.bytes.is_empty() {
+        let id = chunks.take(4)?;
+        let content_len = chunks.count()?;
+        let nested_len = chunks.count()?;
+        let mut content = Reader {
+            bytes: chunks.take(content_len)?,
+        };
+        chunks.take(nested_len)?;
+
+        match id {
+            b"SIZE" => {
+                let mut dims = [0; 3];
+                for dim in &mut dims {
+                    *dim = u32::try_from(content.i32()?)
+                        .map_err(|_| "negative model size".to_owned())?;
+                }
+                size = Some(dims);
+            }
+            b"XYZI" => {
+                let size = size.take().ok_or("XYZI chunk without SIZE")?;
+                let len = content.count()?;
+                let voxels = content
+                    .take(len * 4)?
+                    .chunks_exact(4)
+                    .map(|v| [v[0], v[1], v[2], v[3]])
+                    .collect::<Vec<_>>();
+                if let Some(v) = voxels
+                    .iter()
+                    .find(|v| (0..3).any(|i| v[i] as u32 >= size[i]))
+                {
+                    return Err(format!("voxel {:?} outside of model size {:?}", v, size));
+                }
+                models.push(Model { size, voxels });
+            }
+            b"RGBA" => {
+                // Entry `i` is color index `i + 1`, the last entry is never used.
+                for color in &mut palette[1..] {
+                    let c = content.take(4)?;
+                    *color = [c[0], c[1], c[2], c[3]];
+                }
+            }
+            b"nTRN" => {
+                let id = content.i32()?;
+                content.dict()?;
+                let child = content.i32()?;
+                let _reserved = content.i32()?;
+                let _layer = content.i32()?;
+                let frames = content.count()?;
+                let mut transform = Transform::IDENTITY;
+                for frame in 0..frames {
+                    let attributes = content.dict()?;
+                    if frame != 0 {
+                        continue;
+                    }
+                    if let Some(t) = attributes.get("_t") {
+                        transform.translation = parse_translation(t)?.map(i64::from);
+                    }
+                    if let Some(r) = attributes.get("_r") {
+                        transform.rotation = parse_rotation(r)?;
+                    }
+                }
+                nodes.insert(id, Node::Transform { child, transform });
+            }
+            b"nGRP" => {
+                let id = content.i32()?;
+                content.dict()?;
+                let len = content.count()?;
+                let children = (0..len).map(|_| content.i32()).collect::<Result<_, _>>()?;
+                nodes.insert(id, Node::Group { children });
+            }
+            b"nSHP" => {
+                let id = content.i32()?;
+                content.dict()?;
+                let len = content.count()?;
+                let mut shape_models = Vec::with_capacity(len);
+                for _ in 0..len {
+                    shape_models.push(content.i32()?);
+                    content.dict()?;
+                }
+                nodes.insert(
+                    id,
+                    Node::Shape {
+                        models: shape_models,
+                    },
+                );
+            }
+            // Materials, layers, cameras and the like don't affect the voxels.
+            _ => {}
+        }
+    }
+    if models.is_empty() {
+        return Err("no models in file".into());
+    }
+
+    // Without a scene graph every model sits at the origin.
+    let mut placements = Vec::new();
+    if nodes.is_empty() {
+        placements.extend((0..models.len()).map(|i| (i, Transform::IDENTITY)));
+    } else {
+        place(&nodes, 0, Transform::IDENTITY, &mut placements, 0)?;
+    }
+    let mut instances = Vec::with_capacity(placements.len());
+    for (index, transform) in placements {
+        let model = models
+            .get(index)
+            .ok_or(format!("shape refers to missing model {}", index))?;
+        instances.push((model, transform));
+    }
+
+    // Inclusive bounds of the voxels in scene space.
+    let mut min = [i64::MAX; 3];
+    let mut max = [i64::MIN; 3];
+    for (model, transform) in &instances {
+        let corners = [[0; 3], model.size.map(|s| s as i64 - 1)];
+        for corner in corners.map(|c| transform.apply(model_offset(model, c))) {
+            for i in 0..3 {
+                min[i] = min[i].min(corner[i]);
+                max[i] = max[i].max(corner[i]);
+            }
+        }
+    }
+    let extent: [u32; 3] =
+        std::array::from_fn(|i| (max[i] - min[i] + 1).clamp(0, u32::MAX as i64) as u32);
+    let max_extent = MAX_EXTENT.min(max_dimension);
+    if extent.iter().any(|&e| e == 0 || e > max_extent) {
+        return Err(format!(
+            "scene extent {:?} must be between 1 and {} on every axis",
+            extent, max_extent
+        ));
+    }
+
+    let size = (extent[0], extent[2], extent[1]);
+    let len = (size.0 as usize)
+        .checked_mul(size.1 as usize)
+        .and_then(|len| len.checked_mul(size.2 as usize))
+        .filter(|&len| len <= MAX_VOXELS)
+        .ok_or(format!(
+            "scene extent {:?} exceeds the limit of {} voxels",
+            extent, MAX_VOXELS
+        ))?;
+    let mut voxels = vec![[f16::ZERO; 4]; len];
+    let colors = palette.map(|c| {
+        [
+            f16::from_f32(srgb_to_linear(c[0])),
+            f16::from_f32(srgb_to_linear(c[1])),
+            f16::from_f32(srgb_to_linear(c[2])),
+            f16::from_f32(c[3] as f32 / 255.),
+        ]
+    });
+    for (model, transform) in instances {
+        for &[x, y, z, index] in &model.voxels {
+            let p = transform.apply(model_offset(model, [x, y, z].map(i64::from)));
+            let [x, y, z]: [u32; 3] = std::array::from_fn(|i| (p[i] - min[i]) as u32);
+            let (tx, ty, tz) = (x as usize, z as usize, (size.2 - 1 - y) as usize);
+            voxels[(tz * size.1 as usize + ty) * size.0 as usize + tx] = colors[index as usize];
+        }
+    }
+    Ok(Volume::new(size, voxels))
+}
+
+/// Position of voxel `p` relative to the voxel at the center of `model`,
+/// which is where translations put it and what rotations turn around.
+fn model_offset(model: &Model, p: [i64; 3]) -> [i64; 3] {
+    std::array::from_fn(|i| p[i] - (model.size[i] / 2) as i64)
+}
+
+/// Walks the scene graph from `id`, collecting the models with their accumulated transform.
+fn place(
+    nodes: &HashMap<i32, Node>,
+    id: i32,
+    transform: Transform,
+    placements: &mut Vec<(usize, Transform)>,
+    depth: usize,
+) -> Result<(), String> {
+    if depth > nodes.len() {
+        return Err("cycle in scene graph".into());
+    }
+    match nodes.get(&id) {
+        Some(Node::Transform {
+            child,
+            transform: local,
+        }) => place(nodes, *child, transform.then(local), placements, depth + 1),
+        Some(Node::Group { children }) => children
+            .iter()
+            .try_for_each(|&child| place(nodes, child, transform, placements, depth + 1)),
+        Some(Node::Shape { models }) => {
+            for &model in models {
+                let model = usize::try_from(model).map_err(|_| "negative model id")?;
+                placements.push((model, transform));
+            }
+            Ok(())
+        }
+        None => Err(format!("scene graph refers to missing node {}", id)),
+    }
+}
+
+fn parse_translation(s: &str) -> Result<[i32; 3], String> {
+    let values = s
+        .split_whitespace()
+        .map(str::parse)
+        .collect::<Result<Vec<i32>, _>>()
+        .map_err(|_| format!("invalid translation `{}`", s))?;
+    match values[..] {
+        [x, y, z] => Ok([x, y, z]),
+        _ => Err(format!("invalid translation `{}`", s)),
+    }
+}
+
+/// Decodes the `_r` byte: bits 0-1 and 2-3 hold the column of the non-zero
+/// entry in the first and second row, bits 4-6 make rows 1-3 negative.
+fn parse_rotation(s: &str) -> Result<[[i32; 3]; 3], String> {
+    let err = || format!("invalid rotation `{}`", s);
+    let bits: u8 = s.trim().parse().map_err(|_| err())?;
+    let (first, second) = ((bits & 3) as usize, (bits >> 2 & 3) as usize);
+    if first > 2 || second > 2 || first == second || bits >> 7 != 0 {
+        return Err(err());
+    }
+    let mut rotation = [[0; 3]; 3];
+    for (row, column) in [first, second, 3 - first - second].into_iter().enumerate() {
+        rotation[row][column] = if bits >> (4 + row) & 1 == 0 { 1 } else { -1 };
+    }
+    Ok(rotation)
+}
+
+/// Encodes `volume` as MagicaVoxel models of at most 256³ voxels each.
+/// Voxels whose alpha rounds to zero are left empty.
+pub(crate) fn write(volume: &Volume) -> Result<Vec<u8>, String> {
+    let (width, height, depth) = volume.size;
+    // MagicaVoxel extent of the volume.
+    let extent = [width, depth, height];
+
+    let mut colors = vec![[0u8; 4]; volume.voxels.len()];
+    for (color, voxel) in colors.iter_mut().zip(&volume.voxels) {
+        let [r, g, b, a] = voxel.map(f16::to_f32);
+        *color = [
+            linear_to_srgb(r),
+            linear_to_srgb(g),
+            linear_to_srgb(b),
+            (a.clamp(0., 1.) * 255.).round() as u8,
+        ];
+    }
+    let (palette, lookup) = build_palette(colors.iter().filter(|c| c[3] > 0).copied());
+
+    let chunks: [Vec<u32>; 3] =
+        std::array::from_fn(|i| (0..extent[i]).step_by(MODEL_SIZE as usize).collect());
+    let mut children = Vec::new();
+    let mut translations = Vec::new();
+    for &oz in &chunks[2] {
+        for &oy in &chunks[1] {
+            for &ox in &chunks[0] {
+                let origin = [ox, oy, oz];
+                let size: [u32; 3] =
+                    std::array::from_fn(|i| (extent[i] - origin[i]).min(MODEL_SIZE));
+                let mut voxels = Vec::new();
+                for z in 0..size[2] {
+                    for y in 0..size[1] {
+                        for x in 0..size[0] {
+                            let [vx, vy, vz] = [x + ox, y + oy, z + oz];
+                            let (tx, ty, tz) = (vx, vz, depth - 1 - vy);
+                            let color = colors[((tz * height + ty) * width + tx) as usize];
+                            if color[3] > 0 {
+                                voxels.extend_from_slice(&[
+                                    x as u8,
+                                    y as u8,
+                                    z as u8,
+                                    lookup[&color],
+                                ]);
+                            }
+                        }
+                    }
+                }
+                let mut content = Vec::new();
+                size.iter().for_each(|&s| push_i32(&mut content, s as i32));
+                push_chunk(&mut children, b"SIZE", &content);
+
+                let mut content = Vec::new();
+                push_i32(&mut content, (voxels.len() / 4) as i32);
+                content.extend_from_slice(&voxels);
+                push_chunk(&mut children, b"XYZI", &content);
+
+                translations.push(std::array::from_fn::<i32, 3, _>(|i| {
+                    (origin[i] + size[i] / 2) as i32
+                }));
+            }
+        }
+    }
+
+    // Scene graph: root transform -> group -> one transform and shape per model.
+    if translations.len() > 1 {
+        let model_count = translations.len() as i32;
+        push_transform(&mut children, 0, 1, -1, None);
+        let mut content = Vec::new();
+        push_i32(&mut content, 1);
+        push_i32(&mut content, 0);
+        push_i32(&mut content, model_count);
+        (0..model_count).for_each(|i| push_i32(&mut content, 2 + 2 * i));
+        push_chunk(&mut children, b"nGRP", &content);
+        for (i, translation) in translations.iter().enumerate() {
+            let id = 2 + 2 * i as i32;
+            push_transform(&mut children, id, id + 1, 0, Some(translation));
+            let mut content = Vec::new();
+            push_i32(&mut content, id + 1);
+            push_i32(&mut content, 0);
+            push_i32(&mut content, 1);
+            push_i32(&mut content, i as i32);
+            push_i32(&mut content, 0);
+            push_chunk(&mut children, b"nSHP", &content);
+        }
+    }
+
+    let mut content = Vec::with_capacity(256 * 4);
+    palette.iter().for_each(|c| content.extend_from_slice(c));
+    content.resize(256 * 4, 0);
+    push_chunk(&mut children, b"RGBA", &content);
+
+    let mut bytes = Vec::with_capacity(children.len() + 20);
+    bytes.extend_from_slice(b"VOX ");
+    push_i32(&mut bytes, 150);
+    bytes.extend_from_slice(b"MAIN");
+    push_i32(&mut bytes, 0);
+    push_i32(
+        &mut bytes,
+        i32::try_from(children.len()).map_err(|_| "volume too large for a .vox file")?,
+    );
+    bytes.extend_from_slice(&children);
+    Ok(bytes)
+}
+
+fn push_i32(bytes: &mut Vec<u8>, v: i32) {
+    bytes.extend_from_slice(&v.to_le_bytes());
+}
+
+fn push_chunk(bytes: &mut Vec<u8>, id: &[u8; 4], content: &[u8]) {
+    bytes.extend_from_slice(id);
+    push_i32(bytes, content.len() as i32);
+    push_i32(bytes, 0);
+    bytes.extend_from_slice(content);
+}
+
+fn push_transform(
+    bytes: &mut Vec<u8>,
+    id: i32,
+    child: i32,
+    layer: i32,
+    translation: Option<&[i32; 3]>,
+) {
+    let mut content = Vec::new();
+    push_i32(&mut content, id);
+    push_i32(&mut content, 0);
+    push_i32(&mut content, child);
+    push_i32(&mut content, -1);
+    push_i32(&mut content, layer);
+    push_i32(&mut content, 1);
+    match translation {
+        Some([x, y, z]) => {
+            let value = format!("{} {} {}", x, y, z);
+            push_i32(&mut content, 1);
+            push_i32(&mut content, 2);
+            content.extend_from_slice(b"_t");
+            push_i32(&mut content, value.len() as i32);
+            content.extend_from_slice(value.as_bytes());
+        }
+        None => push_i32(&mut content, 0),
+    }
+    push_chunk(bytes, b"nTRN", &content);
+}
+
+/// Picks at most 255 colors and maps every color in `colors` to a palette index.
+/// Colors are kept exact when they fit, otherwise the most common colors win
+/// and the rest snap to the closest of them.
+fn build_palette(colors: impl Iterator<Item = [u8; 4]>) -> (Vec<[u8; 4]>, HashMap<[u8; 4], u8>) {
+    let mut counts = HashMap::new();
+    for color in colors {
+        *counts.entry(color).or_insert(0u32) += 1;
+    }
+
+    let mut unique: Vec<_> = counts.iter().map(|(&c, &n)| (c, n)).collect();
+    // Deterministic output regardless of the hash map order.
+    unique.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    let palette: Vec<[u8; 4]> = unique.iter().take(PALETTE_LEN).map(|(c, _)| *c).collect();
+
+    let distance = |a: &[u8; 4], b: &[u8; 4]| {
+        a.iter()
+            .zip(b)
+            .map(|(&a, &b)| (a as i32 - b as i32).pow(2))
+            .sum::<i32>()
+    };
+    let lookup = unique
+        .iter()
+        .map(|(color, _)| {
+            let index = palette
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, p)| distance(color, p))
+                .map_or(0, |(i, _)| i);
+            (*color, index as u8 + 1)
+        })
+        .collect();
+    (palette, lookup)
+}
+
+fn srgb_to_linear(c: u8) -> f32 {
+    let c = c as f32 / 255.;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(c: f32) -> u8 {
+    let c = c.clamp(0., 1.);
+    let c = if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1. / 2.4) - 0.055
+    };
+    (c * 255.).round() as u8
+}
+
+/// MagicaVoxel's default palette indexed by color index, used by files without an RGBA chunk.
+/// It is a 6x6x6 color cube without black followed by red, green, blue and gray ramps.
+fn default_palette() -> [[u8; 4]; 256] {
+    const CUBE: [u8; 6] = [0xff, 0xcc, 0x99, 0x66, 0x33, 0x00];
+    const RAMP: [u8; 10] = [0xee, 0xdd, 0xbb, 0xaa, 0x88, 0x77, 0x55, 0x44, 0x22, 0x11];
+
+    let mut palette = [[0; 4]; 256];
+    let cube = CUBE.iter().flat_map(|&r| {
+        CUBE.iter()
+            .flat_map(move |&g| CUBE.iter().map(move |&b| [r, g, b, 0xff]))
+    });
+    let ramps = (0..4).flat_map(|channel| {
+        RAMP.iter().map(move |&v| match channel {
+            3 => [v, v, v, 0xff],
+            _ => {
+                let mut c = [0, 0, 0, 0xff];
+                c[channel] = v;
+                c
+            }
+        })
+    });
+    for (entry, color) in palette[1..].iter_mut().zip(cube.take(215).chain(ramps)) {
+        *entry = color;
+    }
+    palette
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn volume(size: (u32, u32, u32), color: impl Fn(u32, u32, u32) -> [f32; 4]) -> Volume {
+        let (width, height, depth) = size;
+        let mut voxels = Vec::new();
+        for z in 0..depth {
+            for y in 0..height {
+                for x in 0..width {
+                    voxels.push(color(x, y, z).map(f16::from_f32));
+                }
+            }
+        }
+        Volume::new(size, voxels)
+    }
+
+    fn srgb(c: [u8; 4]) -> [f32; 4] {
+        [
+            srgb_to_linear(c[0]),
+            srgb_to_linear(c[1]),
+            srgb_to_linear(c[2]),
+            c[3] as f32 / 255.,
+        ]
+    }
+
+    fn count_chunks(bytes: &[u8], id: &[u8; 4]) -> usize {
+        bytes.windows(4).filter(|w| w == id).count()
+    }
+
+    #[test]
+    fn round_trip_keeps_palette_colors_and_alpha() {
+        let colors = [[255, 0, 0, 255], [12, 200, 99, 128], [1, 2, 3, 4]];
+        let original = volume((5, 4, 3), |x, y, z| match (x + y + z) % 4 {
+            3 => [0.; 4],
+            i => srgb(colors[i as usize]),
+        });
+
+        let bytes = write(&original).unwrap();
+        let loaded = read(&bytes, MAX_EXTENT).unwrap();
+
+        assert_eq!(loaded.size, original.size);
+        assert_eq!(loaded.voxels, original.voxels);
+    }
+
+    #[test]
+    fn volumes_larger_than_a_model_are_chunked() {
+        let original = volume((MODEL_SIZE + 3, 2, 1), |x, y, _| {
+            if (x + y) % 2 == 0 {
+                srgb([(x % 100) as u8, y as u8 * 100, 7, 255])
+            } else {
+                [0.; 4]
+            }
+        });
+
+        let bytes = write(&original).unwrap();
+        assert_eq!(count_chunks(&bytes, b"XYZI"), 2);
+        assert_eq!(count_chunks(&bytes, b"nSHP"), 2);
+
+        let loaded = read(&bytes, MAX_EXTENT).unwrap();
+        assert_eq!(loaded.size, original.size);
+        assert_eq!(loaded.voxels, original.voxels);
+    }
+
+    #[test]
+    fn model_size_limit_is_not_chunked() {
+        let original = volume((MODEL_SIZE, 1, 1), |_, _, _| [1.; 4]);
+        let bytes = write(&original).unwrap();
+        assert_eq!(count_chunks(&bytes, b"XYZI"), 1);
+        assert_eq!(read(&bytes, MAX_EXTENT).unwrap().voxels, original.voxels);
+    }
+
+    #[test]
+    fn palette_overflow_snaps_to_closest_color() {
+        // 256 distinct grays, one more than the palette holds.
+        let original = volume((16, 16, 1), |x, y, _| {
+            let v = (y * 16 + x) as u8;
+            srgb([v, v, v, 255])
+        });
+
+        let loaded = read(&write(&original).unwrap(), MAX_EXTENT).unwrap();
+        let mut distinct = loaded.voxels.clone();
+        distinct.sort_by_key(|c| c.map(f16::to_bits));
+        distinct.dedup();
+        assert_eq!(distinct.len(), PALETTE_LEN);
+
+        for (a, b) in loaded.voxels.iter().zip(&original.voxels) {
+            assert!(linear_to_srgb(a[0].to_f32()).abs_diff(linear_to_srgb(b[0].to_f32())) <= 1);
+        }
+    }
+
+    #[test]
+    fn default_palette_matches_magicavoxel() {
+        let palette = default_palette();
+        assert_eq!(palette[0], [0; 4]);
+        assert_eq!(palette[1], [0xff; 4]);
+        assert_eq!(palette[2], [0xff, 0xff, 0xcc, 0xff]);
+        assert_eq!(palette[215], [0x00, 0x00, 0x33, 0xff]);
+        assert_eq!(palette[216], [0xee, 0x00, 0x00, 0xff]);
+        assert_eq!(palette[255], [0x11, 0x11, 0x11, 0xff]);
+    }
+
+    /// Two single voxel models, the second `offset` voxels further along every axis.
+    fn two_models(offset: i32) -> Vec<u8> {
+        let mut children = Vec::new();
+        for _ in 0..2 {
+            let mut content = Vec::new();
+            (0..3).for_each(|_| push_i32(&mut content, 1));
+            push_chunk(&mut children, b"SIZE", &content);
+            let mut content = Vec::new();
+            push_i32(&mut content, 1);
+            content.extend_from_slice(&[0, 0, 0, 1]);
+            push_chunk(&mut children, b"XYZI", &content);
+        }
+        push_transform(&mut children, 0, 1, -1, None);
+        let mut content = Vec::new();
+        [1, 0, 2, 2, 4]
+            .iter()
+            .for_each(|&v| push_i32(&mut content, v));
+        push_chunk(&mut children, b"nGRP", &content);
+        for i in 0..2 {
+            let id = 2 + 2 * i;
+            push_transform(&mut children, id, id + 1, 0, Some(&[offset * i; 3]));
+            let mut content = Vec::new();
+            [id + 1, 0, 1, i, 0]
+                .iter()
+                .for_each(|&v| push_i32(&mut content, v));
+            push_chunk(&mut children, b"nSHP", &content);
+        }
+
+        let mut bytes = b"VOX ".to_vec();
+        push_i32(&mut bytes, 150);
+        bytes.extend_from_slice(b"MAIN");
+        push_i32(&mut bytes, 0);
+        push_i32(&mut bytes, children.len() as i32);
+        bytes.extend_from_slice(&children);
+        bytes
+    }
+
+    #[test]
+    fn scene_extent_is_limited_by_the_device() {
+        let bytes = two_models(3);
+        assert_eq!(read(&bytes, MAX_EXTENT).unwrap().size, (4, 4, 4));
+        assert!(read(&bytes, 3).is_err());
+    }
+
+    #[test]
+    fn rejects_scenes_over_the_voxel_budget() {
+        // 2000³ voxels fit the extent limit but not the budget.
+        let err = read(&two_models(1999), MAX_EXTENT).err().unwrap();
+        assert!(err.contains("voxels"), "{}", err);
+    }
+
+    /// One 2x1x1 model with colors 1 and 2 under a transform node rotated by `rotation`.
+    fn rotated_model(rotation: &str) -> Vec<u8> {
+        let mut children = Vec::new();
+        let mut content = Vec::new();
+        [2, 1, 1].iter().for_each(|&v| push_i32(&mut content, v));
+        push_chunk(&mut children, b"SIZE", &content);
+        let mut content = Vec::new();
+        push_i32(&mut content, 2);
+        content.extend_from_slice(&[0, 0, 0, 1, 1, 0, 0, 2]);
+        push_chunk(&mut children, b"XYZI", &content);
+
+        let mut content = Vec::new();
+        [0, 0, 1, -1, -1, 1, 1, 2]
+            .iter()
+            .for_each(|&v| push_i32(&mut content, v));
+        content.extend_from_slice(b"_r");
+        push_i32(&mut content, rotation.len() as i32);
+        content.extend_from_slice(rotation.as_bytes());
+        push_chunk(&mut children, b"nTRN", &content);
+        let mut content = Vec::new();
+        [1, 0, 1, 0, 0]
+            .iter()
+            .for_each(|&v| push_i32(&mut content, v));
+        push_chunk(&mut children, b"nSHP", &content);
+
+        let mut bytes = b"VOX ".to_vec();
+        push_i32(&mut bytes, 150);
+        bytes.extend_from_slice(b"MAIN");
+        push_i32(&mut bytes, 0);
+        push_i32(&mut bytes, children.len() as i32);
+        bytes.extend_from_slice(&children);
+        bytes
+    }
+
+    #[test]
+    fn parses_rotation_bits() {
+        assert_eq!(parse_rotation("4"), Ok(Transform::IDENTITY.rotation));
+        // x' = -y, y' = x, a quarter turn around z.
+        assert_eq!(parse_rotation("17"), Ok([[0, -1, 0], [1, 0, 0], [0, 0, 1]]));
+        assert_eq!(
+            parse_rotation("120"),
+            Ok([[-1, 0, 0], [0, 0, -1], [0, -1, 0]])
+        );
+        for s in ["0", "3", "5", "15", "132", "-1", "x"] {
+            assert!(parse_rotation(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn nested_transforms_rotate_child_translations() {
+        let parent = Transform {
+            rotation: [[0, -1, 0], [1, 0, 0], [0, 0, 1]],
+            translation: [10, 0, 0],
+        };
+        let child = Transform {
+            rotation: Transform::IDENTITY.rotation,
+            translation: [2, 0, 0],
+        };
+        let combined = parent.then(&child);
+        assert_eq!(combined.translation, [10, 2, 0]);
+        assert_eq!(combined.apply([1, 0, 0]), [10, 3, 0]);
+        assert_eq!(parent.then(&Transform::IDENTITY), parent);
+    }
+
+    #[test]
+    fn rotated_models_are_turned() {
+        let palette = default_palette();
+        let identity = read(&rotated_model("4"), MAX_EXTENT).unwrap();
+        assert_eq!(identity.size, (2, 1, 1));
+
+        // The model now runs along y, which becomes the texture z axis flipped.
+        let turned = read(&rotated_model("17"), MAX_EXTENT).unwrap();
+        assert_eq!(turned.size, (1, 1, 2));
+        for (voxel, color) in turned.voxels.iter().zip([2, 1]) {
+            let expected = srgb(palette[color]);
+            assert_eq!(
+                voxel.map(|c| c.to_f32()),
+                expected.map(|c| f16::from_f32(c).to_f32())
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let bytes = write(&volume((2, 2, 2), |_, _, _| [1.; 4])).unwrap();
+        assert!(read(&bytes[..bytes.len() - 1], MAX_EXTENT).is_err());
+        assert!(read(b"VOX ", MAX_EXTENT).is_err());
+    }
+}
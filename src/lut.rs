@@ -0,0 +1,281 @@
+use half::f16;
+
+use std::path::Path;
+
+/// Largest side of the 3D table a 1D LUT is resampled into.
+const MAX_SIZE_FROM_1D: u32 = 65;
+
+/// A color grading lookup table from an Adobe/Resolve `.cube` file,
+/// applied to sRGB encoded colors after tone mapping.
+///
+/// ```text
+/// TITLE "Warm"
+/// LUT_3D_SIZE 2
+/// DOMAIN_MIN 0.0 0.0 0.0
+/// DOMAIN_MAX 1.0 1.0 1.0
+/// 0.0 0.0 0.0
+/// 1.0 0.0 0.0
+/// ...
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Lut {
+    pub(crate) title: Option<String>,
+    /// Side of the 3D table, 1D LUTs are resampled into one.
+    pub(crate) size: u32,
+    /// Red changes fastest, then green, then blue.
+    pub(crate) table: Vec<[f32; 3]>,
+    pub(crate) domain_min: [f32; 3],
+    pub(crate) domain_max: [f32; 3],
+}
+
+impl Lut {
+    /// A LUT that leaves colors as they are.
+    pub(crate) fn identity() -> Self {
+        let table = (0..8)
+            .map(|i| [i & 1, (i >> 1) & 1, (i >> 2) & 1].map(|c| c as f32))
+            .collect();
+        Self {
+            title: None,
+            size: 2,
+            table,
+            domain_min: [0.; 3],
+            domain_max: [1.; 3],
+        }
+    }
+
+    pub(crate) fn load(path: &Path) -> Result<Self, String> {
+        let source =
+            std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Self::parse(&source).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn parse(source: &str) -> Result<Self, String> {
+        let mut title = None;
+        let mut size_1d = None;
+        let mut size_3d = None;
+        let mut domain_min = [0.; 3];
+        let mut domain_max = [1.; 3];
+        let mut entries = Vec::new();
+
+        for (number, line) in source.lines().enumerate() {
+            let line_err = |e: String| format!("line {}: {}", number + 1, e);
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            match keyword {
+                "TITLE" => title = Some(rest.trim().trim_matches('"').to_string()),
+                "LUT_1D_SIZE" => size_1d = Some(parse_size(rest, 65536).map_err(line_err)?),
+                "LUT_3D_SIZE" => size_3d = Some(parse_size(rest, 256).map_err(line_err)?),
+                "DOMAIN_MIN" => domain_min = parse_floats(rest).map_err(line_err)?,
+                "DOMAIN_MAX" => domain_max = parse_floats(rest).map_err(line_err)?,
+                "LUT_1D_INPUT_RANGE" | "LUT_3D_INPUT_RANGE" => {
+                    let [min, max] = parse_floats(rest).map_err(line_err)?;
+                    domain_min = [min; 3];
+                    domain_max = [max; 3];
+                }
+                _ if keyword.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+                    // Other keywords, like Resolve's LUT_IN_VIDEO_RANGE, do not change the table.
+                }
+                _ => entries.push(parse_floats(line).map_err(line_err)?),
+            }
+        }
+
+        if (0..3).any(|i| domain_min[i] >= domain_max[i]) {
+            return Err(format!(
+                "DOMAIN_MIN {:?} must be below DOMAIN_MAX {:?}",
+                domain_min, domain_max
+            ));
+        }
+        let (size, table) = match (size_1d, size_3d) {
+            (Some(size), None) => {
+                expect_entries(&entries, size)?;
+                resample_1d(&entries)
+            }
+            (None, Some(size)) => {
+                expect_entries(&entries, size.pow(3))?;
+                (size, entries)
+            }
+            (Some(_), Some(_)) => return Err("both LUT_1D_SIZE and LUT_3D_SIZE are set".into()),
+            (None, None) => return Err("missing LUT_1D_SIZE or LUT_3D_SIZE".into()),
+        };
+        Ok(Self {
+            title,
+            size,
+            table,
+            domain_min,
+            domain_max,
+        })
+    }
+
+    /// Texels of the table as `Rgba16Float`, in the layout of a 3D texture.
+    pub(crate) fn texels(&self) -> Vec<u8> {
+        self.table
+            .iter()
+            .flat_map(|&[r, g, b]| [r, g, b, 1.])
+            .flat_map(|c| f16::from_f32(c).to_le_bytes())
+            .collect()
+    }
+}
+
+fn parse_floats<const N: usize>(s: &str) -> Result<[f32; N], String> {
+    let values: Vec<f32> = s
+        .split_whitespace()
+        .map(|v| {
+            v.parse()
+                .map_err(|_| format!("expected a number, got `{}`", v))
+        })
+        .collect::<Result<_, _>>()?;
+    values
+        .try_into()
+        .map_err(|values: Vec<f32>| format!("expected {} numbers, got {}", N, values.len()))
+}
+
+fn parse_size(s: &str, max: u32) -> Result<u32, String> {
+    match s.trim().parse::<u32>() {
+        Ok(size) if (2..=max).contains(&size) => Ok(size),
+        _ => Err(format!(
+            "expected a size between 2 and {}, got `{}`",
+            max,
+            s.trim()
+        )),
+    }
+}
+
+fn expect_entries(entries: &[[f32; 3]], count: u32) -> Result<(), String> {
+    if entries.len() != count as usize {
+        return Err(format!(
+            "expected {} table entries, got {}",
+            count,
+            entries.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Bakes per-channel curves into a 3D table, trilinear filtering of the result
+/// interpolates linearly along each channel between up to 65 of their entries.
+fn resample_1d(curve: &[[f32; 3]]) -> (u32, Vec<[f32; 3]>) {
+    let size = (curve.len() as u32).min(MAX_SIZE_FROM_1D);
+    let sample = |channel: usize, i: u32| {
+        let x = i as f32 / (size - 1) as f32 * (curve.len() - 1) as f32;
+        let lo = (x.floor() as usize).min(curve.len() - 2);
+        let t = x - lo as f32;
+        curve[lo][channel] + (curve[lo + 1][channel] - curve[lo][channel]) * t
+    };
+    let table = (0..size.pow(3))
+        .map(|i| {
+            let (r, g, b) = (i % size, i / size % size, i / (size * size));
+            [sample(0, r), sample(1, g), sample(2, b)]
+        })
+        .collect();
+    (size, table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `.cube` 3D table of `size` that maps every color to itself.
+    fn identity_source(size: u32) -> String {
+        let mut source = format!("# Identity\nTITLE \"Identity\"\nLUT_3D_SIZE {}\n", size);
+        let step = |i: u32| i as f32 / (size - 1) as f32;
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    source += &format!("{} {} {}\n", step(r), step(g), step(b));
+                }
+            }
+        }
+        source
+    }
+
+    #[test]
+    fn parses_identity_3d_lut() {
+        let lut = Lut::parse(&identity_source(2)).unwrap();
+        assert_eq!(lut.title.as_deref(), Some("Identity"));
+        assert_eq!(
+            lut,
+            Lut {
+                title: lut.title.clone(),
+                ..Lut::identity()
+            }
+        );
+
+        let lut = Lut::parse(&identity_source(5)).unwrap();
+        assert_eq!(lut.size, 5);
+        assert_eq!(lut.table[1], [0.25, 0., 0.]);
+        assert_eq!(lut.table[5], [0., 0.25, 0.]);
+        assert_eq!(lut.table[25], [0., 0., 0.25]);
+        assert_eq!(lut.texels().len(), 125 * 4 * 2);
+    }
+
+    #[test]
+    fn reads_domain_and_input_range() {
+        let source = identity_source(2).replace(
+            "LUT_3D_SIZE 2\n",
+            "LUT_3D_SIZE 2\nDOMAIN_MIN 0 0.1 0.2\nDOMAIN_MAX 1 2 3\nLUT_IN_VIDEO_RANGE\n",
+        );
+        let lut = Lut::parse(&source).unwrap();
+        assert_eq!(lut.domain_min, [0., 0.1, 0.2]);
+        assert_eq!(lut.domain_max, [1., 2., 3.]);
+
+        let source = identity_source(2).replace(
+            "LUT_3D_SIZE 2\n",
+            "LUT_3D_SIZE 2\nLUT_3D_INPUT_RANGE -0.5 1.5\n",
+        );
+        let lut = Lut::parse(&source).unwrap();
+        assert_eq!(lut.domain_min, [-0.5; 3]);
+        assert_eq!(lut.domain_max, [1.5; 3]);
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        let identity = identity_source(2);
+        let cases = [
+            identity.replace("LUT_3D_SIZE 2", "LUT_3D_SIZE 1"),
+            identity.replace("LUT_3D_SIZE 2", "LUT_3D_SIZE two"),
+            identity.replace("LUT_3D_SIZE 2\n", ""),
+            identity.replace("LUT_3D_SIZE 2\n", "LUT_3D_SIZE 2\nLUT_1D_SIZE 8\n"),
+            identity.replace("1 1 1\n", ""),
+            identity.replace("1 1 1\n", "1 1\n"),
+            identity.replace("1 1 1\n", "1 1 x\n"),
+            identity.replace("LUT_3D_SIZE 2\n", "LUT_3D_SIZE 2\nDOMAIN_MIN 1 0 0\n"),
+            identity.replace("LUT_3D_SIZE 2\n", "LUT_3D_SIZE 2\nLUT_3D_INPUT_RANGE 0\n"),
+        ];
+        for source in cases {
+            assert!(Lut::parse(&source).is_err(), "{}", source);
+        }
+        let err = Lut::parse(&identity.replace("1 1 1\n", "1 1\n")).unwrap_err();
+        assert!(err.starts_with("line 11:"), "{}", err);
+    }
+
+    #[test]
+    fn resamples_1d_lut_into_3d() {
+        // Red stays, green is squared and blue is inverted.
+        let source = "LUT_1D_SIZE 3\n0 0 1\n0.5 0.25 0.5\n1 1 0\n";
+        let lut = Lut::parse(source).unwrap();
+        assert_eq!(lut.size, 3);
+        assert_eq!(lut.table.len(), 27);
+        let at = |r: usize, g: usize, b: usize| lut.table[(b * 3 + g) * 3 + r];
+        assert_eq!(at(0, 0, 0), [0., 0., 1.]);
+        assert_eq!(at(1, 2, 0), [0.5, 1., 1.]);
+        assert_eq!(at(2, 1, 2), [1., 0.25, 0.]);
+    }
+
+    #[test]
+    fn long_1d_curves_are_interpolated_down() {
+        let entries: Vec<[f32; 3]> = (0..1024)
+            .map(|i| [i as f32 / 1023.; 3].map(|v| v * v))
+            .collect();
+        let (size, table) = resample_1d(&entries);
+        assert_eq!(size, MAX_SIZE_FROM_1D);
+        assert_eq!(table[0], [0.; 3]);
+        assert_eq!(table[table.len() - 1], [1.; 3]);
+        for r in 0..size {
+            let x = r as f32 / (size - 1) as f32;
+            assert!((table[r as usize][0] - x * x).abs() < 1e-3);
+        }
+    }
+}
@@ -46,28 +46,57 @@ clippy::suspicious,
 )]
 
 mod camera;
+mod cli;
 mod context;
+mod loaders;
+mod lut;
 mod pipelines;
+mod recorder;
+mod scene;
+mod transfer_function;
 mod utils;
+mod watcher;
 
 use crate::camera::{Camera, CameraBinding};
+use crate::cli::Args;
 use crate::context::{Context, GlobalUniformBinding, HdrBackBuffer, Uniform};
-use crate::pipelines::{raycast, xor_compute};
-use crate::utils::{dispatch_optimal, NonZeroSized};
+use crate::loaders::RawLayout;
+use crate::pipelines::{
+    normals, normals::NormalsParams, post::PostEffect, present::PresentParams, raycast,
+    raycast::EntryPoint, tiles::Tiles, xor_compute,
+};
+use crate::utils::{dispatch_optimal, dynamic_resolution::DynamicResolution, NonZeroSized};
 
 use bytemuck::{Pod, Zeroable};
+use lut::Lut;
 use pollster::FutureExt;
+use recorder::Recorder;
+use scene::Scene;
+use transfer_function::TransferFunction;
 use utils::{frame_counter::FrameCounter, input::Input};
+use watcher::Watcher;
 use winit::{
     dpi::{LogicalSize, PhysicalPosition, PhysicalSize},
     event::{DeviceEvent, ElementState, Event, KeyEvent, MouseScrollDelta, WindowEvent},
     event_loop::{ControlFlow, EventLoopBuilder},
-    keyboard::{Key, NamedKey},
+    keyboard::{Key, KeyCode, NamedKey, PhysicalKey},
     window::WindowBuilder,
 };
 
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::path::PathBuf;
+use std::time::Duration;
+
+/// Frames averaged while the view is static, after that the backbuffer is left alone.
+const MAX_SAMPLES: u32 = 256;
+
+/// Frames between the raycast timings printed to the console.
+const STATS_INTERVAL: u32 = 100;
+/// Frames between render scale adjustments of dynamic resolution.
+const RESOLUTION_INTERVAL: u32 = 10;
+
+/// Where the screenshot key drops its captures.
+const SCREENSHOT_DIR: &str = "screenshots";
 
 #[repr(C)]
 #[derive(Clone, Copy, Pod, Zeroable)]
@@ -78,40 +107,243 @@ struct TimestampData {
 
 struct Xor {
     xor_texture: xor_compute::XorCompute,
+    normals: normals::NormalsCompute,
+    /// Loaded volumes must not be overwritten by the generator.
+    volume_from_file: bool,
+    /// Frames between regenerations of the dirty box, `None` generates the volume once.
+    animation_interval: Option<u32>,
     raycast_single: raycast::RaycastPipeline,
-
-    timestamp: wgpu::QuerySet,
+    /// Present when the `tile` entry point spreads frames over several dispatches.
+    tiles: Option<Tiles>,
+    /// Frames in the accumulation buffer, reset whenever the image would change.
+    samples: u32,
+    /// JSON file the transfer function was loaded from, reloaded with F5.
+    transfer_function_path: Option<PathBuf>,
+
+    /// Timestamp queries are optional, software adapters usually lack them.
+    timestamp: Option<wgpu::QuerySet>,
     timestamp_period: f32,
+    timestamp_resolve_buffer: wgpu::Buffer,
     timestamp_buffer: wgpu::Buffer,
+    /// Whether a raycast was timed since dynamic resolution last looked at the timings.
+    timestamp_fresh: bool,
+    dynamic_resolution: Option<DynamicResolution>,
 }
 
 impl Xor {
-    fn init(ctx: &mut Context) -> Self {
-        let raycast_single = {
+    fn init(ctx: &mut Context, args: &Args, scene: &Scene) -> Result<Self, String> {
+        let max_size = ctx.device.limits().max_texture_dimension_3d;
+        let [width, height, depth] = scene.volume.size;
+        let size = args.volume_size.unwrap_or((width, height, depth));
+        let volume = args
+            .volume
+            .as_ref()
+            .or(scene.volume.file.as_ref())
+            .map(|path| {
+                let layout = RawLayout {
+                    size,
+                    voxel_type: args.voxel_type.unwrap_or(scene.volume.voxel_type),
+                    endian: args.endian.unwrap_or(scene.volume.endian),
+                };
+                loaders::load(path, &layout, max_size)
+            })
+            .transpose()?;
+        let (width, height, depth) = volume.as_ref().map_or(size, |volume| volume.size);
+        // Generated volumes fill the unit cube, loaded ones keep their physical proportions.
+        let box_extent = volume.as_ref().map_or([1.; 3], loaders::Volume::box_extent);
+        if width.max(height).max(depth) > max_size {
+            return Err(format!(
+                "Volume size {}x{}x{} exceeds the device limit of {}",
+                width, height, depth, max_size
+            ));
+        }
+
+        let transfer_function_path = transfer_function_path(args, scene).map(Path::to_path_buf);
+        let transfer_function = load_transfer_function(transfer_function_path.as_deref())?;
+        let mut entry_point = args.entry_point.unwrap_or(scene.raycast.entry_point);
+        if let Err(err) = entry_point.check_limits(&ctx.device.limits()) {
+            if entry_point == EntryPoint::Single {
+                return Err(err);
+            }
+            eprintln!("{}, falling back to the single entry point", err);
+            entry_point = EntryPoint::Single;
+        }
+        let mut raycast_single = {
             let module_desc = wgpu::include_wgsl!("../shaders/raycast_compute.wgsl");
-            pipelines::raycast::RaycastPipeline::new(&ctx.device, module_desc.clone(), "single")
+            pipelines::raycast::RaycastPipeline::new(
+                &ctx.device,
+                &ctx.queue,
+                module_desc.clone(),
+                entry_point,
+                scene.raycast.uniform(box_extent),
+                &transfer_function,
+            )
         };
+        if let Some(filter) = args.volume_filter {
+            raycast_single.set_volume_filter(&ctx.queue, filter);
+        }
+
+        let tiles = (entry_point == EntryPoint::Tile).then(|| {
+            let budget = args.tile_budget.unwrap_or(scene.raycast.tile_budget);
+            Tiles::new(
+                &ctx.device,
+                &ctx.queue,
+                ctx.render_backbuffer.size(),
+                Duration::from_secs_f32(budget / 1000.),
+            )
+        });
 
-        let xor_texture = {
+        let mut xor_texture = {
             let shader_module_desc = wgpu::include_wgsl!("../shaders/xor.wgsl");
-            pipelines::xor_compute::XorCompute::new(&ctx.device, shader_module_desc)
+            pipelines::xor_compute::XorCompute::new(
+                &ctx.device,
+                shader_module_desc,
+                (width, height, depth),
+                args.generator.unwrap_or(scene.volume.generator),
+            )
         };
+        xor_texture.set_params(&ctx.queue, scene.volume.generator_params());
+        let animation = &scene.volume.animation;
+        let (dirty_min, dirty_max) = args
+            .dirty_box
+            .unwrap_or((animation.dirty_min, animation.dirty_max));
+        xor_texture.set_dirty_box(&ctx.queue, dirty_min, dirty_max);
+        let mut animation_interval = args
+            .animate
+            .or(animation.enabled.then_some(animation.interval));
+        if animation_interval.is_some() && volume.is_some() {
+            eprintln!("Animation is disabled, the volume was loaded from a file");
+            animation_interval = None;
+        }
+        if let Some(volume) = &volume {
+            xor_texture.upload(&ctx.queue, volume);
+        }
+        let mut normals = normals::NormalsCompute::new(
+            &ctx.device,
+            wgpu::include_wgsl!("../shaders/normals.wgsl"),
+            &xor_texture,
+        );
+        let normals_params = NormalsParams {
+            kernel: args.normals.unwrap_or(scene.normals.kernel),
+            size: args.normals_size.unwrap_or(scene.normals.size),
+        };
+        normals.set_params(&ctx.queue, normals_params);
+        let (dirty_min, dirty_max) = xor_texture.dirty_region();
+        normals.set_dirty_region(&ctx.queue, dirty_min, dirty_max);
+        let lut_paths = if args.luts.is_empty() {
+            &scene.grading.luts
+        } else {
+            &args.luts
+        };
+        for path in lut_paths {
+            let lut = Lut::load(path)?;
+            let name = lut.title.clone().unwrap_or(path.display().to_string());
+            ctx.present_pipeline
+                .add_lut(&ctx.device, &ctx.queue, &name, &lut);
+        }
+        let present_params = PresentParams {
+            tonemap: args.tonemap.unwrap_or(scene.tonemap.operator),
+            exposure: args.exposure.unwrap_or(scene.tonemap.exposure),
+            white_point: args.white_point.unwrap_or(scene.tonemap.white_point),
+            upscaling: args.upscale.unwrap_or(scene.present.upscale),
+            auto_exposure: args.auto_exposure || scene.auto_exposure.enabled,
+            lut: usize::from(!lut_paths.is_empty()),
+            lut_strength: args.lut_strength.unwrap_or(scene.grading.strength),
+        };
+        ctx.present_pipeline.set_params(&ctx.queue, present_params);
+        ctx.auto_exposure
+            .set_params(&ctx.queue, scene.auto_exposure.params());
+        let post_chain = args.post.clone().unwrap_or(scene.post.chain.clone());
+        if post_chain.contains(&PostEffect::Lut) {
+            let path = scene
+                .post
+                .lut
+                .file
+                .as_ref()
+                .ok_or("The lut post effect needs a post.lut.file in the scene")?;
+            let lut = Lut::load(path)?;
+            ctx.post_chain.set_lut(&ctx.device, &ctx.queue, &lut);
+        }
+        ctx.post_chain
+            .set_chain(&ctx.device, &ctx.queue, post_chain);
+        ctx.post_chain.set_params(&ctx.queue, scene.post.params());
 
-        let timestamp = ctx.device.create_query_set(&wgpu::QuerySetDescriptor {
-            label: None,
-            count: 2,
-            ty: wgpu::QueryType::Timestamp,
-        });
+        let timestamp = ctx
+            .device
+            .features()
+            .contains(wgpu::Features::TIMESTAMP_QUERY)
+            .then(|| {
+                ctx.device.create_query_set(&wgpu::QuerySetDescriptor {
+                    label: None,
+                    count: 2,
+                    ty: wgpu::QueryType::Timestamp,
+                })
+            });
         let timestamp_period = ctx.queue.get_timestamp_period();
+        let timestamp_size = std::mem::size_of::<TimestampData>() as _;
+        // Queries are resolved into a separate buffer, mapping it directly
+        // needs `MAPPABLE_PRIMARY_BUFFERS` which not every adapter has.
+        let timestamp_resolve_buffer = ctx.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Query Resolve Buffer"),
+            size: timestamp_size,
+            usage: wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::QUERY_RESOLVE,
+            mapped_at_creation: false,
+        });
         let timestamp_buffer = ctx.device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Query Buffer"),
-            size: std::mem::size_of::<TimestampData>() as _,
-            usage: wgpu::BufferUsages::COPY_DST
-                | wgpu::BufferUsages::MAP_READ
-                | wgpu::BufferUsages::QUERY_RESOLVE,
+            size: timestamp_size,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
             mapped_at_creation: false,
         });
 
+        let dynamic_resolution = args.target_frame_time.and_then(|target| {
+            if entry_point == EntryPoint::Tile {
+                eprintln!(
+                    "Dynamic resolution is disabled, the tile entry point has its own budget"
+                );
+                return None;
+            }
+            if timestamp.is_none() {
+                eprintln!("Dynamic resolution is disabled, the adapter lacks timestamp queries");
+                return None;
+            }
+            Some(DynamicResolution::new(
+                Duration::from_secs_f32(target / 1000.),
+                args.min_render_scale,
+                args.max_render_scale,
+            ))
+        });
+
+        let mut xor = Self {
+            xor_texture,
+            normals,
+            volume_from_file: volume.is_some(),
+            animation_interval,
+            raycast_single,
+            tiles,
+            samples: 0,
+            transfer_function_path,
+
+            timestamp,
+            timestamp_period,
+            timestamp_resolve_buffer,
+            timestamp_buffer,
+            timestamp_fresh: false,
+            dynamic_resolution,
+        };
+        if xor.volume_from_file {
+            xor.compute_normals(ctx);
+        } else {
+            xor.generate_volume(ctx);
+        }
+        Ok(xor)
+    }
+
+    fn generate_volume(&mut self, ctx: &Context) {
+        if self.volume_from_file {
+            return;
+        }
+        self.reset_accumulation();
         let mut encoder = ctx
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
@@ -122,50 +354,210 @@ impl Xor {
             label: Some("XOR Update Pass"),
             ..Default::default()
         });
-        xor_texture.record(&mut cpass, &ctx.global_uniform_binding);
+        self.xor_texture
+            .record(&mut cpass, &ctx.global_uniform_binding);
+        drop(cpass);
+
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Normals Pass"),
+            ..Default::default()
+        });
+        self.normals.record(&mut cpass);
+        drop(cpass);
+        ctx.queue.submit(Some(encoder.finish()));
+    }
+
+    fn compute_normals(&mut self, ctx: &Context) {
+        self.reset_accumulation();
+        let mut encoder = ctx
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Normals Encoder"),
+            });
+
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Normals Pass"),
+            ..Default::default()
+        });
+        self.normals.record(&mut cpass);
         drop(cpass);
         ctx.queue.submit(Some(encoder.finish()));
+    }
 
-        Self {
-            xor_texture,
-            raycast_single,
+    /// Rebuilds the pipeline that uses the shader at `path` with the freshly compiled `module`.
+    fn reload_shader(&mut self, ctx: &mut Context, path: &Path, module: wgpu::ShaderModule) {
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            return;
+        };
+        match name {
+            "raycast_compute.wgsl" => {
+                self.raycast_single.rebuild(&ctx.device, module);
+                self.reset_accumulation();
+            }
+            "xor.wgsl" => {
+                self.xor_texture.rebuild(&ctx.device, module);
+                self.generate_volume(ctx);
+            }
+            "normals.wgsl" => {
+                self.normals.rebuild(&ctx.device, module);
+                self.compute_normals(ctx);
+            }
+            "present.wgsl" => ctx.present_pipeline.rebuild(&ctx.device, module),
+            "auto_exposure.wgsl" => ctx.auto_exposure.rebuild(&ctx.device, module),
+            "post.wgsl" => ctx.post_chain.rebuild(&ctx.device, module),
+            "bloom.wgsl" => ctx.post_chain.bloom.rebuild(&ctx.device, module),
+            _ => return,
+        }
+        eprintln!("Reloaded {}", path.display());
+    }
 
-            timestamp,
-            timestamp_period,
-            timestamp_buffer,
+    fn next_generator(&mut self, ctx: &Context) {
+        if self.volume_from_file {
+            eprintln!("The volume was loaded from a file, there is no generator to switch");
+            return;
+        }
+        let generator = self.xor_texture.generator().next();
+        self.xor_texture.set_generator(generator);
+        self.generate_volume(ctx);
+        eprintln!("Generator {:?}", generator);
+    }
+
+    fn next_normals_kernel(&mut self, ctx: &Context) {
+        let params = self.normals.params().next();
+        self.normals.set_params(&ctx.queue, params);
+        self.compute_normals(ctx);
+        eprintln!("Normals {:?} kernel of size {}", params.kernel, params.size);
+    }
+
+    fn next_volume_filter(&mut self, ctx: &Context) {
+        let filter = self.raycast_single.volume_filter().next();
+        self.raycast_single.set_volume_filter(&ctx.queue, filter);
+        self.reset_accumulation();
+        eprintln!("Volume filter {:?}", filter);
+    }
+
+    fn reload_transfer_function(&mut self, ctx: &Context) {
+        let Some(path) = &self.transfer_function_path else {
+            eprintln!("No transfer function file to reload");
+            return;
+        };
+        match TransferFunction::load(path) {
+            Ok(tf) => {
+                self.raycast_single.set_transfer_function(&ctx.queue, &tf);
+                eprintln!("Reloaded {}", path.display());
+                self.reset_accumulation();
+            }
+            Err(err) => eprintln!("Failed to reload transfer function: {}", err),
+        }
+    }
+
+    /// Starts averaging frames from scratch.
+    fn reset_accumulation(&mut self) {
+        self.samples = 0;
+        if let Some(tiles) = &mut self.tiles {
+            tiles.restart();
         }
     }
 
     fn update(&mut self, ctx: &mut Context) {
-        if ctx.global_uniform.frame % 100 == 0 {
-            let _ = self
-                .timestamp_buffer
-                .slice(..)
-                .map_async(wgpu::MapMode::Read, |_| ());
-            {
-                ctx.device.poll(wgpu::Maintain::Wait);
-                let timestamp_view = self
-                    .timestamp_buffer
-                    .slice(..std::mem::size_of::<TimestampData>() as wgpu::BufferAddress)
-                    .get_mapped_range();
-                let timestamp_data: &TimestampData = bytemuck::from_bytes(&*timestamp_view);
-                let nanoseconds =
-                    (timestamp_data.end - timestamp_data.start) as f32 * self.timestamp_period;
-                let time_period = std::time::Duration::from_nanos(nanoseconds as _);
-                eprintln!("Time on raycast shader: {:?} (single pass)", time_period);
+        let frame = ctx.global_uniform.frame;
+        let print_stats = frame.is_multiple_of(STATS_INTERVAL);
+        let adjust_resolution = self.dynamic_resolution.is_some()
+            && self.timestamp_fresh
+            && frame.is_multiple_of(RESOLUTION_INTERVAL);
+        if !(print_stats || adjust_resolution) {
+            return;
+        }
+        let Some(gpu_time) = self.read_timestamps(ctx) else {
+            return;
+        };
+
+        if let Some(dynamic_resolution) = self.dynamic_resolution.filter(|_| adjust_resolution) {
+            self.timestamp_fresh = false;
+            let scale = ctx.render_scale();
+            let next_scale = dynamic_resolution.next_scale(scale, gpu_time);
+            if next_scale != scale {
+                ctx.set_render_scale(next_scale);
+            }
+            if ctx.render_scale() != scale {
+                self.reset_accumulation();
             }
-            self.timestamp_buffer.unmap();
         }
+        if print_stats {
+            let pass = match self.raycast_single.entry_point {
+                EntryPoint::Single => "single pass",
+                EntryPoint::Tile => "one batch of tiles",
+            };
+            eprintln!(
+                "Time on raycast shader: {:?} ({}), render scale {:.2}",
+                gpu_time,
+                pass,
+                ctx.render_scale()
+            );
+        }
+    }
+
+    /// GPU time of the last timed raycast, `None` without timestamp queries.
+    fn read_timestamps(&self, ctx: &Context) -> Option<Duration> {
+        self.timestamp.as_ref()?;
+        let _ = self
+            .timestamp_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, |_| ());
+        ctx.device.poll(wgpu::Maintain::Wait);
+        let nanoseconds = {
+            let timestamp_view = self
+                .timestamp_buffer
+                .slice(..std::mem::size_of::<TimestampData>() as wgpu::BufferAddress)
+                .get_mapped_range();
+            let timestamp_data: &TimestampData = bytemuck::from_bytes(&*timestamp_view);
+            (timestamp_data.end - timestamp_data.start) as f32 * self.timestamp_period
+        };
+        self.timestamp_buffer.unmap();
+        Some(Duration::from_nanos(nanoseconds as _))
     }
 
-    fn render(&mut self, ctx: &Context) {
+    /// Raycasts into the backbuffer. The `tile` entry point only draws what fits
+    /// into its budget unless `whole_frame` is set.
+    fn render(&mut self, ctx: &Context, whole_frame: bool) {
+        let regenerate = self
+            .animation_interval
+            .is_some_and(|interval| ctx.global_uniform.frame.is_multiple_of(interval));
+        if ctx.camera_changed || regenerate {
+            self.reset_accumulation();
+        }
+        if self.samples >= MAX_SAMPLES {
+            return;
+        }
+        self.raycast_single
+            .set_sample_index(&ctx.queue, self.samples);
+
         let mut encoder = ctx
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                 label: Some("Volume Encoder"),
             });
 
-        encoder.write_timestamp(&self.timestamp, 0);
+        // A pass of its own, so the raycast only starts once the voxels are written.
+        if regenerate {
+            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("XOR Update Pass"),
+                ..Default::default()
+            });
+            self.xor_texture
+                .record_dirty(&mut cpass, &ctx.global_uniform_binding);
+            drop(cpass);
+
+            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Normals Pass"),
+                ..Default::default()
+            });
+            self.normals.record_dirty(&mut cpass);
+        }
+
+        if let Some(timestamp) = &self.timestamp {
+            encoder.write_timestamp(timestamp, 0);
+        }
 
         let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
             label: Some("Raycast Pass"),
@@ -176,41 +568,191 @@ impl Xor {
 
         cpass.set_bind_group(0, &ctx.global_uniform_binding.binding, &[]);
         cpass.set_bind_group(1, &ctx.camera_binding.bind_group, &[]);
-        cpass.set_bind_group(2, &self.xor_texture.storage_bind_group, &[]);
+        cpass.set_bind_group(2, &self.xor_texture.render_bind_group, &[]);
         cpass.set_bind_group(3, &ctx.render_backbuffer.storage_bind_group, &[]);
-        let (width, height) = HdrBackBuffer::DEFAULT_RESOLUTION;
-        cpass.dispatch_workgroups(dispatch_optimal(width, 8), dispatch_optimal(height, 8), 1);
+        cpass.set_bind_group(4, &self.raycast_single.params_bind_group, &[]);
+        let batch = match &mut self.tiles {
+            Some(tiles) => {
+                tiles.resize(&ctx.device, &ctx.queue, ctx.render_backbuffer.size());
+                let batch = tiles.next_batch(whole_frame);
+                let workgroups = tiles.workgroups();
+                for tile in batch.clone() {
+                    cpass.set_bind_group(5, &tiles.bind_group, &[tiles.offset(tile)]);
+                    cpass.dispatch_workgroups(workgroups, workgroups, 1);
+                }
+                Some(batch)
+            }
+            None => {
+                let (width, height) = ctx.render_backbuffer.size();
+                cpass.dispatch_workgroups(
+                    dispatch_optimal(width, 8),
+                    dispatch_optimal(height, 8),
+                    1,
+                );
+                None
+            }
+        };
         drop(cpass);
 
-        encoder.write_timestamp(&self.timestamp, 1);
-        encoder.resolve_query_set(&self.timestamp, 0..2, &self.timestamp_buffer, 0);
+        if let Some(timestamp) = &self.timestamp {
+            encoder.write_timestamp(timestamp, 1);
+            encoder.resolve_query_set(timestamp, 0..2, &self.timestamp_resolve_buffer, 0);
+            encoder.copy_buffer_to_buffer(
+                &self.timestamp_resolve_buffer,
+                0,
+                &self.timestamp_buffer,
+                0,
+                self.timestamp_buffer.size(),
+            );
+            self.timestamp_fresh = true;
+        }
 
         ctx.queue.submit(Some(encoder.finish()));
+        match (&self.tiles, batch) {
+            (Some(tiles), Some(batch)) => {
+                if !whole_frame {
+                    tiles.submitted(&ctx.queue, batch);
+                }
+                if tiles.pass_finished() {
+                    self.samples += 1;
+                }
+            }
+            _ => self.samples += 1,
+        }
     }
 }
 
+/// Changes a present pass setting if `key` is bound to one, returns whether it was.
+fn adjust_present_params(context: &mut Context, key: KeyCode) -> bool {
+    let mut params = context.present_pipeline.params();
+    match key {
+        KeyCode::KeyU => params.upscaling = params.upscaling.next(),
+        KeyCode::KeyT => params.tonemap = params.tonemap.next(),
+        KeyCode::Minus => params.exposure -= 0.25,
+        KeyCode::Equal => params.exposure += 0.25,
+        KeyCode::KeyL => params.lut = (params.lut + 1) % context.present_pipeline.lut_count(),
+        KeyCode::BracketLeft => params.lut_strength = (params.lut_strength - 0.1).max(0.),
+        KeyCode::BracketRight => params.lut_strength = (params.lut_strength + 0.1).min(1.),
+        KeyCode::KeyE => {
+            params.auto_exposure = !params.auto_exposure;
+            context.auto_exposure.reset(&context.queue);
+        }
+        _ => return false,
+    }
+    eprintln!(
+        "Tone mapping {:?}, exposure {:+.2} EV{}, white point {}, upscaling {:?}, LUT {} at {:.1}",
+        params.tonemap,
+        params.exposure,
+        if params.auto_exposure { " + auto" } else { "" },
+        params.white_point,
+        params.upscaling,
+        context.present_pipeline.lut_name(params.lut),
+        params.lut_strength
+    );
+    context.present_pipeline.set_params(&context.queue, params);
+    true
+}
+
+/// The command line wins over the scene.
+fn transfer_function_path<'a>(args: &'a Args, scene: &'a Scene) -> Option<&'a Path> {
+    args.transfer_function
+        .as_deref()
+        .or(scene.raycast.transfer_function.as_deref())
+}
+
+fn load_transfer_function(path: Option<&Path>) -> Result<TransferFunction, String> {
+    path.map_or(Ok(TransferFunction::default()), TransferFunction::load)
+}
+
+/// Renders without a window, either a single frame to `output` or a whole recording.
+fn render_headless(output: &Path, args: &Args, scene: &Scene) -> Result<(), String> {
+    let (width, height) = args.window_size;
+    let camera = scene.camera.build(width as f32 / height as f32);
+
+    let mut context = Context::new_headless(camera, args).block_on()?;
+    let mut xor = Xor::init(&mut context, args, scene)?;
+    let frame_counter = FrameCounter::new();
+    let input = Input::new();
+
+    if let Some(recording) = args.recording.clone() {
+        let mut recorder = Recorder::new(&mut context, recording)?;
+        while !recorder.is_finished() {
+            context.update(&frame_counter, &input);
+            xor.render(&context, true);
+            context.render_offscreen();
+            recorder.record(&context)?;
+        }
+        return Ok(());
+    }
+
+    for _ in 0..args.samples {
+        context.update(&frame_counter, &input);
+        xor.render(&context, true);
+    }
+    context.render_offscreen();
+
+    let frame = context.capture_rgb()?;
+    if frame.is_black() {
+        eprintln!(
+            "Warning: the frame saved to {} is completely black",
+            output.display()
+        );
+    }
+    frame.save_png(output)?;
+    eprintln!("Saved frame to {}", output.display());
+    Ok(())
+}
+
+/// Generates or loads the volume without a window and writes it as `.vox`.
+fn export_vox(output: &Path, args: &Args, scene: &Scene) -> Result<(), String> {
+    let (width, height) = args.window_size;
+    let camera = scene.camera.build(width as f32 / height as f32);
+
+    let mut context = Context::new_headless(camera, args).block_on()?;
+    let xor = Xor::init(&mut context, args, scene)?;
+    let volume = xor.xor_texture.download(&context.device, &context.queue)?;
+    loaders::vox::save(output, &volume)
+}
+
 fn main() -> Result<(), String> {
+    let args = Args::from_env()?;
+    let scene = match &args.scene {
+        Some(path) => Scene::load(path)?,
+        None => Scene::default(),
+    };
+    if let Some(path) = &args.save_transfer_function {
+        load_transfer_function(transfer_function_path(&args, &scene))?.save(path)?;
+        eprintln!("Saved transfer function to {}", path.display());
+        return Ok(());
+    }
+    if let Some(path) = &args.export_vox {
+        env_logger::init();
+        return export_vox(path, &args, &scene);
+    }
+    if let Some(path) = &args.headless {
+        env_logger::init();
+        return render_headless(path, &args, &scene);
+    }
+
     let event_loop = EventLoopBuilder::<(PathBuf, wgpu::ShaderModule)>::with_user_event()
         .build()
         .map_err(|e| e.to_string())?;
-    let window = Arc::new(WindowBuilder::new()
-        .with_title("Vokselis")
-        .with_inner_size(LogicalSize::new(1280, 720))
-        .build(&event_loop)
-        .map_err(|e| e.to_string())?);
+    let window = Arc::new(
+        WindowBuilder::new()
+            .with_title("Vokselis")
+            .with_inner_size(LogicalSize::new(args.window_size.0, args.window_size.1))
+            .build(&event_loop)
+            .map_err(|e| e.to_string())?,
+    );
     let window_size = window.inner_size();
 
-    let camera = Camera::new(
-        3.,
-        -0.5,
-        1.,
-        (0., 0., 0.).into(),
-        window_size.width as f32 / window_size.height as f32,
-    );
+    let camera = scene
+        .camera
+        .build(window_size.width as f32 / window_size.height as f32);
 
     env_logger::init();
 
-    let mut context = Context::new(window.clone(), camera).block_on()?;
+    let mut context = Context::new(window.clone(), camera, &args).block_on()?;
 
     let mut frame_counter = FrameCounter::new();
     let mut input = Input::new();
@@ -219,7 +761,32 @@ fn main() -> Result<(), String> {
     let rotate_speed = 0.0025;
     let zoom_speed = 0.002;
 
-    let mut xor = Xor::init(&mut context);
+    let mut xor = Xor::init(&mut context, &args, &scene)?;
+    let mut recorder = args
+        .recording
+        .map(|recording| Recorder::new(&mut context, recording))
+        .transpose()?;
+
+    // Shaders are embedded in the binary, hot reload is only a convenience.
+    let shader_dir = args
+        .shader_dir
+        .as_deref()
+        .unwrap_or(Path::new(watcher::SHADER_DIR));
+    let _watcher = match Watcher::new(
+        context.device.clone(),
+        event_loop.create_proxy(),
+        shader_dir,
+    ) {
+        Ok(watcher) => Some(watcher),
+        Err(err) => {
+            eprintln!(
+                "Shader hot reload disabled, failed to watch {}: {}",
+                shader_dir.display(),
+                err
+            );
+            None
+        }
+    };
 
     let mut main_window_focused = false;
     event_loop
@@ -253,13 +820,104 @@ fn main() -> Result<(), String> {
                             ..
                         } => target.exit(),
 
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    physical_key: PhysicalKey::Code(KeyCode::F12),
+                                    state: ElementState::Pressed,
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } => {
+                            if let Err(err) = context.save_screenshot(SCREENSHOT_DIR) {
+                                eprintln!("Failed to save screenshot: {}", err);
+                            }
+                        }
+
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    physical_key: PhysicalKey::Code(KeyCode::F5),
+                                    state: ElementState::Pressed,
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } => {
+                            xor.reload_transfer_function(&context);
+                            window.request_redraw();
+                        }
+
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    physical_key: PhysicalKey::Code(KeyCode::KeyG),
+                                    state: ElementState::Pressed,
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } => {
+                            xor.next_generator(&context);
+                            window.request_redraw();
+                        }
+
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    physical_key: PhysicalKey::Code(KeyCode::KeyN),
+                                    state: ElementState::Pressed,
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } => {
+                            xor.next_normals_kernel(&context);
+                            window.request_redraw();
+                        }
+
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    physical_key: PhysicalKey::Code(KeyCode::KeyF),
+                                    state: ElementState::Pressed,
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } => {
+                            xor.next_volume_filter(&context);
+                            window.request_redraw();
+                        }
+
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    physical_key: PhysicalKey::Code(key),
+                                    state: ElementState::Pressed,
+                                    ..
+                                },
+                            ..
+                        } if adjust_present_params(&mut context, key) => {}
+
                         WindowEvent::RedrawRequested => {
                             frame_counter.record();
 
-                            xor.render(&context);
+                            // Recorded frames have to be complete.
+                            xor.render(&context, recorder.is_some());
 
                             match context.render() {
-                                Ok(_) => {}
+                                Ok(_) => {
+                                    if let Some(recorder) = &mut recorder {
+                                        if let Err(err) = recorder.record(&context) {
+                                            eprintln!("Failed to record frame: {}", err);
+                                            target.exit();
+                                        } else if recorder.is_finished() {
+                                            target.exit();
+                                        }
+                                    }
+                                }
                                 Err(wgpu::SurfaceError::Lost) => {
                                     context.resize(context.width, context.height);
                                     window.request_redraw();
@@ -273,8 +931,8 @@ fn main() -> Result<(), String> {
                         }
 
                         WindowEvent::Resized(PhysicalSize { width, height }) => {
-                            if width != 0 && height != 0 {
-                                context.resize(width, height);
+                            if width != 0 && height != 0 && context.resize(width, height) {
+                                xor.reset_accumulation();
                             }
                         }
 
@@ -282,6 +940,11 @@ fn main() -> Result<(), String> {
                     }
                 }
 
+                Event::UserEvent((path, module)) => {
+                    xor.reload_shader(&mut context, &path, module);
+                    window.request_redraw();
+                }
+
                 Event::DeviceEvent { ref event, .. } if main_window_focused => match event {
                     DeviceEvent::Button {
                         button: 1,
@@ -0,0 +1,258 @@
+use crate::context::HdrBackBuffer;
+use crate::utils::{dispatch_optimal, validate};
+use crate::{GlobalUniformBinding, NonZeroSized, Uniform};
+
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
+
+/// Bins of the luminance histogram, mirrors `BINS` in `auto_exposure.wgsl`.
+const BINS: u64 = 256;
+
+/// How the exposure follows the brightness of the frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct AutoExposureParams {
+    /// Darkest average luminance in EV that is still compensated.
+    pub(crate) min_ev: f32,
+    /// Brightest average luminance in EV that is still compensated.
+    pub(crate) max_ev: f32,
+    /// Adaptation rate per second, higher reacts faster.
+    pub(crate) speed: f32,
+}
+
+impl Default for AutoExposureParams {
+    fn default() -> Self {
+        Self {
+            min_ev: -8.,
+            max_ev: 4.,
+            speed: 3.,
+        }
+    }
+}
+
+/// Mirrors `Params` in `auto_exposure.wgsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct AutoExposureUniform {
+    min_ev: f32,
+    max_ev: f32,
+    speed: f32,
+    _padding: f32,
+}
+
+impl From<AutoExposureParams> for AutoExposureUniform {
+    fn from(params: AutoExposureParams) -> Self {
+        Self {
+            min_ev: params.min_ev,
+            max_ev: params.max_ev,
+            speed: params.speed,
+            _padding: 0.,
+        }
+    }
+}
+
+/// Mirrors `Exposure` in `auto_exposure.wgsl` and `present.wgsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct Exposure {
+    ev: f32,
+    initialized: u32,
+}
+
+/// Measures a log-luminance histogram of the backbuffer and smooths
+/// the exposure that brings its average to middle grey over time.
+pub(crate) struct AutoExposure {
+    histogram_pipeline: wgpu::ComputePipeline,
+    average_pipeline: wgpu::ComputePipeline,
+    layout: wgpu::PipelineLayout,
+    bind_group: wgpu::BindGroup,
+    params_buffer: wgpu::Buffer,
+    exposure_buffer: wgpu::Buffer,
+    /// Copy of the exposure the present pass reads as a uniform.
+    pub(crate) exposure_uniform: wgpu::Buffer,
+}
+
+impl AutoExposure {
+    pub(crate) const DESC: wgpu::BindGroupLayoutDescriptor<'static> =
+        wgpu::BindGroupLayoutDescriptor {
+            label: Some("Auto Exposure Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(AutoExposureUniform::SIZE),
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(Exposure::SIZE),
+                    },
+                    count: None,
+                },
+            ],
+        };
+
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        module_desc: wgpu::ShaderModuleDescriptor<'_>,
+    ) -> Self {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Auto Exposure Pipeline Layout"),
+            bind_group_layouts: &[
+                &device.create_bind_group_layout(&Uniform::DESC),
+                &device.create_bind_group_layout(&HdrBackBuffer::DESC_RENDER),
+                &device.create_bind_group_layout(&Self::DESC),
+            ],
+            push_constant_ranges: &[],
+        });
+        let module = device.create_shader_module(module_desc);
+        let (histogram_pipeline, average_pipeline) = Self::make_pipelines(device, &layout, &module);
+
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Auto Exposure Params Buffer"),
+            contents: bytemuck::bytes_of(&AutoExposureUniform::from(AutoExposureParams::default())),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let histogram_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Auto Exposure Histogram Buffer"),
+            contents: &[0; BINS as usize * 4],
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let exposure_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Auto Exposure Buffer"),
+            size: Exposure::SIZE.get(),
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let exposure_uniform = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Auto Exposure Uniform Buffer"),
+            size: Exposure::SIZE.get(),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Auto Exposure Bind Group"),
+            layout: &device.create_bind_group_layout(&Self::DESC),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: histogram_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: exposure_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        Self {
+            histogram_pipeline,
+            average_pipeline,
+            layout,
+            bind_group,
+            params_buffer,
+            exposure_buffer,
+            exposure_uniform,
+        }
+    }
+
+    pub(crate) fn set_params(&self, queue: &wgpu::Queue, params: AutoExposureParams) {
+        let uniform = AutoExposureUniform::from(params);
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&uniform));
+    }
+
+    /// Jumps straight to the exposure of the next frame instead of adapting to it.
+    pub(crate) fn reset(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.exposure_buffer,
+            0,
+            bytemuck::bytes_of(&Exposure::zeroed()),
+        );
+    }
+
+    /// Swaps in pipelines built from `module`, keeping the old ones if they fail to validate.
+    pub(crate) fn rebuild(&mut self, device: &wgpu::Device, module: wgpu::ShaderModule) {
+        match validate(device, || {
+            Self::make_pipelines(device, &self.layout, &module)
+        }) {
+            Ok((histogram, average)) => {
+                self.histogram_pipeline = histogram;
+                self.average_pipeline = average;
+            }
+            Err(err) => eprintln!("Failed to rebuild auto exposure pipelines: {}", err),
+        }
+    }
+
+    fn make_pipelines(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        module: &wgpu::ShaderModule,
+    ) -> (wgpu::ComputePipeline, wgpu::ComputePipeline) {
+        let make = |label, entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(layout),
+                module,
+                entry_point,
+            })
+        };
+        (
+            make("Auto Exposure Histogram", "histogram_main"),
+            make("Auto Exposure Average", "average_main"),
+        )
+    }
+
+    /// Measures `backbuffer` and updates the exposure uniform of the present pass.
+    pub(crate) fn record(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        uniform_bind_group: &GlobalUniformBinding,
+        backbuffer: &HdrBackBuffer,
+    ) {
+        let (width, height) = backbuffer.size();
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Auto Exposure Pass"),
+            timestamp_writes: None,
+        });
+        cpass.set_bind_group(0, &uniform_bind_group.binding, &[]);
+        cpass.set_bind_group(1, &backbuffer.render_bind_group, &[]);
+        cpass.set_bind_group(2, &self.bind_group, &[]);
+        cpass.set_pipeline(&self.histogram_pipeline);
+        cpass.dispatch_workgroups(dispatch_optimal(width, 16), dispatch_optimal(height, 16), 1);
+        cpass.set_pipeline(&self.average_pipeline);
+        cpass.dispatch_workgroups(1, 1, 1);
+        drop(cpass);
+
+        encoder.copy_buffer_to_buffer(
+            &self.exposure_buffer,
+            0,
+            &self.exposure_uniform,
+            0,
+            Exposure::SIZE.get(),
+        );
+    }
+}
@@ -0,0 +1,351 @@
+use crate::context::HdrBackBuffer;
+use crate::utils::validate;
+use crate::NonZeroSized;
+
+use bytemuck::{Pod, Zeroable};
+use serde::Deserialize;
+use wgpu::util::DeviceExt;
+
+/// Most mips in the chain, each one halves the size of the previous one.
+const MAX_MIPS: u32 = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct BloomParams {
+    /// Brightness above which pixels start to glow.
+    pub(crate) threshold: f32,
+    /// Width of the soft transition around the threshold.
+    pub(crate) knee: f32,
+    /// Amount of glow added to the image.
+    pub(crate) intensity: f32,
+    /// Spread of the upsampling filter in texels of each mip.
+    pub(crate) radius: f32,
+}
+
+impl Default for BloomParams {
+    fn default() -> Self {
+        Self {
+            threshold: 1.,
+            knee: 0.5,
+            intensity: 0.5,
+            radius: 1.,
+        }
+    }
+}
+
+/// Mirrors `Params` in `bloom.wgsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct BloomUniform {
+    threshold: f32,
+    knee: f32,
+    intensity: f32,
+    radius: f32,
+    mip_count: u32,
+    _padding: [u32; 3],
+}
+
+/// One level of the mip chain.
+struct Mip {
+    view: wgpu::TextureView,
+    bind_group: wgpu::BindGroup,
+}
+
+/// Glow around bright parts of the HDR image, from a chain of downsampled
+/// mips that get upsampled and summed back up before compositing.
+pub(crate) struct Bloom {
+    downsample_first: wgpu::RenderPipeline,
+    downsample: wgpu::RenderPipeline,
+    upsample: wgpu::RenderPipeline,
+    composite: wgpu::RenderPipeline,
+    layout: wgpu::PipelineLayout,
+    composite_layout: wgpu::PipelineLayout,
+    params: BloomParams,
+    params_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    /// Half the size of the input and smaller, empty until [`Bloom::resize`].
+    mips: Vec<Mip>,
+}
+
+impl Bloom {
+    pub(crate) const DESC: wgpu::BindGroupLayoutDescriptor<'static> =
+        wgpu::BindGroupLayoutDescriptor {
+            label: Some("Bloom Params Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(BloomUniform::SIZE),
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        };
+
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        module_desc: wgpu::ShaderModuleDescriptor<'_>,
+    ) -> Self {
+        let texture_layout = device.create_bind_group_layout(&HdrBackBuffer::DESC_RENDER);
+        let params_layout = device.create_bind_group_layout(&Self::DESC);
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Bloom Pipeline Layout"),
+            bind_group_layouts: &[&texture_layout, &params_layout],
+            push_constant_ranges: &[],
+        });
+        let composite_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Bloom Composite Pipeline Layout"),
+            bind_group_layouts: &[&texture_layout, &params_layout, &texture_layout],
+            push_constant_ranges: &[],
+        });
+        let module = device.create_shader_module(module_desc);
+        let [downsample_first, downsample, upsample, composite] =
+            Self::make_pipelines(device, &layout, &composite_layout, &module);
+
+        let params = BloomParams::default();
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Bloom Params Buffer"),
+            contents: bytemuck::bytes_of(&uniform(params, 0)),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Bloom Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Bloom Params Bind Group"),
+            layout: &params_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+
+        Self {
+            downsample_first,
+            downsample,
+            upsample,
+            composite,
+            layout,
+            composite_layout,
+            params,
+            params_buffer,
+            bind_group,
+            mips: Vec::new(),
+        }
+    }
+
+    pub(crate) fn set_params(&mut self, queue: &wgpu::Queue, params: BloomParams) {
+        self.params = params;
+        self.write_params(queue);
+    }
+
+    fn write_params(&self, queue: &wgpu::Queue) {
+        let uniform = uniform(self.params, self.mips.len() as u32);
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&uniform));
+    }
+
+    pub(crate) fn is_allocated(&self) -> bool {
+        !self.mips.is_empty()
+    }
+
+    /// Recreates the mip chain for an input of `size`, or frees it for `None`.
+    pub(crate) fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        size: Option<(u32, u32)>,
+    ) {
+        let Some((width, height)) = size else {
+            self.mips.clear();
+            return;
+        };
+        // Stops before the smallest mip gets thinner than 2 pixels.
+        let count = (width.min(height).max(4).ilog2() - 1).min(MAX_MIPS);
+        self.mips = (1..=count)
+            .map(|level| {
+                let texture = device.create_texture(&wgpu::TextureDescriptor {
+                    label: Some("Bloom Mip"),
+                    size: wgpu::Extent3d {
+                        width: (width >> level).max(1),
+                        height: (height >> level).max(1),
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: HdrBackBuffer::FORMAT,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                        | wgpu::TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[],
+                });
+                let view = texture.create_view(&Default::default());
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("Bloom Mip Bind Group"),
+                    layout: &device.create_bind_group_layout(&HdrBackBuffer::DESC_RENDER),
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&view),
+                    }],
+                });
+                Mip { view, bind_group }
+            })
+            .collect();
+        self.write_params(queue);
+    }
+
+    /// Swaps in pipelines built from `module`, keeping the old ones if they fail to validate.
+    pub(crate) fn rebuild(&mut self, device: &wgpu::Device, module: wgpu::ShaderModule) {
+        match validate(device, || {
+            Self::make_pipelines(device, &self.layout, &self.composite_layout, &module)
+        }) {
+            Ok([downsample_first, downsample, upsample, composite]) => {
+                self.downsample_first = downsample_first;
+                self.downsample = downsample;
+                self.upsample = upsample;
+                self.composite = composite;
+            }
+            Err(err) => eprintln!("Failed to rebuild bloom pipelines: {}", err),
+        }
+    }
+
+    fn make_pipelines(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        composite_layout: &wgpu::PipelineLayout,
+        module: &wgpu::ShaderModule,
+    ) -> [wgpu::RenderPipeline; 4] {
+        let make = |layout, entry_point, blend| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module,
+                    entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: HdrBackBuffer::FORMAT,
+                        blend,
+                        write_mask: Default::default(),
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+        let additive = wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent::REPLACE,
+        };
+        [
+            make(layout, "downsample_first", None),
+            make(layout, "downsample", None),
+            make(layout, "upsample", Some(additive)),
+            make(composite_layout, "composite", None),
+        ]
+    }
+
+    /// Adds the glow of `input` to it and writes the result into `target`.
+    pub(crate) fn record(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        input: &wgpu::BindGroup,
+        target: &wgpu::TextureView,
+    ) {
+        let Some(top) = self.mips.first() else {
+            return;
+        };
+        let clear = wgpu::LoadOp::Clear(wgpu::Color::BLACK);
+        self.pass(encoder, &self.downsample_first, input, &top.view, clear);
+        for pair in self.mips.windows(2) {
+            let (src, dst) = (&pair[0].bind_group, &pair[1].view);
+            self.pass(encoder, &self.downsample, src, dst, clear);
+        }
+        // Each level adds its upsampled blur on top of its own downsampled content.
+        for pair in self.mips.windows(2).rev() {
+            let (src, dst) = (&pair[1].bind_group, &pair[0].view);
+            self.pass(encoder, &self.upsample, src, dst, wgpu::LoadOp::Load);
+        }
+
+        let mut rpass = begin_pass(encoder, target, clear);
+        rpass.set_pipeline(&self.composite);
+        rpass.set_bind_group(0, input, &[]);
+        rpass.set_bind_group(1, &self.bind_group, &[]);
+        rpass.set_bind_group(2, &top.bind_group, &[]);
+        rpass.draw(0..3, 0..1);
+    }
+
+    fn pass(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &wgpu::RenderPipeline,
+        source: &wgpu::BindGroup,
+        target: &wgpu::TextureView,
+        load: wgpu::LoadOp<wgpu::Color>,
+    ) {
+        let mut rpass = begin_pass(encoder, target, load);
+        rpass.set_pipeline(pipeline);
+        rpass.set_bind_group(0, source, &[]);
+        rpass.set_bind_group(1, &self.bind_group, &[]);
+        rpass.draw(0..3, 0..1);
+    }
+}
+
+fn begin_pass<'a>(
+    encoder: &'a mut wgpu::CommandEncoder,
+    target: &'a wgpu::TextureView,
+    load: wgpu::LoadOp<wgpu::Color>,
+) -> wgpu::RenderPass<'a> {
+    encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("Bloom Pass"),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: target,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load,
+                store: wgpu::StoreOp::Store,
+            },
+        })],
+        ..Default::default()
+    })
+}
+
+fn uniform(params: BloomParams, mip_count: u32) -> BloomUniform {
+    BloomUniform {
+        threshold: params.threshold,
+        knee: params.knee,
+        intensity: params.intensity,
+        radius: params.radius,
+        mip_count: mip_count.max(1),
+        _padding: [0; 3],
+    }
+}
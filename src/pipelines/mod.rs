@@ -1,3 +1,8 @@
+pub(crate) mod auto_exposure;
+pub(crate) mod bloom;
+pub(crate) mod normals;
+pub(crate) mod post;
 pub(crate) mod present;
 pub(crate) mod raycast;
+pub(crate) mod tiles;
 pub(crate) mod xor_compute;
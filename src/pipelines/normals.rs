@@ -0,0 +1,293 @@
+use crate::pipelines::xor_compute::XorCompute;
+use crate::utils::{dispatch_optimal, validate};
+use crate::NonZeroSized;
+
+use bytemuck::{Pod, Zeroable};
+use serde::Deserialize;
+use wgpu::util::DeviceExt;
+
+/// Filter that estimates the gradient of the opacity.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum NormalKernel {
+    /// Differences along each axis only, sharp but sensitive to noise.
+    #[default]
+    Central,
+    /// Differences smoothed over the whole cube of neighbors.
+    Sobel,
+}
+
+impl NormalKernel {
+    pub(crate) const ALL: [Self; 2] = [Self::Central, Self::Sobel];
+
+    pub(crate) fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct NormalsParams {
+    pub(crate) kernel: NormalKernel,
+    /// Side of the kernel in voxels, 3 or 5.
+    pub(crate) size: u32,
+}
+
+impl Default for NormalsParams {
+    fn default() -> Self {
+        Self {
+            kernel: NormalKernel::Central,
+            size: 3,
+        }
+    }
+}
+
+impl NormalsParams {
+    /// Cycles through every kernel at each size.
+    pub(crate) fn next(self) -> Self {
+        match self.size {
+            3 => Self { size: 5, ..self },
+            _ => Self {
+                kernel: self.kernel.next(),
+                size: 3,
+            },
+        }
+    }
+
+    fn radius(self) -> u32 {
+        self.size / 2
+    }
+}
+
+/// Mirrors `Params` in `normals.wgsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct NormalsUniform {
+    min: [u32; 3],
+    kernel: u32,
+    max: [u32; 3],
+    radius: u32,
+}
+
+/// Derives the normal texture from the opacity of the volume after it is generated or loaded.
+pub(crate) struct NormalsCompute {
+    pipeline: wgpu::ComputePipeline,
+    params: NormalsParams,
+    size: wgpu::Extent3d,
+    /// Covers the whole volume.
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    /// Covers the voxels the generator regenerates, grown by the kernel radius.
+    dirty_buffer: wgpu::Buffer,
+    dirty_bind_group: wgpu::BindGroup,
+    dirty_region: ([u32; 3], [u32; 3]),
+}
+
+impl NormalsCompute {
+    pub(crate) const DESC: wgpu::BindGroupLayoutDescriptor<'static> =
+        wgpu::BindGroupLayoutDescriptor {
+            label: Some("Normals Compute Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: wgpu::TextureFormat::Rgba16Float,
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(NormalsUniform::SIZE),
+                    },
+                    count: None,
+                },
+            ],
+        };
+
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        module_desc: wgpu::ShaderModuleDescriptor<'_>,
+        volume: &XorCompute,
+    ) -> Self {
+        let pipeline = {
+            let module = device.create_shader_module(module_desc);
+            Self::make_pipeline(device, module)
+        };
+
+        let params = NormalsParams::default();
+        let size = volume.size;
+        let whole = (
+            [0; 3],
+            [size.width, size.height, size.depth_or_array_layers],
+        );
+        let make_buffer = |label, region| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents: bytemuck::bytes_of(&uniform(params, region)),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            })
+        };
+        let buffer = make_buffer("Normals Params Buffer", whole);
+        let dirty_buffer = make_buffer("Normals Dirty Params Buffer", whole);
+
+        let layout = device.create_bind_group_layout(&Self::DESC);
+        let make_bind_group = |buffer: &wgpu::Buffer| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Normals Compute Bind Group"),
+                layout: &layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&volume.volume_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&volume.normal_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: buffer.as_entire_binding(),
+                    },
+                ],
+            })
+        };
+        let bind_group = make_bind_group(&buffer);
+        let dirty_bind_group = make_bind_group(&dirty_buffer);
+
+        Self {
+            pipeline,
+            params,
+            size,
+            buffer,
+            bind_group,
+            dirty_buffer,
+            dirty_bind_group,
+            dirty_region: whole,
+        }
+    }
+
+    pub(crate) fn params(&self) -> NormalsParams {
+        self.params
+    }
+
+    /// Takes effect the next time normals are computed.
+    pub(crate) fn set_params(&mut self, queue: &wgpu::Queue, params: NormalsParams) {
+        self.params = params;
+        self.write_params(queue);
+    }
+
+    /// Limits [`NormalsCompute::record_dirty`] to the voxels from `min` up to but excluding `max`.
+    pub(crate) fn set_dirty_region(&mut self, queue: &wgpu::Queue, min: [u32; 3], max: [u32; 3]) {
+        self.dirty_region = (min, max);
+        self.write_params(queue);
+    }
+
+    fn write_params(&self, queue: &wgpu::Queue) {
+        let size = [
+            self.size.width,
+            self.size.height,
+            self.size.depth_or_array_layers,
+        ];
+        let whole = uniform(self.params, ([0; 3], size));
+        queue.write_buffer(&self.buffer, 0, bytemuck::bytes_of(&whole));
+
+        // Normals next to the region read voxels inside it, so they change too.
+        let radius = self.params.radius();
+        let (min, max) = self.dirty_region;
+        let min = min.map(|v| v.saturating_sub(radius));
+        let max = std::array::from_fn(|i| (max[i] + radius).min(size[i]));
+        let dirty = uniform(self.params, (min, max));
+        queue.write_buffer(&self.dirty_buffer, 0, bytemuck::bytes_of(&dirty));
+    }
+
+    /// Swaps in a pipeline built from `module`, keeping the old one if it fails to validate.
+    pub(crate) fn rebuild(&mut self, device: &wgpu::Device, module: wgpu::ShaderModule) {
+        match validate(device, || Self::make_pipeline(device, module)) {
+            Ok(pipeline) => self.pipeline = pipeline,
+            Err(err) => eprintln!("Failed to rebuild normals pipeline: {}", err),
+        }
+    }
+
+    fn make_pipeline(device: &wgpu::Device, module: wgpu::ShaderModule) -> wgpu::ComputePipeline {
+        let layout = device.create_bind_group_layout(&Self::DESC);
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Normals Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Compute Volume Normals"),
+            layout: Some(&pipeline_layout),
+            module: &module,
+            entry_point: "cs_main",
+        })
+    }
+}
+
+impl<'a> NormalsCompute {
+    /// Computes normals for the whole volume.
+    pub(crate) fn record<'pass>(&'a self, cpass: &mut wgpu::ComputePass<'pass>)
+    where
+        'a: 'pass,
+    {
+        let extent = [
+            self.size.width,
+            self.size.height,
+            self.size.depth_or_array_layers,
+        ];
+        self.record_region(cpass, &self.bind_group, extent);
+    }
+
+    /// Computes normals around the region set by [`NormalsCompute::set_dirty_region`].
+    pub(crate) fn record_dirty<'pass>(&'a self, cpass: &mut wgpu::ComputePass<'pass>)
+    where
+        'a: 'pass,
+    {
+        let (min, max) = self.dirty_region;
+        let extent = std::array::from_fn(|i| max[i] - min[i] + 2 * self.params.radius());
+        self.record_region(cpass, &self.dirty_bind_group, extent);
+    }
+
+    fn record_region<'pass>(
+        &'a self,
+        cpass: &mut wgpu::ComputePass<'pass>,
+        bind_group: &'a wgpu::BindGroup,
+        [width, height, depth]: [u32; 3],
+    ) where
+        'a: 'pass,
+    {
+        cpass.set_pipeline(&self.pipeline);
+        cpass.set_bind_group(0, bind_group, &[]);
+        cpass.dispatch_workgroups(
+            dispatch_optimal(width, 8),
+            dispatch_optimal(height, 8),
+            dispatch_optimal(depth, 8),
+        );
+    }
+}
+
+fn uniform(params: NormalsParams, (min, max): ([u32; 3], [u32; 3])) -> NormalsUniform {
+    NormalsUniform {
+        min,
+        kernel: params.kernel as u32,
+        max,
+        radius: params.radius(),
+    }
+}
@@ -0,0 +1,435 @@
+use crate::context::HdrBackBuffer;
+use crate::lut::Lut;
+use crate::pipelines::bloom::{Bloom, BloomParams};
+use crate::pipelines::present::{create_lut_bind_group, PresentPipeline};
+use crate::utils::validate;
+use crate::{GlobalUniformBinding, NonZeroSized, Uniform};
+
+use bytemuck::{Pod, Zeroable};
+use serde::Deserialize;
+use wgpu::util::DeviceExt;
+
+/// A pass of the post-processing chain, runs on the HDR image before tone mapping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum PostEffect {
+    Vignette,
+    FilmGrain,
+    ChromaticAberration,
+    Bloom,
+    /// Color grading of the HDR image, separate from the LUTs of the present pass.
+    Lut,
+}
+
+impl PostEffect {
+    pub(crate) const ALL: [Self; 5] = [
+        Self::Vignette,
+        Self::FilmGrain,
+        Self::ChromaticAberration,
+        Self::Bloom,
+        Self::Lut,
+    ];
+
+    /// Fragment entry point in `post.wgsl`, bloom runs its own passes from `bloom.wgsl`.
+    fn entry_point(self) -> Option<&'static str> {
+        match self {
+            Self::Vignette => Some("vignette"),
+            Self::FilmGrain => Some("film_grain"),
+            Self::ChromaticAberration => Some("chromatic_aberration"),
+            Self::Bloom => None,
+            Self::Lut => Some("lut"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct VignetteParams {
+    /// How much the corners are darkened, 1 makes them black.
+    pub(crate) intensity: f32,
+    /// Distance from the center where darkening starts, 1 is a corner.
+    pub(crate) radius: f32,
+    /// Distance over which it fades in.
+    pub(crate) smoothness: f32,
+}
+
+impl Default for VignetteParams {
+    fn default() -> Self {
+        Self {
+            intensity: 0.5,
+            radius: 0.5,
+            smoothness: 0.5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct FilmGrainParams {
+    pub(crate) intensity: f32,
+    /// Side of a grain in backbuffer pixels.
+    pub(crate) size: f32,
+}
+
+impl Default for FilmGrainParams {
+    fn default() -> Self {
+        Self {
+            intensity: 0.1,
+            size: 1.,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ChromaticAberrationParams {
+    /// Offset of the red and blue channels at the edges, as a fraction of the image.
+    pub(crate) strength: f32,
+}
+
+impl Default for ChromaticAberrationParams {
+    fn default() -> Self {
+        Self { strength: 0.01 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct LutParams {
+    /// Blend of the graded colors, 0 leaves the image untouched.
+    pub(crate) strength: f32,
+}
+
+impl Default for LutParams {
+    fn default() -> Self {
+        Self { strength: 1. }
+    }
+}
+
+/// Parameters of every effect, whether it is in the chain or not.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct PostParams {
+    pub(crate) vignette: VignetteParams,
+    pub(crate) film_grain: FilmGrainParams,
+    pub(crate) chromatic_aberration: ChromaticAberrationParams,
+    pub(crate) bloom: BloomParams,
+    pub(crate) lut: LutParams,
+}
+
+/// Mirrors `Params` in `post.wgsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct PostUniform {
+    vignette_intensity: f32,
+    vignette_radius: f32,
+    vignette_smoothness: f32,
+    grain_intensity: f32,
+    grain_size: f32,
+    aberration_strength: f32,
+    lut_strength: f32,
+    _padding: f32,
+}
+
+impl From<PostParams> for PostUniform {
+    fn from(params: PostParams) -> Self {
+        Self {
+            vignette_intensity: params.vignette.intensity,
+            vignette_radius: params.vignette.radius,
+            vignette_smoothness: params.vignette.smoothness,
+            grain_intensity: params.film_grain.intensity,
+            grain_size: params.film_grain.size,
+            aberration_strength: params.chromatic_aberration.strength,
+            lut_strength: params.lut.strength,
+            _padding: 0.,
+        }
+    }
+}
+
+/// HDR texture a pass renders into and the next one samples.
+struct PostTarget {
+    view: wgpu::TextureView,
+    bind_group: wgpu::BindGroup,
+}
+
+impl PostTarget {
+    fn new(device: &wgpu::Device, (width, height): (u32, u32)) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Post Target"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: HdrBackBuffer::FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&Default::default());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Post Target Bind Group"),
+            layout: &device.create_bind_group_layout(&HdrBackBuffer::DESC_RENDER),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&view),
+            }],
+        });
+        Self { view, bind_group }
+    }
+}
+
+/// Ordered effects between the raycast and the present pass,
+/// each one reads the output of the previous one from a pair of ping-pong targets.
+pub(crate) struct PostChain {
+    chain: Vec<PostEffect>,
+    /// One per effect with an entry point, in the order of [`PostEffect::ALL`].
+    pipelines: Vec<Option<wgpu::RenderPipeline>>,
+    layout: wgpu::PipelineLayout,
+    params_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    lut_sampler: wgpu::Sampler,
+    /// Identity until [`Self::set_lut`] replaces it.
+    lut_bind_group: wgpu::BindGroup,
+    size: (u32, u32),
+    /// Only allocated while the chain is not empty.
+    targets: Option<[PostTarget; 2]>,
+    pub(crate) bloom: Bloom,
+}
+
+impl PostChain {
+    pub(crate) const DESC: wgpu::BindGroupLayoutDescriptor<'static> =
+        wgpu::BindGroupLayoutDescriptor {
+            label: Some("Post Params Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(PostUniform::SIZE),
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        };
+
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        module_desc: wgpu::ShaderModuleDescriptor<'_>,
+        bloom_module_desc: wgpu::ShaderModuleDescriptor<'_>,
+        size: (u32, u32),
+    ) -> Self {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Post Pipeline Layout"),
+            bind_group_layouts: &[
+                &device.create_bind_group_layout(&Uniform::DESC),
+                &device.create_bind_group_layout(&HdrBackBuffer::DESC_RENDER),
+                &device.create_bind_group_layout(&Self::DESC),
+                &device.create_bind_group_layout(&PresentPipeline::DESC_LUT),
+            ],
+            push_constant_ranges: &[],
+        });
+        let module = device.create_shader_module(module_desc);
+        let pipelines = Self::make_pipelines(device, &layout, &module);
+
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Post Params Buffer"),
+            contents: bytemuck::bytes_of(&PostUniform::from(PostParams::default())),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Post Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Post Params Bind Group"),
+            layout: &device.create_bind_group_layout(&Self::DESC),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+
+        let lut_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Post LUT Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let lut_bind_group = create_lut_bind_group(device, queue, &lut_sampler, &Lut::identity());
+
+        Self {
+            chain: Vec::new(),
+            pipelines,
+            layout,
+            params_buffer,
+            bind_group,
+            lut_sampler,
+            lut_bind_group,
+            size,
+            targets: None,
+            bloom: Bloom::new(device, bloom_module_desc),
+        }
+    }
+
+    pub(crate) fn set_chain(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        chain: Vec<PostEffect>,
+    ) {
+        self.chain = chain;
+        self.fit_targets(device, queue);
+    }
+
+    pub(crate) fn set_params(&mut self, queue: &wgpu::Queue, params: PostParams) {
+        let uniform = PostUniform::from(params);
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&uniform));
+        self.bloom.set_params(queue, params.bloom);
+    }
+
+    /// Uploads the table of the [`PostEffect::Lut`] pass.
+    pub(crate) fn set_lut(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, lut: &Lut) {
+        self.lut_bind_group = create_lut_bind_group(device, queue, &self.lut_sampler, lut);
+    }
+
+    /// Follows the size of the backbuffer.
+    pub(crate) fn resize(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, size: (u32, u32)) {
+        if self.size != size {
+            self.size = size;
+            self.targets = None;
+            self.bloom.resize(device, queue, None);
+            self.fit_targets(device, queue);
+        }
+    }
+
+    fn fit_targets(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if self.chain.is_empty() {
+            self.targets = None;
+        } else if self.targets.is_none() {
+            self.targets = Some([
+                PostTarget::new(device, self.size),
+                PostTarget::new(device, self.size),
+            ]);
+        }
+        let bloom_size = self.chain.contains(&PostEffect::Bloom).then_some(self.size);
+        if bloom_size.is_none() || !self.bloom.is_allocated() {
+            self.bloom.resize(device, queue, bloom_size);
+        }
+    }
+
+    /// Swaps in pipelines built from `module`, keeping the old ones if they fail to validate.
+    pub(crate) fn rebuild(&mut self, device: &wgpu::Device, module: wgpu::ShaderModule) {
+        match validate(device, || {
+            Self::make_pipelines(device, &self.layout, &module)
+        }) {
+            Ok(pipelines) => self.pipelines = pipelines,
+            Err(err) => eprintln!("Failed to rebuild post pipelines: {}", err),
+        }
+    }
+
+    fn make_pipelines(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        module: &wgpu::ShaderModule,
+    ) -> Vec<Option<wgpu::RenderPipeline>> {
+        PostEffect::ALL
+            .iter()
+            .map(|effect| {
+                let entry_point = effect.entry_point()?;
+                Some(
+                    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                        label: Some(entry_point),
+                        layout: Some(layout),
+                        vertex: wgpu::VertexState {
+                            module,
+                            entry_point: "vs_main",
+                            buffers: &[],
+                        },
+                        fragment: Some(wgpu::FragmentState {
+                            module,
+                            entry_point,
+                            targets: &[Some(wgpu::ColorTargetState {
+                                format: HdrBackBuffer::FORMAT,
+                                blend: None,
+                                write_mask: Default::default(),
+                            })],
+                        }),
+                        primitive: wgpu::PrimitiveState::default(),
+                        depth_stencil: None,
+                        multisample: wgpu::MultisampleState::default(),
+                        multiview: None,
+                    }),
+                )
+            })
+            .collect()
+    }
+
+    /// Runs the chain over `backbuffer` and returns the bind group of the result,
+    /// which is the backbuffer itself when the chain is empty.
+    pub(crate) fn record<'a>(
+        &'a self,
+        encoder: &mut wgpu::CommandEncoder,
+        uniform_bind_group: &GlobalUniformBinding,
+        backbuffer: &'a HdrBackBuffer,
+    ) -> &'a wgpu::BindGroup {
+        let Some(targets) = &self.targets else {
+            return &backbuffer.render_bind_group;
+        };
+        let mut input = &backbuffer.render_bind_group;
+        for (i, &effect) in self.chain.iter().enumerate() {
+            let target = &targets[i % 2];
+            if effect == PostEffect::Bloom {
+                self.bloom.record(encoder, input, &target.view);
+                input = &target.bind_group;
+                continue;
+            }
+            let pipeline = self.pipelines[effect as usize]
+                .as_ref()
+                .expect("every other effect has a pipeline");
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Post Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &target.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                ..Default::default()
+            });
+            rpass.set_pipeline(pipeline);
+            rpass.set_bind_group(0, &uniform_bind_group.binding, &[]);
+            rpass.set_bind_group(1, input, &[]);
+            rpass.set_bind_group(2, &self.bind_group, &[]);
+            rpass.set_bind_group(3, &self.lut_bind_group, &[]);
+            rpass.draw(0..3, 0..1);
+            input = &target.bind_group;
+        }
+        input
+    }
+}
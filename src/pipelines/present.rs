@@ -1,33 +1,268 @@
-use crate::{GlobalUniformBinding, Uniform};
+use crate::context::HdrBackBuffer;
+use crate::lut::Lut;
+use crate::pipelines::auto_exposure::AutoExposure;
+use crate::utils::validate;
+use crate::{GlobalUniformBinding, NonZeroSized, Uniform};
+
+use bytemuck::{Pod, Zeroable};
+use serde::Deserialize;
+use wgpu::util::DeviceExt;
+
+/// Operator mapping the HDR backbuffer to displayable colors.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ToneMapping {
+    /// Narkowicz's fit of the ACES filmic curve.
+    #[default]
+    Aces,
+    /// Only clamps, useful to inspect the raw values.
+    None,
+    Reinhard,
+    /// Reinhard that reaches white at the white point instead of infinity.
+    ReinhardExtended,
+    /// Hable's filmic curve, normalized by the white point.
+    Uncharted2,
+    /// Troy Sobotka's AgX with the default look.
+    Agx,
+    /// Khronos PBR Neutral, keeps base colors close to their sRGB values.
+    Neutral,
+}
+
+impl ToneMapping {
+    pub(crate) const ALL: [Self; 7] = [
+        Self::Aces,
+        Self::None,
+        Self::Reinhard,
+        Self::ReinhardExtended,
+        Self::Uncharted2,
+        Self::Agx,
+        Self::Neutral,
+    ];
+
+    /// The operator after this one, wrapping around.
+    pub(crate) fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+}
+
+/// Filter stretching the backbuffer over the window when their sizes differ.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Upscaling {
+    Nearest,
+    #[default]
+    Bilinear,
+    Quadratic,
+    /// Cubic B-spline, smooth but slightly blurry.
+    Bicubic,
+    /// Lanczos-2, sharp with some ringing.
+    Lanczos,
+    /// Bilinear followed by contrast adaptive sharpening.
+    Cas,
+}
+
+impl Upscaling {
+    pub(crate) const ALL: [Self; 6] = [
+        Self::Nearest,
+        Self::Bilinear,
+        Self::Quadratic,
+        Self::Bicubic,
+        Self::Lanczos,
+        Self::Cas,
+    ];
+
+    /// The filter after this one, wrapping around.
+    pub(crate) fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+}
+
+/// Present pass settings that can be changed between frames.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct PresentParams {
+    pub(crate) tonemap: ToneMapping,
+    /// Exposure in EV stops, applied before the tone mapping operator.
+    pub(crate) exposure: f32,
+    /// Linear value that maps to white, used by the operators that have one.
+    pub(crate) white_point: f32,
+    pub(crate) upscaling: Upscaling,
+    /// Adds the exposure measured by [`AutoExposure`] on top of `exposure`.
+    pub(crate) auto_exposure: bool,
+    /// Index of the active color grading LUT, 0 disables grading.
+    pub(crate) lut: usize,
+    /// Blend between the ungraded and the graded color.
+    pub(crate) lut_strength: f32,
+}
+
+impl Default for PresentParams {
+    fn default() -> Self {
+        Self {
+            tonemap: ToneMapping::default(),
+            exposure: 0.,
+            white_point: 11.2,
+            upscaling: Upscaling::default(),
+            auto_exposure: false,
+            lut: 0,
+            lut_strength: 1.,
+        }
+    }
+}
+
+/// How the present pass encodes colors for the surface, mirrors `OUTPUT_*` in `present.wgsl`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SurfaceOutput {
+    /// The shader applies the sRGB curve itself.
+    Unorm,
+    /// The surface applies the sRGB curve on write.
+    Srgb,
+    /// Float surface, takes the tone mapped and graded colors without the sRGB curve.
+    Hdr,
+}
+
+impl SurfaceOutput {
+    pub(crate) fn new(format: wgpu::TextureFormat) -> Self {
+        match format {
+            wgpu::TextureFormat::Rgba16Float => Self::Hdr,
+            format if format.is_srgb() => Self::Srgb,
+            _ => Self::Unorm,
+        }
+    }
+}
+
+/// Mirrors `Params` in `present.wgsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct PresentUniform {
+    exposure: f32,
+    tonemap: u32,
+    upscale: u32,
+    white_point: f32,
+    auto_exposure: u32,
+    output: u32,
+    lut_strength: f32,
+}
+
+impl PresentUniform {
+    fn new(params: PresentParams, output: SurfaceOutput) -> Self {
+        Self {
+            exposure: params.exposure,
+            tonemap: params.tonemap as u32,
+            upscale: params.upscaling as u32,
+            white_point: params.white_point,
+            auto_exposure: params.auto_exposure as u32,
+            output: output as u32,
+            lut_strength: if params.lut == 0 {
+                0.
+            } else {
+                params.lut_strength
+            },
+        }
+    }
+}
+
+/// Mirrors `LutDomain` in `present.wgsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct LutDomain {
+    min: [f32; 4],
+    max: [f32; 4],
+}
+
+/// A color grading LUT uploaded for the present pass.
+struct LutBinding {
+    name: String,
+    bind_group: wgpu::BindGroup,
+}
 
 pub(crate) struct PresentPipeline {
     pub(crate) pipeline: wgpu::RenderPipeline,
-    _surface_format: wgpu::TextureFormat,
+    layout: wgpu::PipelineLayout,
+    surface_format: wgpu::TextureFormat,
+    /// Whether the pass also writes the `Rgba8Unorm` capture target.
+    rgb_target: bool,
     sampler_bind_group: wgpu::BindGroup,
+    params: PresentParams,
+    params_buffer: wgpu::Buffer,
+    params_bind_group: wgpu::BindGroup,
+    lut_sampler: wgpu::Sampler,
+    /// Starts with an identity LUT for [`PresentParams::lut`] 0.
+    luts: Vec<LutBinding>,
 }
 
 impl PresentPipeline {
+    pub(crate) const DESC_PARAMS: wgpu::BindGroupLayoutDescriptor<'static> =
+        wgpu::BindGroupLayoutDescriptor {
+            label: Some("Present Params BGL"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(PresentUniform::SIZE),
+                    },
+                    count: None,
+                },
+                // Exposure measured by [`AutoExposure`].
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        };
+
+    pub(crate) const DESC_LUT: wgpu::BindGroupLayoutDescriptor<'static> =
+        wgpu::BindGroupLayoutDescriptor {
+            label: Some("Present LUT BGL"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(LutDomain::SIZE),
+                    },
+                    count: None,
+                },
+            ],
+        };
+
     pub(crate) fn new(
         device: &wgpu::Device,
+        queue: &wgpu::Queue,
         surface_format: wgpu::TextureFormat,
+        rgb_target: bool,
         module_desc: wgpu::ShaderModuleDescriptor<'_>,
+        auto_exposure: &AutoExposure,
     ) -> Self {
         let shader = device.create_shader_module(module_desc);
         let global_bind_group_layout = device.create_bind_group_layout(&Uniform::DESC);
         let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("Present Texture BGL"),
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        multisampled: false,
-                    },
-                    count: None,
-                }],
-            });
+            device.create_bind_group_layout(&HdrBackBuffer::DESC_RENDER);
         let sampler_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: Some("Present Sampler BGL"),
@@ -38,47 +273,19 @@ impl PresentPipeline {
                     count: None,
                 }],
             });
+        let params_bind_group_layout = device.create_bind_group_layout(&Self::DESC_PARAMS);
         let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Screen Pass Layout"),
             bind_group_layouts: &[
                 &global_bind_group_layout,
                 &texture_bind_group_layout,
                 &sampler_bind_group_layout,
+                &params_bind_group_layout,
+                &device.create_bind_group_layout(&Self::DESC_LUT),
             ],
             push_constant_ranges: &[],
         });
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Present Pipeline"),
-            layout: Some(&layout),
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[
-                    Some(wgpu::ColorTargetState {
-                        format: surface_format,
-                        blend: None,
-                        write_mask: Default::default(),
-                    }),
-                    Some(wgpu::ColorTargetState {
-                        format: wgpu::TextureFormat::Rgba8Unorm,
-                        blend: None,
-                        write_mask: Default::default(),
-                    }),
-                ],
-            }),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[],
-            },
-            primitive: wgpu::PrimitiveState::default(),
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                ..Default::default()
-            },
-            multiview: None,
-        });
+        let pipeline = Self::make_pipeline(device, &layout, shader, surface_format, rgb_target);
 
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             label: Some("Present Sampler"),
@@ -99,12 +306,149 @@ impl PresentPipeline {
             }],
         });
 
-        Self {
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Present Params Buffer"),
+            contents: bytemuck::bytes_of(&PresentUniform::new(
+                PresentParams::default(),
+                SurfaceOutput::new(surface_format),
+            )),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let params_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Present Params Bind Group"),
+            layout: &params_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: auto_exposure.exposure_uniform.as_entire_binding(),
+                },
+            ],
+        });
+
+        let lut_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Present LUT Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let mut pipeline = Self {
             pipeline,
-            _surface_format: surface_format,
+            layout,
+            surface_format,
+            rgb_target,
             sampler_bind_group,
+            params: PresentParams::default(),
+            params_buffer,
+            params_bind_group,
+            lut_sampler,
+            luts: Vec::new(),
+        };
+        pipeline.add_lut(device, queue, "none", &Lut::identity());
+        pipeline
+    }
+
+    /// Uploads `lut` and returns the index that selects it in [`PresentParams::lut`].
+    pub(crate) fn add_lut(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        name: &str,
+        lut: &Lut,
+    ) -> usize {
+        let bind_group = create_lut_bind_group(device, queue, &self.lut_sampler, lut);
+        self.luts.push(LutBinding {
+            name: name.to_string(),
+            bind_group,
+        });
+        self.luts.len() - 1
+    }
+
+    /// Number of selectable LUTs, including the disabled one at index 0.
+    pub(crate) fn lut_count(&self) -> usize {
+        self.luts.len()
+    }
+
+    pub(crate) fn lut_name(&self, index: usize) -> &str {
+        &self.luts[index].name
+    }
+
+    pub(crate) fn params(&self) -> PresentParams {
+        self.params
+    }
+
+    pub(crate) fn set_params(&mut self, queue: &wgpu::Queue, params: PresentParams) {
+        self.params = params;
+        let uniform = PresentUniform::new(params, SurfaceOutput::new(self.surface_format));
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&uniform));
+    }
+
+    /// Swaps in a pipeline built from `module`, keeping the old one if it fails to validate.
+    pub(crate) fn rebuild(&mut self, device: &wgpu::Device, module: wgpu::ShaderModule) {
+        match validate(device, || {
+            Self::make_pipeline(
+                device,
+                &self.layout,
+                module,
+                self.surface_format,
+                self.rgb_target,
+            )
+        }) {
+            Ok(pipeline) => self.pipeline = pipeline,
+            Err(err) => eprintln!("Failed to rebuild present pipeline: {}", err),
         }
     }
+
+    fn make_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: wgpu::ShaderModule,
+        surface_format: wgpu::TextureFormat,
+        rgb_target: bool,
+    ) -> wgpu::RenderPipeline {
+        let targets = [
+            Some(wgpu::ColorTargetState {
+                format: surface_format,
+                blend: None,
+                write_mask: Default::default(),
+            }),
+            Some(wgpu::ColorTargetState {
+                format: wgpu::TextureFormat::Rgba8Unorm,
+                blend: None,
+                write_mask: Default::default(),
+            }),
+        ];
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Present Pipeline"),
+            layout: Some(layout),
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                // Headless contexts capture the first target, a single target
+                // also keeps GL drivers without multiple draw buffers happy.
+                targets: &targets[..1 + rgb_target as usize],
+            }),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                ..Default::default()
+            },
+            multiview: None,
+        })
+    }
 }
 
 impl<'a> PresentPipeline {
@@ -120,6 +464,68 @@ impl<'a> PresentPipeline {
         rpass.set_bind_group(0, &uniform_bind_group.binding, &[]);
         rpass.set_bind_group(1, input_texture_binding, &[]);
         rpass.set_bind_group(2, &self.sampler_bind_group, &[]);
+        rpass.set_bind_group(3, &self.params_bind_group, &[]);
+        rpass.set_bind_group(4, &self.luts[self.params.lut].bind_group, &[]);
         rpass.draw(0..3, 0..1);
     }
 }
+
+/// Uploads `lut` and binds it for the [`PresentPipeline::DESC_LUT`] layout,
+/// shared by the present pass and the LUT post effect.
+pub(crate) fn create_lut_bind_group(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    sampler: &wgpu::Sampler,
+    lut: &Lut,
+) -> wgpu::BindGroup {
+    let texture = device.create_texture_with_data(
+        queue,
+        &wgpu::TextureDescriptor {
+            label: Some("LUT Texture"),
+            size: wgpu::Extent3d {
+                width: lut.size,
+                height: lut.size,
+                depth_or_array_layers: lut.size,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            format: wgpu::TextureFormat::Rgba16Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        },
+        wgpu::util::TextureDataOrder::LayerMajor,
+        &lut.texels(),
+    );
+    let [min_r, min_g, min_b] = lut.domain_min;
+    let [max_r, max_g, max_b] = lut.domain_max;
+    let domain = LutDomain {
+        min: [min_r, min_g, min_b, 0.],
+        max: [max_r, max_g, max_b, 1.],
+    };
+    let domain_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("LUT Domain Buffer"),
+        contents: bytemuck::bytes_of(&domain),
+        usage: wgpu::BufferUsages::UNIFORM,
+    });
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("LUT Bind Group"),
+        layout: &device.create_bind_group_layout(&PresentPipeline::DESC_LUT),
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(
+                    &texture.create_view(&Default::default()),
+                ),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: domain_buffer.as_entire_binding(),
+            },
+        ],
+    })
+}
@@ -1,42 +1,298 @@
-use crate::pipelines::xor_compute;
-use crate::{CameraBinding, HdrBackBuffer, Uniform};
+use crate::pipelines::{tiles::Tiles, xor_compute};
+use crate::transfer_function::{self, TransferFunction};
+use crate::utils::validate;
+use crate::{CameraBinding, HdrBackBuffer, NonZeroSized, Uniform};
+
+use bytemuck::{Pod, Zeroable};
+use half::f16;
+use serde::Deserialize;
+use wgpu::util::DeviceExt;
+
+/// Entry points of `raycast_compute.wgsl` the renderer knows how to drive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum EntryPoint {
+    /// The whole backbuffer in one dispatch.
+    #[default]
+    Single,
+    /// A few tiles per frame, see [`Tiles`](super::tiles::Tiles).
+    Tile,
+}
+
+impl EntryPoint {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Single => "single",
+            Self::Tile => "tile",
+        }
+    }
+
+    /// Bind groups in the pipeline layout, `tile` adds the offsets as group 5.
+    const fn bind_groups(self) -> u32 {
+        match self {
+            Self::Single => 5,
+            Self::Tile => 6,
+        }
+    }
+
+    /// Fails when the device can't bind as many groups as the entry point needs,
+    /// WebGPU only guarantees 4.
+    pub(crate) fn check_limits(self, limits: &wgpu::Limits) -> Result<(), String> {
+        if limits.max_bind_groups < self.bind_groups() {
+            return Err(format!(
+                "The {} raycast entry point needs {} bind groups, the device supports {}",
+                self.name(),
+                self.bind_groups(),
+                limits.max_bind_groups
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// How the raycaster reads the volume between voxel centers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum VolumeFilter {
+    /// The voxel the sample falls in, blocky up close.
+    Nearest,
+    /// Hardware interpolation between the eight closest voxels.
+    #[default]
+    Trilinear,
+    /// Cubic B-spline over the 64 closest voxels, smoothest but eight times the samples.
+    Tricubic,
+}
+
+impl VolumeFilter {
+    pub(crate) const ALL: [Self; 3] = [Self::Nearest, Self::Trilinear, Self::Tricubic];
+
+    pub(crate) fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+}
+
+/// Raycast parameters, mirrors `Params` in `raycast_compute.wgsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+pub(crate) struct RaycastUniform {
+    clear_color: [f32; 4],
+    light_direction: [f32; 3],
+    step_scale: f32,
+    /// Half extents of the box the volume is drawn in.
+    box_extent: [f32; 3],
+    opacity_cutoff: f32,
+    /// Non-zero when the transfer function tints the volume colors.
+    modulate: u32,
+    /// Frames already averaged into the accumulation buffer, zero restarts the average.
+    sample_index: u32,
+    volume_filter: u32,
+    _padding: u32,
+}
+
+impl RaycastUniform {
+    pub(crate) fn new(
+        clear_color: [f32; 4],
+        light_direction: [f32; 3],
+        step_scale: f32,
+        opacity_cutoff: f32,
+        box_extent: [f32; 3],
+        volume_filter: VolumeFilter,
+    ) -> Self {
+        Self {
+            clear_color,
+            light_direction,
+            step_scale,
+            box_extent,
+            opacity_cutoff,
+            modulate: 0,
+            sample_index: 0,
+            volume_filter: volume_filter as u32,
+            _padding: 0,
+        }
+    }
+}
 
 pub(crate) struct RaycastPipeline {
     pub(crate) pipeline: wgpu::ComputePipeline,
+    pub(crate) entry_point: EntryPoint,
+
+    params: RaycastUniform,
+    params_buffer: wgpu::Buffer,
+    transfer_function: wgpu::Texture,
+    pub(crate) params_bind_group: wgpu::BindGroup,
 }
 
 impl RaycastPipeline {
+    pub(crate) const DESC_PARAMS: wgpu::BindGroupLayoutDescriptor<'static> =
+        wgpu::BindGroupLayoutDescriptor {
+            label: Some("Raycast Params Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(RaycastUniform::SIZE),
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D1,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        };
+
     pub(crate) fn new(
         device: &wgpu::Device,
+        queue: &wgpu::Queue,
         module_desc: wgpu::ShaderModuleDescriptor<'_>,
-        entry_point: &str,
+        entry_point: EntryPoint,
+        params: RaycastUniform,
+        transfer_function: &TransferFunction,
     ) -> Self {
         let module = device.create_shader_module(module_desc);
         let pipeline = Self::make_pipeline(device, module, entry_point);
-        Self { pipeline }
+
+        let params = RaycastUniform {
+            modulate: transfer_function.modulate as u32,
+            ..params
+        };
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Raycast Params Buffer"),
+            contents: bytemuck::bytes_of(&params),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let transfer_function_texture = device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some("Transfer Function Texture"),
+                size: wgpu::Extent3d {
+                    width: transfer_function::RESOLUTION,
+                    height: 1,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D1,
+                format: wgpu::TextureFormat::Rgba16Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            wgpu::util::TextureDataOrder::LayerMajor,
+            &bake(transfer_function),
+        );
+        let params_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Raycast Params Bind Group"),
+            layout: &device.create_bind_group_layout(&Self::DESC_PARAMS),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(
+                        &transfer_function_texture.create_view(&Default::default()),
+                    ),
+                },
+            ],
+        });
+
+        Self {
+            pipeline,
+            entry_point,
+
+            params,
+            params_buffer,
+            transfer_function: transfer_function_texture,
+            params_bind_group,
+        }
+    }
+
+    /// Rebakes the lookup texture, for instance after the JSON file changed.
+    pub(crate) fn set_transfer_function(
+        &mut self,
+        queue: &wgpu::Queue,
+        transfer_function: &TransferFunction,
+    ) {
+        queue.write_texture(
+            self.transfer_function.as_image_copy(),
+            &bake(transfer_function),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: None,
+                rows_per_image: None,
+            },
+            self.transfer_function.size(),
+        );
+        self.params.modulate = transfer_function.modulate as u32;
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&self.params));
+    }
+
+    /// Sets how many frames the next one gets averaged with, zero starts over.
+    pub(crate) fn set_sample_index(&mut self, queue: &wgpu::Queue, sample_index: u32) {
+        if self.params.sample_index != sample_index {
+            self.params.sample_index = sample_index;
+            queue.write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&self.params));
+        }
+    }
+
+    pub(crate) fn volume_filter(&self) -> VolumeFilter {
+        VolumeFilter::ALL[self.params.volume_filter as usize]
+    }
+
+    pub(crate) fn set_volume_filter(&mut self, queue: &wgpu::Queue, filter: VolumeFilter) {
+        self.params.volume_filter = filter as u32;
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&self.params));
+    }
+
+    /// Swaps in a pipeline built from `module`, keeping the old one if it fails to validate.
+    pub(crate) fn rebuild(&mut self, device: &wgpu::Device, module: wgpu::ShaderModule) {
+        match validate(device, || {
+            Self::make_pipeline(device, module, self.entry_point)
+        }) {
+            Ok(pipeline) => self.pipeline = pipeline,
+            Err(err) => eprintln!("Failed to rebuild raycast pipeline: {}", err),
+        }
     }
 
     fn make_pipeline(
         device: &wgpu::Device,
         module: wgpu::ShaderModule,
-        entry_point: &str,
+        entry_point: EntryPoint,
     ) -> wgpu::ComputePipeline {
         let layout = {
             let global_bind_group_layout = device.create_bind_group_layout(&Uniform::DESC);
             let camera_bind_group_layout = device.create_bind_group_layout(&CameraBinding::DESC);
             let volume_bind_group_layout =
-                device.create_bind_group_layout(&xor_compute::XorCompute::DESC_COMPUTE);
+                device.create_bind_group_layout(&xor_compute::XorCompute::DESC_RENDER);
             let output_texture_bind_group_layout =
                 device.create_bind_group_layout(&HdrBackBuffer::DESC_COMPUTE);
+            let params_bind_group_layout = device.create_bind_group_layout(&Self::DESC_PARAMS);
+            let tiles_bind_group_layout = device.create_bind_group_layout(&Tiles::DESC);
 
+            let mut bind_group_layouts = vec![
+                &global_bind_group_layout,
+                &camera_bind_group_layout,
+                &volume_bind_group_layout,
+                &output_texture_bind_group_layout,
+                &params_bind_group_layout,
+            ];
+            // Every group of the layout has to be bound, so only `tile` gets the offsets.
+            if entry_point == EntryPoint::Tile {
+                bind_group_layouts.push(&tiles_bind_group_layout);
+            }
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Compute Raycast Pass Layout"),
-                bind_group_layouts: &[
-                    &global_bind_group_layout,
-                    &camera_bind_group_layout,
-                    &volume_bind_group_layout,
-                    &output_texture_bind_group_layout,
-                ],
+                bind_group_layouts: &bind_group_layouts,
                 push_constant_ranges: &[],
             })
         };
@@ -45,7 +301,39 @@ impl RaycastPipeline {
             label: Some("Compute Raycast Pipeline"),
             layout: Some(&layout),
             module: &module,
-            entry_point,
+            entry_point: entry_point.name(),
         })
     }
 }
+
+/// Texels of the transfer function in the layout of the lookup texture.
+fn bake(transfer_function: &TransferFunction) -> Vec<u8> {
+    transfer_function
+        .bake()
+        .into_iter()
+        .flatten()
+        .flat_map(|c| f16::from_f32(c).to_le_bytes())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tile_entry_point_needs_six_bind_groups() {
+        let limits = wgpu::Limits {
+            max_bind_groups: 5,
+            ..Default::default()
+        };
+        assert!(EntryPoint::Single.check_limits(&limits).is_ok());
+        let err = EntryPoint::Tile.check_limits(&limits).unwrap_err();
+        assert!(err.contains("6 bind groups"), "{}", err);
+        assert!(EntryPoint::Tile
+            .check_limits(&wgpu::Limits {
+                max_bind_groups: 6,
+                ..limits
+            })
+            .is_ok());
+    }
+}
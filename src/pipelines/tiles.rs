@@ -0,0 +1,242 @@
+use crate::utils::{dispatch_optimal, NonZeroSized};
+
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Side of a square tile in pixels.
+const TILE_SIZE: u32 = 128;
+/// Workgroup size of the `tile` entry point.
+const WORKGROUP_SIZE: u32 = 16;
+
+/// Pixel offset of a tile, mirrors `Offset` in `raycast_compute.wgsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct TileOffset {
+    x: f32,
+    y: f32,
+}
+
+/// Splits the backbuffer into tiles and hands out as many of them per frame
+/// as fit into the time budget, so slow settings converge over several frames
+/// instead of stalling the window.
+pub(crate) struct Tiles {
+    size: (u32, u32),
+    /// Distance between offsets in the buffer, a multiple of the dynamic offset alignment.
+    stride: u32,
+    pub(crate) bind_group: wgpu::BindGroup,
+
+    batches: Batches,
+    /// Time per tile of the last batch, from submitting it to the queue reporting it done.
+    tile_time: Arc<Mutex<Option<Duration>>>,
+}
+
+impl Tiles {
+    pub(crate) const DESC: wgpu::BindGroupLayoutDescriptor<'static> =
+        wgpu::BindGroupLayoutDescriptor {
+            label: Some("Tile Offsets Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: true,
+                    min_binding_size: Some(TileOffset::SIZE),
+                },
+                count: None,
+            }],
+        };
+
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        (width, height): (u32, u32),
+        budget: Duration,
+    ) -> Self {
+        let (columns, rows) = (
+            dispatch_optimal(width, TILE_SIZE),
+            dispatch_optimal(height, TILE_SIZE),
+        );
+        let count = columns * rows;
+        let alignment = device.limits().min_storage_buffer_offset_alignment;
+        let stride = wgpu::util::align_to(TileOffset::SIZE.get() as u32, alignment);
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Tile Offsets Buffer"),
+            size: (count * stride) as _,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut contents = vec![0; buffer.size() as usize];
+        for (i, chunk) in contents.chunks_exact_mut(stride as usize).enumerate() {
+            let i = i as u32;
+            let offset = TileOffset {
+                x: (i % columns * TILE_SIZE) as f32,
+                y: (i / columns * TILE_SIZE) as f32,
+            };
+            chunk[..TileOffset::SIZE.get() as usize].copy_from_slice(bytemuck::bytes_of(&offset));
+        }
+        queue.write_buffer(&buffer, 0, &contents);
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Tile Offsets Bind Group"),
+            layout: &device.create_bind_group_layout(&Self::DESC),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &buffer,
+                    offset: 0,
+                    size: Some(TileOffset::SIZE),
+                }),
+            }],
+        });
+
+        Self {
+            size: (width, height),
+            stride,
+            bind_group,
+
+            batches: Batches::new(count, budget),
+            tile_time: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Recreates the offsets when the backbuffer changed size.
+    pub(crate) fn resize(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, size: (u32, u32)) {
+        if self.size != size {
+            let per_frame = self.batches.per_frame;
+            *self = Self::new(device, queue, size, self.batches.budget);
+            self.batches.per_frame = per_frame;
+        }
+    }
+
+    /// Tiles to draw this frame, all of them when `whole_frame` is set.
+    /// Adjusts the batch size to the time the previous one took on the GPU.
+    pub(crate) fn next_batch(&mut self, whole_frame: bool) -> Range<u32> {
+        let tile_time = self.tile_time.lock().unwrap().take();
+        self.batches.next(tile_time, whole_frame)
+    }
+
+    /// Starts the next batch from the first tile.
+    pub(crate) fn restart(&mut self) {
+        self.batches.next = 0;
+    }
+
+    /// Whether the last batch drew the final tile of the backbuffer.
+    pub(crate) fn pass_finished(&self) -> bool {
+        self.batches.next == 0
+    }
+
+    /// Dynamic offset of the `tile`-th offset in the buffer.
+    pub(crate) fn offset(&self, tile: u32) -> wgpu::DynamicOffset {
+        tile * self.stride
+    }
+
+    /// Workgroups covering one tile.
+    pub(crate) fn workgroups(&self) -> u32 {
+        TILE_SIZE / WORKGROUP_SIZE
+    }
+
+    /// Starts timing `batch`, which was just submitted to `queue`.
+    pub(crate) fn submitted(&self, queue: &wgpu::Queue, batch: Range<u32>) {
+        let submitted = Instant::now();
+        let tile_time = self.tile_time.clone();
+        queue.on_submitted_work_done(move || {
+            *tile_time.lock().unwrap() = Some(submitted.elapsed() / batch.len() as u32);
+        });
+    }
+}
+
+/// Walks over `count` tiles in batches sized to fit the time budget.
+struct Batches {
+    count: u32,
+    next: u32,
+    per_frame: u32,
+    budget: Duration,
+}
+
+impl Batches {
+    fn new(count: u32, budget: Duration) -> Self {
+        Self {
+            count,
+            next: 0,
+            per_frame: 1,
+            budget,
+        }
+    }
+
+    /// `tile_time` is the time per tile of the previous batch, if it has finished by now.
+    fn next(&mut self, tile_time: Option<Duration>, whole_frame: bool) -> Range<u32> {
+        if let Some(tile_time) = tile_time {
+            let fits = self.budget.as_secs_f32() / tile_time.as_secs_f32().max(1e-6);
+            // Grow carefully, a single slow frame hurts more than a few extra frames to converge.
+            self.per_frame = (fits as u32).clamp(1, (self.per_frame * 2).min(self.count));
+        }
+
+        if whole_frame {
+            self.next = 0;
+            return 0..self.count;
+        }
+        let start = self.next;
+        let end = (start + self.per_frame).min(self.count);
+        self.next = if end == self.count { 0 } else { end };
+        start..end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: Duration = Duration::from_millis(1);
+    /// Budgets and tile times that are exact in f32.
+    const SECOND: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn batches_grow_by_at_most_double() {
+        let mut batches = Batches::new(100, SECOND);
+        assert_eq!(batches.next(None, false), 0..1);
+        // 32 tiles would fit, but the batch only doubles per frame.
+        let fast = SECOND / 32;
+        assert_eq!(batches.next(Some(fast), false), 1..3);
+        assert_eq!(batches.next(Some(fast), false), 3..7);
+        assert_eq!(batches.next(Some(fast), false), 7..15);
+        assert_eq!(batches.next(Some(fast), false), 15..31);
+        assert_eq!(batches.next(Some(fast), false), 31..63);
+        assert_eq!(batches.next(Some(fast), false), 63..95);
+        // Without a new measurement the size stays put.
+        assert_eq!(batches.next(None, false), 95..100);
+    }
+
+    #[test]
+    fn slow_tiles_shrink_the_batch() {
+        let mut batches = Batches::new(100, SECOND);
+        batches.per_frame = 16;
+        assert_eq!(batches.next(Some(SECOND / 4), false), 0..4);
+        assert_eq!(batches.next(Some(2 * SECOND), false), 4..5);
+        assert_eq!(batches.next(Some(Duration::ZERO), false), 5..7);
+    }
+
+    #[test]
+    fn batches_wrap_around_at_the_last_tile() {
+        let mut batches = Batches::new(5, 10 * MS);
+        batches.per_frame = 3;
+        assert_eq!(batches.next(None, false), 0..3);
+        assert_ne!(batches.next, 0);
+        assert_eq!(batches.next(None, false), 3..5);
+        assert_eq!(batches.next, 0);
+        assert_eq!(batches.next(None, false), 0..3);
+        // The batch never outgrows the tile count.
+        assert_eq!(batches.next(Some(Duration::from_nanos(1)), false), 3..5);
+        assert_eq!(batches.per_frame, 5);
+    }
+
+    #[test]
+    fn whole_frames_draw_every_tile_and_restart() {
+        let mut batches = Batches::new(6, 10 * MS);
+        assert_eq!(batches.next(None, false), 0..1);
+        assert_eq!(batches.next(None, true), 0..6);
+        assert_eq!(batches.next, 0);
+        assert_eq!(batches.next(None, false), 0..1);
+    }
+}
@@ -1,33 +1,255 @@
-use crate::{GlobalUniformBinding, Uniform};
+use crate::loaders::Volume;
+use crate::utils::capture::{copy_texture_to_staging, read_staging};
+use crate::utils::{dispatch_optimal, validate};
+use crate::{GlobalUniformBinding, NonZeroSized, Uniform};
+
+use bytemuck::{Pod, Zeroable};
+use half::f16;
+use serde::Deserialize;
+use wgpu::util::DeviceExt;
+
+/// Volume generators available in `xor.wgsl`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Generator {
+    /// Fractal value noise.
+    #[default]
+    Noise,
+    /// The XOR pattern the project is named after.
+    Xor,
+    /// Power N extension of the Mandelbrot set.
+    Mandelbulb,
+    /// Sponge of recursively carved cubes.
+    Menger,
+    /// Triply periodic minimal surface.
+    Gyroid,
+}
+
+impl Generator {
+    pub(crate) const ALL: [Self; 5] = [
+        Self::Noise,
+        Self::Xor,
+        Self::Mandelbulb,
+        Self::Menger,
+        Self::Gyroid,
+    ];
+
+    pub(crate) fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+
+    fn entry_point(self) -> &'static str {
+        match self {
+            Self::Noise => "cs_main",
+            Self::Xor => "cs_xor",
+            Self::Mandelbulb => "cs_mandelbulb",
+            Self::Menger => "cs_menger",
+            Self::Gyroid => "cs_gyroid",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct NoiseParams {
+    /// Noise cells across the volume.
+    pub(crate) frequency: f32,
+    pub(crate) octaves: u32,
+}
+
+impl Default for NoiseParams {
+    fn default() -> Self {
+        Self {
+            frequency: 32.,
+            octaves: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct XorParams {
+    pub(crate) frequency: f32,
+    /// Levels the pattern is quantized to.
+    pub(crate) resolution: f32,
+}
+
+impl Default for XorParams {
+    fn default() -> Self {
+        Self {
+            frequency: 32.,
+            resolution: 25.,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct MandelbulbParams {
+    pub(crate) power: f32,
+    pub(crate) iterations: u32,
+}
+
+impl Default for MandelbulbParams {
+    fn default() -> Self {
+        Self {
+            power: 8.,
+            iterations: 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct MengerParams {
+    /// Levels of holes, each one a third the size of the previous.
+    pub(crate) iterations: u32,
+}
+
+impl Default for MengerParams {
+    fn default() -> Self {
+        Self { iterations: 4 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct GyroidParams {
+    /// Radians of the surface across the volume.
+    pub(crate) frequency: f32,
+    /// Half width of the sheet around the surface.
+    pub(crate) thickness: f32,
+}
+
+impl Default for GyroidParams {
+    fn default() -> Self {
+        Self {
+            frequency: 20.,
+            thickness: 0.3,
+        }
+    }
+}
+
+/// Parameters of every generator, whether it is selected or not.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct GeneratorParams {
+    pub(crate) noise: NoiseParams,
+    pub(crate) xor: XorParams,
+    pub(crate) mandelbulb: MandelbulbParams,
+    pub(crate) menger: MengerParams,
+    pub(crate) gyroid: GyroidParams,
+}
+
+/// Mirrors `Params` in `xor.wgsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct GeneratorUniform {
+    noise_frequency: f32,
+    noise_octaves: u32,
+    xor_frequency: f32,
+    xor_resolution: f32,
+    mandelbulb_power: f32,
+    mandelbulb_iterations: u32,
+    menger_iterations: u32,
+    gyroid_frequency: f32,
+    gyroid_thickness: f32,
+    _padding: [u32; 3],
+}
+
+impl From<GeneratorParams> for GeneratorUniform {
+    fn from(params: GeneratorParams) -> Self {
+        Self {
+            noise_frequency: params.noise.frequency,
+            noise_octaves: params.noise.octaves,
+            xor_frequency: params.xor.frequency,
+            xor_resolution: params.xor.resolution,
+            mandelbulb_power: params.mandelbulb.power,
+            mandelbulb_iterations: params.mandelbulb.iterations,
+            menger_iterations: params.menger.iterations,
+            gyroid_frequency: params.gyroid.frequency,
+            gyroid_thickness: params.gyroid.thickness,
+            _padding: [0; 3],
+        }
+    }
+}
+
+/// Mirrors `Region` in `xor.wgsl`, voxels from `min` up to but excluding `max`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct RegionUniform {
+    min: [u32; 3],
+    _padding: u32,
+    max: [u32; 3],
+    _padding2: u32,
+}
+
+impl RegionUniform {
+    fn new(min: [u32; 3], max: [u32; 3]) -> Self {
+        Self {
+            min,
+            _padding: 0,
+            max,
+            _padding2: 0,
+        }
+    }
+}
 
 pub(crate) struct XorCompute {
-    pipeline: wgpu::ComputePipeline,
+    /// One per generator, in the order of [`Generator::ALL`].
+    pipelines: Vec<wgpu::ComputePipeline>,
+    generator: Generator,
+    params_buffer: wgpu::Buffer,
+    /// Params with a region covering the whole volume.
+    params_bind_group: wgpu::BindGroup,
+    /// Params with the region [`XorCompute::record_dirty`] regenerates.
+    dirty_buffer: wgpu::Buffer,
+    dirty_bind_group: wgpu::BindGroup,
+    dirty_region: RegionUniform,
+    pub(crate) size: wgpu::Extent3d,
+    texture: wgpu::Texture,
+    pub(crate) volume_view: wgpu::TextureView,
+    pub(crate) normal_view: wgpu::TextureView,
     pub(crate) storage_bind_group: wgpu::BindGroup,
-    pub(crate) _render_bind_group: wgpu::BindGroup,
+    pub(crate) render_bind_group: wgpu::BindGroup,
 }
 
 impl XorCompute {
     pub(crate) const DESC_COMPUTE: wgpu::BindGroupLayoutDescriptor<'static> =
         wgpu::BindGroupLayoutDescriptor {
             label: Some("Compute Storage Texture Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::StorageTexture {
+                    access: wgpu::StorageTextureAccess::WriteOnly,
+                    format: wgpu::TextureFormat::Rgba16Float,
+                    view_dimension: wgpu::TextureViewDimension::D3,
+                },
+                count: None,
+            }],
+        };
+
+    pub(crate) const DESC_PARAMS: wgpu::BindGroupLayoutDescriptor<'static> =
+        wgpu::BindGroupLayoutDescriptor {
+            label: Some("Generator Params Layout"),
             entries: &[
                 wgpu::BindGroupLayoutEntry {
                     binding: 0,
                     visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::StorageTexture {
-                        access: wgpu::StorageTextureAccess::ReadWrite,
-                        format: wgpu::TextureFormat::Rgba16Float,
-                        view_dimension: wgpu::TextureViewDimension::D3,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(GeneratorUniform::SIZE),
                     },
                     count: None,
                 },
                 wgpu::BindGroupLayoutEntry {
                     binding: 1,
                     visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::StorageTexture {
-                        access: wgpu::StorageTextureAccess::ReadWrite,
-                        format: wgpu::TextureFormat::Rgba16Float,
-                        view_dimension: wgpu::TextureViewDimension::D3,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(RegionUniform::SIZE),
                     },
                     count: None,
                 },
@@ -73,26 +295,29 @@ impl XorCompute {
     pub(crate) fn new(
         device: &wgpu::Device,
         module_desc: wgpu::ShaderModuleDescriptor<'_>,
+        (width, height, depth): (u32, u32, u32),
+        generator: Generator,
     ) -> Self {
         let size = wgpu::Extent3d {
-            width: 256,
-            height: 256,
-            depth_or_array_layers: 256,
+            width,
+            height,
+            depth_or_array_layers: depth,
         };
 
-        let xor_view = {
-            let xor_texture = device.create_texture(&wgpu::TextureDescriptor {
-                label: Some("XOR Texture"),
-                size,
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D3,
-                format: wgpu::TextureFormat::Rgba16Float,
-                usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
-                view_formats: &[],
-            });
-            xor_texture.create_view(&Default::default())
-        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("XOR Texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            format: wgpu::TextureFormat::Rgba16Float,
+            usage: wgpu::TextureUsages::STORAGE_BINDING
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let xor_view = texture.create_view(&Default::default());
 
         let normal_view = {
             let normal_texture = device.create_texture(&wgpu::TextureDescriptor {
@@ -108,24 +333,53 @@ impl XorCompute {
             normal_texture.create_view(&Default::default())
         };
 
-        let pipeline = {
+        let pipelines = {
             let module = device.create_shader_module(module_desc);
-            Self::make_pipeline(device, module)
+            Self::make_pipelines(device, &module)
         };
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Generator Params Buffer"),
+            contents: bytemuck::bytes_of(&GeneratorUniform::from(GeneratorParams::default())),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let whole_region = RegionUniform::new([0; 3], [width, height, depth]);
+        let region_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Generator Region Buffer"),
+            contents: bytemuck::bytes_of(&whole_region),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let dirty_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Generator Dirty Region Buffer"),
+            contents: bytemuck::bytes_of(&whole_region),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let params_layout = device.create_bind_group_layout(&Self::DESC_PARAMS);
+        let make_params_bind_group = |region_buffer: &wgpu::Buffer| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Generator Params Bind Group"),
+                layout: &params_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: params_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: region_buffer.as_entire_binding(),
+                    },
+                ],
+            })
+        };
+        let params_bind_group = make_params_bind_group(&region_buffer);
+        let dirty_bind_group = make_params_bind_group(&dirty_buffer);
 
         let storage_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("XOR Compute Bind Group"),
             layout: &device.create_bind_group_layout(&Self::DESC_COMPUTE),
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&xor_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::TextureView(&normal_view),
-                },
-            ],
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&xor_view),
+            }],
         });
 
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
@@ -155,30 +409,162 @@ impl XorCompute {
         });
 
         Self {
-            pipeline,
+            pipelines,
+            generator,
+            params_buffer,
+            params_bind_group,
+            dirty_buffer,
+            dirty_bind_group,
+            dirty_region: whole_region,
+            size,
+            texture,
+            volume_view: xor_view,
+            normal_view,
             storage_bind_group,
-            _render_bind_group: render_bind_group,
+            render_bind_group,
         }
     }
 
-    fn make_pipeline(device: &wgpu::Device, module: wgpu::ShaderModule) -> wgpu::ComputePipeline {
+    pub(crate) fn generator(&self) -> Generator {
+        self.generator
+    }
+
+    /// Takes effect the next time the volume is generated.
+    pub(crate) fn set_generator(&mut self, generator: Generator) {
+        self.generator = generator;
+    }
+
+    pub(crate) fn set_params(&self, queue: &wgpu::Queue, params: GeneratorParams) {
+        let uniform = GeneratorUniform::from(params);
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&uniform));
+    }
+
+    /// Limits [`XorCompute::record_dirty`] to a box given in fractions of the volume,
+    /// rounded outwards to whole voxels.
+    pub(crate) fn set_dirty_box(&mut self, queue: &wgpu::Queue, min: [f32; 3], max: [f32; 3]) {
+        let size = [
+            self.size.width,
+            self.size.height,
+            self.size.depth_or_array_layers,
+        ];
+        let min = std::array::from_fn(|i| (min[i] * size[i] as f32).floor() as u32);
+        let max = std::array::from_fn(|i| {
+            ((max[i] * size[i] as f32).ceil() as u32).clamp(min[i] + 1, size[i])
+        });
+        self.dirty_region = RegionUniform::new(min, max);
+        queue.write_buffer(
+            &self.dirty_buffer,
+            0,
+            bytemuck::bytes_of(&self.dirty_region),
+        );
+    }
+
+    /// Voxels [`XorCompute::record_dirty`] regenerates, from the first up to but excluding the second.
+    pub(crate) fn dirty_region(&self) -> ([u32; 3], [u32; 3]) {
+        (self.dirty_region.min, self.dirty_region.max)
+    }
+
+    /// Replaces the generated voxels with `volume`, which must match the texture size.
+    /// Normals are left untouched, see `NormalsCompute`.
+    pub(crate) fn upload(&self, queue: &wgpu::Queue, volume: &Volume) {
+        let (width, height, depth) = volume.size;
+        debug_assert_eq!(
+            (width, height, depth),
+            (
+                self.size.width,
+                self.size.height,
+                self.size.depth_or_array_layers
+            )
+        );
+        // One slice at a time keeps the staging copy small for big volumes.
+        for z in 0..depth {
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &self.texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d { x: 0, y: 0, z },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                &volume.slice_bytes(z),
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(width * 8),
+                    rows_per_image: Some(height),
+                },
+                wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+    }
+
+    /// Reads the voxels back from the GPU, for instance to export a generated volume.
+    pub(crate) fn download(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<Volume, String> {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Volume Download Encoder"),
+        });
+        let (buffer, dims) = copy_texture_to_staging(device, &mut encoder, &self.texture);
+        queue.submit(Some(encoder.finish()));
+        let bytes = read_staging(device, &buffer, &dims)?;
+
+        let voxels = bytes
+            .chunks_exact(8)
+            .map(|c| std::array::from_fn(|i| f16::from_le_bytes([c[2 * i], c[2 * i + 1]])))
+            .collect();
+        let size = (
+            self.size.width,
+            self.size.height,
+            self.size.depth_or_array_layers,
+        );
+        Ok(Volume::new(size, voxels))
+    }
+
+    /// Swaps in pipelines built from `module`, keeping the old ones if they fail to validate.
+    pub(crate) fn rebuild(&mut self, device: &wgpu::Device, module: wgpu::ShaderModule) {
+        match validate(device, || Self::make_pipelines(device, &module)) {
+            Ok(pipelines) => self.pipelines = pipelines,
+            Err(err) => eprintln!("Failed to rebuild XOR pipelines: {}", err),
+        }
+    }
+
+    fn make_pipelines(
+        device: &wgpu::Device,
+        module: &wgpu::ShaderModule,
+    ) -> Vec<wgpu::ComputePipeline> {
         let global_bind_group_layout = device.create_bind_group_layout(&Uniform::DESC);
         let storage_texture_layout = device.create_bind_group_layout(&Self::DESC_COMPUTE);
+        let params_layout = device.create_bind_group_layout(&Self::DESC_PARAMS);
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("XOR Pipeline Layout"),
-            bind_group_layouts: &[&global_bind_group_layout, &storage_texture_layout],
+            bind_group_layouts: &[
+                &global_bind_group_layout,
+                &storage_texture_layout,
+                &params_layout,
+            ],
             push_constant_ranges: &[],
         });
-        device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some("Generate XOR Texture"),
-            layout: Some(&pipeline_layout),
-            module: &module,
-            entry_point: "cs_main",
-        })
+        Generator::ALL
+            .iter()
+            .map(|generator| {
+                device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some(generator.entry_point()),
+                    layout: Some(&pipeline_layout),
+                    module,
+                    entry_point: generator.entry_point(),
+                })
+            })
+            .collect()
     }
 }
 
 impl<'a> XorCompute {
+    /// Generates the whole volume.
     pub(crate) fn record<'pass>(
         &'a self,
         cpass: &mut wgpu::ComputePass<'pass>,
@@ -186,10 +572,45 @@ impl<'a> XorCompute {
     ) where
         'a: 'pass,
     {
-        cpass.set_pipeline(&self.pipeline);
+        let extent = [
+            self.size.width,
+            self.size.height,
+            self.size.depth_or_array_layers,
+        ];
+        self.record_region(cpass, uniform_bind_group, &self.params_bind_group, extent);
+    }
+
+    /// Generates only the box set by [`XorCompute::set_dirty_box`], the rest keeps its voxels.
+    pub(crate) fn record_dirty<'pass>(
+        &'a self,
+        cpass: &mut wgpu::ComputePass<'pass>,
+        uniform_bind_group: &'a GlobalUniformBinding,
+    ) where
+        'a: 'pass,
+    {
+        let RegionUniform { min, max, .. } = self.dirty_region;
+        let extent = std::array::from_fn(|i| max[i] - min[i]);
+        self.record_region(cpass, uniform_bind_group, &self.dirty_bind_group, extent);
+    }
+
+    fn record_region<'pass>(
+        &'a self,
+        cpass: &mut wgpu::ComputePass<'pass>,
+        uniform_bind_group: &'a GlobalUniformBinding,
+        params_bind_group: &'a wgpu::BindGroup,
+        [width, height, depth]: [u32; 3],
+    ) where
+        'a: 'pass,
+    {
+        cpass.set_pipeline(&self.pipelines[self.generator as usize]);
 
         cpass.set_bind_group(0, &uniform_bind_group.binding, &[]);
         cpass.set_bind_group(1, &self.storage_bind_group, &[]);
-        cpass.dispatch_workgroups(32, 32, 32);
+        cpass.set_bind_group(2, params_bind_group, &[]);
+        cpass.dispatch_workgroups(
+            dispatch_optimal(width, 8),
+            dispatch_optimal(height, 8),
+            dispatch_optimal(depth, 8),
+        );
     }
 }
@@ -0,0 +1,50 @@
+use crate::context::Context;
+
+use std::path::PathBuf;
+
+/// Settings for exporting an animation as an image sequence.
+#[derive(Debug, Clone)]
+pub(crate) struct Recording {
+    pub(crate) dir: PathBuf,
+    pub(crate) fps: f32,
+    pub(crate) frames: u32,
+}
+
+/// Writes every rendered frame as a numbered PNG and EXR pair.
+pub(crate) struct Recorder {
+    dir: PathBuf,
+    frames: u32,
+    recorded: u32,
+}
+
+impl Recorder {
+    /// Creates the output folder and puts `ctx` on the fixed timestep of `recording`.
+    pub(crate) fn new(ctx: &mut Context, recording: Recording) -> Result<Self, String> {
+        std::fs::create_dir_all(&recording.dir)
+            .map_err(|e| format!("{}: {}", recording.dir.display(), e))?;
+        ctx.set_fixed_timestep(recording.fps);
+        Ok(Self {
+            dir: recording.dir,
+            frames: recording.frames,
+            recorded: 0,
+        })
+    }
+
+    pub(crate) fn is_finished(&self) -> bool {
+        self.recorded >= self.frames
+    }
+
+    /// Saves the frame that was just presented.
+    pub(crate) fn record(&mut self, ctx: &Context) -> Result<(), String> {
+        let (ldr, hdr) = ctx.capture_frame()?;
+        let stem = format!("frame_{:05}", self.recorded);
+        ldr.save_png(self.dir.join(&stem).with_extension("png"))?;
+        hdr.save_exr(self.dir.join(&stem).with_extension("exr"))?;
+
+        self.recorded += 1;
+        if self.is_finished() {
+            eprintln!("Recorded {} frames to {}", self.frames, self.dir.display());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,684 @@
+use crate::camera::Camera;
+use crate::loaders::{Endian, VoxelType};
+use crate::pipelines::auto_exposure::AutoExposureParams;
+use crate::pipelines::bloom::BloomParams;
+use crate::pipelines::normals::NormalsParams;
+use crate::pipelines::post::{
+    ChromaticAberrationParams, FilmGrainParams, LutParams, PostEffect, PostParams, VignetteParams,
+};
+use crate::pipelines::present::{PresentParams, ToneMapping, Upscaling};
+use crate::pipelines::raycast::{EntryPoint, RaycastUniform, VolumeFilter};
+use crate::pipelines::xor_compute::{
+    Generator, GeneratorParams, GyroidParams, MandelbulbParams, MengerParams, NoiseParams,
+    XorParams,
+};
+
+use serde::Deserialize;
+
+use std::path::{Path, PathBuf};
+
+/// A reproducible setup loaded from a TOML file, every section and field is optional.
+///
+/// ```toml
+/// [camera]
+/// zoom = 3.0
+/// pitch = -0.5
+/// yaw = 1.0
+/// target = [0.0, 0.0, 0.0]
+/// fov = 90.0
+///
+/// [volume]
+/// # noise, xor, mandelbulb, menger or gyroid, G cycles through them.
+/// generator = "noise"
+/// size = [256, 256, 256]
+/// # Loads voxels instead of generating them, relative to the scene file.
+/// # `size`, `voxel_type` and `endian` describe `.raw` files.
+/// file = "head.raw"
+/// voxel_type = "u16"
+/// endian = "little"
+///
+/// [volume.noise]
+/// frequency = 32.0
+/// octaves = 3
+///
+/// [volume.xor]
+/// frequency = 32.0
+/// resolution = 25.0
+///
+/// [volume.mandelbulb]
+/// power = 8.0
+/// iterations = 8
+///
+/// [volume.menger]
+/// iterations = 4
+///
+/// [volume.gyroid]
+/// frequency = 20.0
+/// thickness = 0.3
+///
+/// [volume.animation]
+/// # Regenerates the volume every `interval` frames so it follows the time.
+/// enabled = false
+/// interval = 1
+/// # Part of the volume that is regenerated, in fractions of its size.
+/// dirty_min = [0.0, 0.0, 0.0]
+/// dirty_max = [1.0, 1.0, 1.0]
+///
+/// [normals]
+/// # central or sobel gradient of the opacity, N cycles through kernels and sizes.
+/// kernel = "central"
+/// # Side of the kernel in voxels, 3 or 5.
+/// size = 3
+///
+/// [raycast]
+/// step_scale = 1.0
+/// clear_color = [0.023, 0.02, 0.02, 0.0]
+/// light_direction = [0.0, -1.0, 0.0]
+/// opacity_cutoff = 0.95
+/// # JSON transfer function, relative to the scene file.
+/// transfer_function = "bone.json"
+/// # `single` draws every pixel each frame, `tile` spreads them over frames.
+/// entry_point = "single"
+/// tile_budget = 8.0
+/// # nearest, trilinear or tricubic reads of the volume, F cycles through them.
+/// filter = "trilinear"
+///
+/// [tonemap]
+/// # aces, none, reinhard, reinhard_extended, uncharted2, agx or neutral
+/// operator = "aces"
+/// exposure = 0.0
+/// white_point = 11.2
+///
+/// [auto_exposure]
+/// enabled = false
+/// # Range of average luminance in EV that gets compensated.
+/// min_ev = -8.0
+/// max_ev = 4.0
+/// speed = 3.0
+///
+/// [present]
+/// # nearest, bilinear, quadratic, bicubic, lanczos or cas
+/// upscale = "bilinear"
+///
+/// [grading]
+/// # .cube files relative to the scene file, L cycles through them.
+/// luts = ["warm.cube", "bleach.cube"]
+/// strength = 1.0
+///
+/// [post]
+/// # Effects in the order they run, each can appear in any position.
+/// chain = ["bloom", "lut", "chromatic_aberration", "vignette", "film_grain"]
+///
+/// [post.vignette]
+/// intensity = 0.5
+/// radius = 0.5
+/// smoothness = 0.5
+///
+/// [post.film_grain]
+/// intensity = 0.1
+/// size = 1.0
+///
+/// [post.chromatic_aberration]
+/// strength = 0.01
+///
+/// [post.bloom]
+/// # Brightness where the glow starts, softened over the knee.
+/// threshold = 1.0
+/// knee = 0.5
+/// intensity = 0.5
+/// radius = 1.0
+///
+/// [post.lut]
+/// # Grades the HDR image before tone mapping, relative to the scene file.
+/// file = "film.cube"
+/// strength = 1.0
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Scene {
+    pub(crate) camera: CameraSettings,
+    pub(crate) volume: VolumeSettings,
+    pub(crate) normals: NormalsParams,
+    pub(crate) raycast: RaycastSettings,
+    pub(crate) tonemap: ToneMapSettings,
+    pub(crate) auto_exposure: AutoExposureSettings,
+    pub(crate) present: PresentSettings,
+    pub(crate) grading: GradingSettings,
+    pub(crate) post: PostSettings,
+}
+
+impl Scene {
+    pub(crate) fn load(path: &Path) -> Result<Self, String> {
+        let source =
+            std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut scene: Self =
+            toml::from_str(&source).map_err(|e| format!("{}: {}", path.display(), e))?;
+        if let Some(dir) = path.parent() {
+            let files = [&mut scene.volume.file, &mut scene.raycast.transfer_function];
+            for file in files.into_iter().flatten() {
+                *file = dir.join(&*file);
+            }
+            let luts = scene
+                .grading
+                .luts
+                .iter_mut()
+                .chain(&mut scene.post.lut.file);
+            for lut in luts {
+                *lut = dir.join(&*lut);
+            }
+        }
+        scene
+            .validate()
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(scene)
+    }
+
+    /// Catches values that parse fine but would break rendering.
+    fn validate(&self) -> Result<(), String> {
+        let camera = &self.camera;
+        ensure(
+            "camera.fov",
+            camera.fov,
+            "between 0 and 180 degrees",
+            |fov| fov > 0. && fov < 180.,
+        )?;
+        ensure_positive("camera.zoom", camera.zoom)?;
+        if self.volume.size.contains(&0) {
+            return Err(format!(
+                "volume.size must not contain zeros, got {:?}",
+                self.volume.size
+            ));
+        }
+        let volume = &self.volume;
+        if !(1..=8).contains(&volume.noise.octaves) {
+            return Err(format!(
+                "volume.noise.octaves must be between 1 and 8, got {}",
+                volume.noise.octaves
+            ));
+        }
+        ensure(
+            "volume.xor.resolution",
+            volume.xor.resolution,
+            "at least 1",
+            |r| r >= 1.,
+        )?;
+        if !(1..=32).contains(&volume.mandelbulb.iterations) {
+            return Err(format!(
+                "volume.mandelbulb.iterations must be between 1 and 32, got {}",
+                volume.mandelbulb.iterations
+            ));
+        }
+        ensure(
+            "volume.mandelbulb.power",
+            volume.mandelbulb.power,
+            "above 1",
+            |p| p > 1.,
+        )?;
+        if !(1..=6).contains(&volume.menger.iterations) {
+            return Err(format!(
+                "volume.menger.iterations must be between 1 and 6, got {}",
+                volume.menger.iterations
+            ));
+        }
+        ensure_positive("volume.gyroid.thickness", volume.gyroid.thickness)?;
+        let animation = &volume.animation;
+        if animation.interval == 0 {
+            return Err("volume.animation.interval must be at least 1".into());
+        }
+        if !(0..3).all(|i| {
+            0. <= animation.dirty_min[i]
+                && animation.dirty_min[i] < animation.dirty_max[i]
+                && animation.dirty_max[i] <= 1.
+        }) {
+            return Err(format!(
+                "volume.animation.dirty_min must be below dirty_max within [0, 1], got {:?} and {:?}",
+                animation.dirty_min, animation.dirty_max
+            ));
+        }
+        if ![3, 5].contains(&self.normals.size) {
+            return Err(format!(
+                "normals.size must be 3 or 5, got {}",
+                self.normals.size
+            ));
+        }
+        let raycast = &self.raycast;
+        ensure_positive("raycast.step_scale", raycast.step_scale)?;
+        ensure(
+            "raycast.opacity_cutoff",
+            raycast.opacity_cutoff,
+            "in (0, 1]",
+            |c| c > 0. && c <= 1.,
+        )?;
+        ensure_positive("raycast.tile_budget", raycast.tile_budget)?;
+        if raycast.light_direction == [0.; 3] {
+            return Err("raycast.light_direction must not be zero".into());
+        }
+        ensure("tonemap.exposure", self.tonemap.exposure, "finite", |_| {
+            true
+        })?;
+        ensure_positive("tonemap.white_point", self.tonemap.white_point)?;
+        ensure_unit("grading.strength", self.grading.strength)?;
+        let post = &self.post;
+        ensure_unit("post.vignette.intensity", post.vignette.intensity)?;
+        ensure(
+            "post.film_grain.size",
+            post.film_grain.size,
+            "at least 1",
+            |s| s >= 1.,
+        )?;
+        let bloom = [
+            ("post.bloom.threshold", post.bloom.threshold),
+            ("post.bloom.knee", post.bloom.knee),
+            ("post.bloom.intensity", post.bloom.intensity),
+        ];
+        for (name, value) in bloom {
+            ensure(name, value, "non-negative", |v| v >= 0.)?;
+        }
+        ensure_positive("post.bloom.radius", post.bloom.radius)?;
+        ensure_unit("post.lut.strength", post.lut.strength)?;
+        let auto_exposure = &self.auto_exposure;
+        ensure_finite("auto_exposure.max_ev", auto_exposure.max_ev)?;
+        ensure(
+            "auto_exposure.min_ev",
+            auto_exposure.min_ev,
+            &format!("below max_ev {}", auto_exposure.max_ev),
+            |ev| ev < auto_exposure.max_ev,
+        )?;
+        ensure_positive("auto_exposure.speed", auto_exposure.speed)?;
+        Ok(())
+    }
+}
+
+/// Fails with a uniform message unless `value` is finite and passes `valid`,
+/// so NaN and infinities from the TOML file never get through.
+fn ensure(
+    name: &str,
+    value: f32,
+    expected: &str,
+    valid: impl FnOnce(f32) -> bool,
+) -> Result<(), String> {
+    if value.is_finite() && valid(value) {
+        Ok(())
+    } else {
+        Err(format!("{} must be {}, got {}", name, expected, value))
+    }
+}
+
+fn ensure_finite(name: &str, value: f32) -> Result<(), String> {
+    ensure(name, value, "finite", |_| true)
+}
+
+fn ensure_positive(name: &str, value: f32) -> Result<(), String> {
+    ensure(name, value, "positive", |v| v > 0.)
+}
+
+fn ensure_unit(name: &str, value: f32) -> Result<(), String> {
+    ensure(name, value, "in [0, 1]", |v| (0. ..=1.).contains(&v))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct CameraSettings {
+    pub(crate) zoom: f32,
+    pub(crate) pitch: f32,
+    pub(crate) yaw: f32,
+    pub(crate) target: [f32; 3],
+    /// Vertical field of view in degrees.
+    pub(crate) fov: f32,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        Self {
+            zoom: 3.,
+            pitch: -0.5,
+            yaw: 1.,
+            target: [0.; 3],
+            fov: 90.,
+        }
+    }
+}
+
+impl CameraSettings {
+    pub(crate) fn build(&self, aspect: f32) -> Camera {
+        let mut camera = Camera::new(self.zoom, self.pitch, self.yaw, self.target.into(), aspect);
+        camera.set_fovy(self.fov.to_radians());
+        camera
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct VolumeSettings {
+    pub(crate) generator: Generator,
+    pub(crate) size: [u32; 3],
+    pub(crate) file: Option<PathBuf>,
+    pub(crate) voxel_type: VoxelType,
+    pub(crate) endian: Endian,
+    pub(crate) noise: NoiseParams,
+    pub(crate) xor: XorParams,
+    pub(crate) mandelbulb: MandelbulbParams,
+    pub(crate) menger: MengerParams,
+    pub(crate) gyroid: GyroidParams,
+    pub(crate) animation: AnimationSettings,
+}
+
+impl Default for VolumeSettings {
+    fn default() -> Self {
+        Self {
+            generator: Generator::default(),
+            size: [256; 3],
+            file: None,
+            voxel_type: VoxelType::default(),
+            endian: Endian::default(),
+            noise: NoiseParams::default(),
+            xor: XorParams::default(),
+            mandelbulb: MandelbulbParams::default(),
+            menger: MengerParams::default(),
+            gyroid: GyroidParams::default(),
+            animation: AnimationSettings::default(),
+        }
+    }
+}
+
+impl VolumeSettings {
+    pub(crate) fn generator_params(&self) -> GeneratorParams {
+        GeneratorParams {
+            noise: self.noise,
+            xor: self.xor,
+            mandelbulb: self.mandelbulb,
+            menger: self.menger,
+            gyroid: self.gyroid,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct AnimationSettings {
+    pub(crate) enabled: bool,
+    /// Frames between regenerations.
+    pub(crate) interval: u32,
+    pub(crate) dirty_min: [f32; 3],
+    pub(crate) dirty_max: [f32; 3],
+}
+
+impl Default for AnimationSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval: 1,
+            dirty_min: [0.; 3],
+            dirty_max: [1.; 3],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct RaycastSettings {
+    /// Multiplier of the step between samples, smaller is slower but more precise.
+    pub(crate) step_scale: f32,
+    pub(crate) clear_color: [f32; 4],
+    pub(crate) light_direction: [f32; 3],
+    /// Rays stop once the accumulated opacity gets past this value.
+    pub(crate) opacity_cutoff: f32,
+    pub(crate) transfer_function: Option<PathBuf>,
+    pub(crate) entry_point: EntryPoint,
+    /// GPU time in milliseconds the `tile` entry point may spend per frame.
+    pub(crate) tile_budget: f32,
+    pub(crate) filter: VolumeFilter,
+}
+
+impl Default for RaycastSettings {
+    fn default() -> Self {
+        Self {
+            step_scale: 1.,
+            clear_color: [0.023, 0.02, 0.02, 0.],
+            light_direction: [0., -1., 0.],
+            opacity_cutoff: 0.95,
+            transfer_function: None,
+            entry_point: EntryPoint::default(),
+            tile_budget: 8.,
+            filter: VolumeFilter::default(),
+        }
+    }
+}
+
+impl RaycastSettings {
+    pub(crate) fn uniform(&self, box_extent: [f32; 3]) -> RaycastUniform {
+        RaycastUniform::new(
+            self.clear_color,
+            self.light_direction,
+            self.step_scale,
+            self.opacity_cutoff,
+            box_extent,
+            self.filter,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ToneMapSettings {
+    pub(crate) operator: ToneMapping,
+    /// Exposure adjustment in EV stops.
+    pub(crate) exposure: f32,
+    /// Linear value mapped to white by `reinhard_extended` and `uncharted2`.
+    pub(crate) white_point: f32,
+}
+
+impl Default for ToneMapSettings {
+    fn default() -> Self {
+        let params = PresentParams::default();
+        Self {
+            operator: params.tonemap,
+            exposure: params.exposure,
+            white_point: params.white_point,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct AutoExposureSettings {
+    pub(crate) enabled: bool,
+    pub(crate) min_ev: f32,
+    pub(crate) max_ev: f32,
+    /// Adaptation rate per second.
+    pub(crate) speed: f32,
+}
+
+impl Default for AutoExposureSettings {
+    fn default() -> Self {
+        let params = AutoExposureParams::default();
+        Self {
+            enabled: false,
+            min_ev: params.min_ev,
+            max_ev: params.max_ev,
+            speed: params.speed,
+        }
+    }
+}
+
+impl AutoExposureSettings {
+    pub(crate) fn params(&self) -> AutoExposureParams {
+        AutoExposureParams {
+            min_ev: self.min_ev,
+            max_ev: self.max_ev,
+            speed: self.speed,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct PresentSettings {
+    /// Filter used when the render scale is not 1.
+    pub(crate) upscale: Upscaling,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct GradingSettings {
+    /// The first one is active from the start.
+    pub(crate) luts: Vec<PathBuf>,
+    pub(crate) strength: f32,
+}
+
+impl Default for GradingSettings {
+    fn default() -> Self {
+        Self {
+            luts: Vec::new(),
+            strength: PresentParams::default().lut_strength,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct PostSettings {
+    pub(crate) chain: Vec<PostEffect>,
+    pub(crate) vignette: VignetteParams,
+    pub(crate) film_grain: FilmGrainParams,
+    pub(crate) chromatic_aberration: ChromaticAberrationParams,
+    pub(crate) bloom: BloomParams,
+    pub(crate) lut: PostLutSettings,
+}
+
+/// Settings of [`PostEffect::Lut`], the table lives in the chain instead of [`LutParams`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct PostLutSettings {
+    pub(crate) file: Option<PathBuf>,
+    pub(crate) strength: f32,
+}
+
+impl Default for PostLutSettings {
+    fn default() -> Self {
+        Self {
+            file: None,
+            strength: LutParams::default().strength,
+        }
+    }
+}
+
+impl PostSettings {
+    pub(crate) fn params(&self) -> PostParams {
+        PostParams {
+            vignette: self.vignette,
+            film_grain: self.film_grain,
+            chromatic_aberration: self.chromatic_aberration,
+            bloom: self.bloom,
+            lut: LutParams {
+                strength: self.lut.strength,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::utils::test_dir::TestDir;
+
+    /// Writes `source` to a scene file in a folder of its own and loads it.
+    fn load_source(name: &str, source: &str) -> Result<Scene, String> {
+        let dir = TestDir::new(name, &[("scene.toml", source.as_bytes())]);
+        Scene::load(&dir.join("scene.toml"))
+    }
+
+    #[test]
+    fn empty_scene_keeps_defaults() {
+        let scene = load_source("scene_empty", "").unwrap();
+        assert_eq!(scene.camera.fov, 90.);
+        assert_eq!(scene.volume.size, [256; 3]);
+        assert_eq!(scene.raycast.entry_point, EntryPoint::Single);
+    }
+
+    #[test]
+    fn partial_sections_keep_other_defaults() {
+        let scene = load_source(
+            "scene_partial",
+            "[camera]\nfov = 60.0\n\n[raycast]\nstep_scale = 0.5\n",
+        )
+        .unwrap();
+        assert_eq!(scene.camera.fov, 60.);
+        assert_eq!(scene.camera.zoom, 3.);
+        assert_eq!(scene.raycast.step_scale, 0.5);
+        assert_eq!(
+            scene.raycast.opacity_cutoff,
+            RaycastSettings::default().opacity_cutoff
+        );
+    }
+
+    #[test]
+    fn example_scene_is_valid() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes/xor.toml");
+        Scene::load(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        let cases = [
+            ("[camera]\nfov = 180.0", "camera.fov"),
+            ("[camera]\nzoom = 0.0", "camera.zoom"),
+            ("[camera]\nzoom = nan", "camera.zoom"),
+            ("[volume]\nsize = [256, 0, 256]", "volume.size"),
+            ("[volume.noise]\noctaves = 9", "volume.noise.octaves"),
+            (
+                "[volume.animation]\ninterval = 0",
+                "volume.animation.interval",
+            ),
+            (
+                "[volume.animation]\ndirty_min = [0.5, 0.0, 0.0]\ndirty_max = [0.5, 1.0, 1.0]",
+                "dirty_min",
+            ),
+            ("[normals]\nsize = 4", "normals.size"),
+            ("[raycast]\nstep_scale = -1.0", "raycast.step_scale"),
+            ("[raycast]\nstep_scale = inf", "raycast.step_scale"),
+            ("[raycast]\nopacity_cutoff = 1.5", "raycast.opacity_cutoff"),
+            (
+                "[raycast]\nlight_direction = [0.0, 0.0, 0.0]",
+                "raycast.light_direction",
+            ),
+            ("[tonemap]\nexposure = nan", "tonemap.exposure"),
+            ("[tonemap]\nwhite_point = -1.0", "tonemap.white_point"),
+            ("[grading]\nstrength = 2.0", "grading.strength"),
+            ("[post.bloom]\nradius = 0.0", "post.bloom.radius"),
+            ("[post.bloom]\nknee = -1.0", "post.bloom.knee"),
+            ("[post.lut]\nstrength = -0.5", "post.lut.strength"),
+            (
+                "[auto_exposure]\nmin_ev = 4.0\nmax_ev = 4.0",
+                "auto_exposure.min_ev",
+            ),
+        ];
+        for (i, (source, field)) in cases.into_iter().enumerate() {
+            let err = load_source(&format!("scene_invalid_{}", i), source).unwrap_err();
+            assert!(err.contains(field), "{}: {}", source, err);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_fields_and_values() {
+        assert!(load_source("scene_unknown_field", "[camera]\nfocus = 1.0").is_err());
+        assert!(load_source("scene_unknown_section", "[lighting]\nsun = true").is_err());
+        assert!(load_source("scene_unknown_value", "[tonemap]\noperator = \"filmic\"").is_err());
+    }
+
+    #[test]
+    fn relative_paths_follow_the_scene_file() {
+        let absolute = std::env::temp_dir().join("absolute.cube");
+        let source = format!(
+            "[volume]\nfile = \"data/head.nrrd\"\n\n[raycast]\ntransfer_function = \"bone.json\"\n\n[grading]\nluts = [\"warm.cube\", {:?}]\n\n[post.lut]\nfile = \"film.cube\"\n",
+            absolute
+        );
+        let dir = TestDir::new("scene_paths", &[("scene.toml", source.as_bytes())]);
+        let scene = Scene::load(&dir.join("scene.toml")).unwrap();
+        assert_eq!(scene.volume.file, Some(dir.join("data/head.nrrd")));
+        assert_eq!(scene.raycast.transfer_function, Some(dir.join("bone.json")));
+        assert_eq!(scene.grading.luts, [dir.join("warm.cube"), absolute]);
+        assert_eq!(scene.post.lut.file, Some(dir.join("film.cube")));
+    }
+}
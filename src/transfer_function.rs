@@ -0,0 +1,285 @@
+use serde::{Deserialize, Serialize};
+
+use std::path::Path;
+
+/// Texels in the baked lookup texture.
+pub(crate) const RESOLUTION: u32 = 256;
+
+/// Maps the density of a voxel to color and opacity through piecewise-linear
+/// control points, stored as JSON.
+///
+/// ```json
+/// {
+///   "modulate": false,
+///   "color": [
+///     { "position": 0.0, "color": [0.1, 0.2, 0.8] },
+///     { "position": 1.0, "color": [1.0, 0.9, 0.7] }
+///   ],
+///   "opacity": [
+///     { "position": 0.2, "opacity": 0.0 },
+///     { "position": 0.6, "opacity": 0.8 }
+///   ]
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct TransferFunction {
+    /// Multiply the color of the volume instead of replacing it.
+    #[serde(default)]
+    pub(crate) modulate: bool,
+    pub(crate) color: Vec<ColorPoint>,
+    pub(crate) opacity: Vec<OpacityPoint>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ColorPoint {
+    pub(crate) position: f32,
+    pub(crate) color: [f32; 3],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct OpacityPoint {
+    pub(crate) position: f32,
+    pub(crate) opacity: f32,
+}
+
+impl Default for TransferFunction {
+    /// Keeps the volume colors and follows the `smoothstep(0, 0.7, a³)`
+    /// opacity curve the raycaster used to hardcode.
+    fn default() -> Self {
+        const POINTS: u32 = 16;
+        let opacity = (0..=POINTS)
+            .map(|i| {
+                let position = i as f32 / POINTS as f32;
+                let t = (position.powi(3) / 0.7).clamp(0., 1.);
+                OpacityPoint {
+                    position,
+                    opacity: t * t * (3. - 2. * t),
+                }
+            })
+            .collect();
+        Self {
+            modulate: true,
+            color: vec![ColorPoint {
+                position: 0.,
+                color: [1.; 3],
+            }],
+            opacity,
+        }
+    }
+}
+
+impl TransferFunction {
+    pub(crate) fn load(path: &Path) -> Result<Self, String> {
+        let source =
+            std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut tf: Self =
+            serde_json::from_str(&source).map_err(|e| format!("{}: {}", path.display(), e))?;
+        tf.validate()
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        tf.color.sort_by(|a, b| a.position.total_cmp(&b.position));
+        tf.opacity.sort_by(|a, b| a.position.total_cmp(&b.position));
+        Ok(tf)
+    }
+
+    pub(crate) fn save(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, json + "\n").map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.color.is_empty() || self.opacity.is_empty() {
+            return Err("both `color` and `opacity` need at least one point".into());
+        }
+        let positions = self
+            .color
+            .iter()
+            .map(|p| p.position)
+            .chain(self.opacity.iter().map(|p| p.position));
+        for position in positions {
+            if !(0. ..=1.).contains(&position) {
+                return Err(format!("position {} is outside of [0, 1]", position));
+            }
+        }
+        let values = self
+            .color
+            .iter()
+            .flat_map(|p| p.color)
+            .chain(self.opacity.iter().map(|p| p.opacity));
+        for value in values {
+            if !(0. ..=1.).contains(&value) {
+                return Err(format!("value {} is outside of [0, 1]", value));
+            }
+        }
+        Ok(())
+    }
+
+    /// Samples the control points into `RESOLUTION` RGBA texels.
+    pub(crate) fn bake(&self) -> Vec<[f32; 4]> {
+        (0..RESOLUTION)
+            .map(|i| {
+                let x = i as f32 / (RESOLUTION - 1) as f32;
+                let [r, g, b] = interpolate(&self.color, x, |p| p.position, |p| p.color);
+                let [a] = interpolate(&self.opacity, x, |p| p.position, |p| [p.opacity]);
+                [r, g, b, a]
+            })
+            .collect()
+    }
+}
+
+/// Linear interpolation between sorted `points`, clamping outside of them.
+fn interpolate<P, const N: usize>(
+    points: &[P],
+    x: f32,
+    position: impl Fn(&P) -> f32,
+    value: impl Fn(&P) -> [f32; N],
+) -> [f32; N] {
+    let next = points.partition_point(|p| position(p) <= x);
+    match (next.checked_sub(1).map(|i| &points[i]), points.get(next)) {
+        (Some(a), Some(b)) => {
+            let t = (x - position(a)) / (position(b) - position(a)).max(f32::EPSILON);
+            let (a, b) = (value(a), value(b));
+            std::array::from_fn(|i| a[i] + (b[i] - a[i]) * t)
+        }
+        (Some(p), None) | (None, Some(p)) => value(p),
+        (None, None) => [0.; N],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::utils::test_dir::TestDir;
+
+    fn opacity(points: &[(f32, f32)]) -> Vec<OpacityPoint> {
+        points
+            .iter()
+            .map(|&(position, opacity)| OpacityPoint { position, opacity })
+            .collect()
+    }
+
+    fn sample(points: &[OpacityPoint], x: f32) -> f32 {
+        interpolate(points, x, |p| p.position, |p| [p.opacity])[0]
+    }
+
+    fn smoothstep_curve(a: f32) -> f32 {
+        let t = (a.powi(3) / 0.7).clamp(0., 1.);
+        t * t * (3. - 2. * t)
+    }
+
+    #[test]
+    fn interpolates_between_points() {
+        let points = opacity(&[(0.2, 0.), (0.6, 0.8), (1., 0.4)]);
+        assert_eq!(sample(&points, 0.2), 0.);
+        assert!((sample(&points, 0.4) - 0.4).abs() < 1e-6);
+        assert_eq!(sample(&points, 0.6), 0.8);
+        assert!((sample(&points, 0.8) - 0.6).abs() < 1e-6);
+    }
+
+    #[test]
+    fn clamps_outside_of_the_points() {
+        let points = opacity(&[(0.2, 0.3), (0.6, 0.8)]);
+        assert_eq!(sample(&points, 0.), 0.3);
+        assert_eq!(sample(&points, 1.), 0.8);
+    }
+
+    #[test]
+    fn single_point_is_constant() {
+        let points = opacity(&[(0.5, 0.25)]);
+        for x in [0., 0.5, 1.] {
+            assert_eq!(sample(&points, x), 0.25);
+        }
+        assert_eq!(sample(&[], 0.5), 0.);
+    }
+
+    #[test]
+    fn coincident_points_make_a_step() {
+        let points = opacity(&[(0.5, 0.), (0.5, 1.)]);
+        assert_eq!(sample(&points, 0.49), 0.);
+        assert_eq!(sample(&points, 0.51), 1.);
+    }
+
+    #[test]
+    fn bake_covers_the_whole_range() {
+        let tf = TransferFunction {
+            modulate: false,
+            color: vec![
+                ColorPoint {
+                    position: 0.,
+                    color: [0., 0., 1.],
+                },
+                ColorPoint {
+                    position: 1.,
+                    color: [1., 0., 0.],
+                },
+            ],
+            opacity: opacity(&[(0., 0.), (1., 1.)]),
+        };
+        let texels = tf.bake();
+        assert_eq!(texels.len(), RESOLUTION as usize);
+        assert_eq!(texels[0], [0., 0., 1., 0.]);
+        assert_eq!(texels[RESOLUTION as usize - 1], [1., 0., 0., 1.]);
+    }
+
+    #[test]
+    fn rejects_out_of_range_values() {
+        let valid = TransferFunction::default();
+        assert!(valid.validate().is_ok());
+
+        let mut tf = valid.clone();
+        tf.opacity[0].position = -0.1;
+        assert!(tf.validate().is_err());
+
+        let mut tf = valid.clone();
+        tf.color[0].color = [1.5, 0., 0.];
+        assert!(tf.validate().is_err());
+
+        let mut tf = valid.clone();
+        tf.opacity[3].opacity = f32::NAN;
+        assert!(tf.validate().is_err());
+
+        let mut tf = valid;
+        tf.color.clear();
+        assert!(tf.validate().is_err());
+    }
+
+    #[test]
+    fn default_follows_the_old_opacity_curve() {
+        let tf = TransferFunction::default();
+        assert!(tf.modulate);
+        for a in [0., 0.25, 0.5, 0.75, 1.] {
+            assert!((sample(&tf.opacity, a) - smoothstep_curve(a)).abs() < 1e-6);
+        }
+        // Between the control points the linear segments stay close to the curve.
+        for (i, texel) in tf.bake().iter().enumerate() {
+            let a = i as f32 / (RESOLUTION - 1) as f32;
+            assert!(
+                (texel[3] - smoothstep_curve(a)).abs() < 0.02,
+                "density {}",
+                a
+            );
+            assert_eq!(texel[..3], [1.; 3]);
+        }
+    }
+
+    #[test]
+    fn load_sorts_points_and_round_trips() {
+        let json = r#"{
+            "color": [{ "position": 1.0, "color": [1, 1, 1] }, { "position": 0.0, "color": [0, 0, 0] }],
+            "opacity": [{ "position": 0.9, "opacity": 1.0 }, { "position": 0.1, "opacity": 0.0 }]
+        }"#;
+        let dir = TestDir::new("transfer_function", &[("tf.json", json.as_bytes())]);
+        let path = dir.join("tf.json");
+        let tf = TransferFunction::load(&path).unwrap();
+        tf.save(&path).unwrap();
+        let reloaded = TransferFunction::load(&path).unwrap();
+
+        assert!(!tf.modulate);
+        assert_eq!(tf.color[0].position, 0.);
+        assert_eq!(tf.opacity[0].position, 0.1);
+        assert_eq!(reloaded, tf);
+    }
+}
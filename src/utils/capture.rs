@@ -0,0 +1,226 @@
+use half::f16;
+
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Layout of a texture copied into a buffer. Rows in the buffer have to be
+/// padded to [`wgpu::COPY_BYTES_PER_ROW_ALIGNMENT`].
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BufferDimensions {
+    pub(crate) height: u32,
+    pub(crate) unpadded_bytes_per_row: u32,
+    pub(crate) padded_bytes_per_row: u32,
+}
+
+impl BufferDimensions {
+    pub(crate) fn new(width: u32, height: u32, bytes_per_pixel: u32) -> Self {
+        let unpadded_bytes_per_row = width * bytes_per_pixel;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(align) * align;
+        Self {
+            height,
+            unpadded_bytes_per_row,
+            padded_bytes_per_row,
+        }
+    }
+
+    pub(crate) fn buffer_size(&self) -> wgpu::BufferAddress {
+        self.padded_bytes_per_row as wgpu::BufferAddress * self.height as wgpu::BufferAddress
+    }
+}
+
+/// Copies the first mip level of a 2D or 3D `texture` into a staging buffer,
+/// the slices of a 3D texture follow each other.
+pub(crate) fn copy_texture_to_staging(
+    device: &wgpu::Device,
+    encoder: &mut wgpu::CommandEncoder,
+    texture: &wgpu::Texture,
+) -> (wgpu::Buffer, BufferDimensions) {
+    let bytes_per_pixel = texture
+        .format()
+        .block_copy_size(None)
+        .expect("Texture format must be copyable");
+    let rows = texture.height() * texture.depth_or_array_layers();
+    let dims = BufferDimensions::new(texture.width(), rows, bytes_per_pixel);
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Capture Staging Buffer"),
+        size: dims.buffer_size(),
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(dims.padded_bytes_per_row),
+                rows_per_image: Some(texture.height()),
+            },
+        },
+        texture.size(),
+    );
+    (buffer, dims)
+}
+
+/// Maps a staging buffer filled by [`copy_texture_to_staging`] and strips the row padding.
+pub(crate) fn read_staging(
+    device: &wgpu::Device,
+    buffer: &wgpu::Buffer,
+    dims: &BufferDimensions,
+) -> Result<Vec<u8>, String> {
+    let slice = buffer.slice(..);
+    let (tx, rx) = std::sync::mpsc::channel();
+    slice.map_async(wgpu::MapMode::Read, move |res| {
+        tx.send(res).ok();
+    });
+    device.poll(wgpu::Maintain::Wait);
+    rx.recv()
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())?;
+
+    let padded = slice.get_mapped_range();
+    let mut pixels = Vec::with_capacity((dims.unpadded_bytes_per_row * dims.height) as usize);
+    for row in padded.chunks(dims.padded_bytes_per_row as usize) {
+        pixels.extend_from_slice(&row[..dims.unpadded_bytes_per_row as usize]);
+    }
+    drop(padded);
+    buffer.unmap();
+    Ok(pixels)
+}
+
+/// Pixels of a texture read back to the CPU, rows are tightly packed.
+pub(crate) struct Capture {
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) format: wgpu::TextureFormat,
+    pub(crate) data: Vec<u8>,
+}
+
+impl Capture {
+    /// Whether every color channel is zero, which usually means the pass never wrote the texture.
+    pub(crate) fn is_black(&self) -> bool {
+        let pixel_size = self.format.block_copy_size(None).unwrap_or(1) as usize;
+        let color_size = pixel_size * 3 / 4;
+        self.data
+            .chunks_exact(pixel_size)
+            .all(|px| px[..color_size].iter().all(|&b| b == 0))
+    }
+
+    /// Writes an 8-bit capture as a PNG, swapping BGRA formats back to RGBA.
+    pub(crate) fn save_png(&self, path: impl AsRef<Path>) -> Result<(), String> {
+        let path = path.as_ref();
+        let rgba = match self.format {
+            wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => {
+                self.data.clone()
+            }
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => self
+                .data
+                .chunks_exact(4)
+                .flat_map(|px| [px[2], px[1], px[0], px[3]])
+                .collect(),
+            format => return Err(format!("Can't save {:?} texture as PNG", format)),
+        };
+
+        let file = File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+        let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+        writer.write_image_data(&rgba).map_err(|e| e.to_string())
+    }
+
+    /// Writes a half float capture as an OpenEXR image, keeping the full HDR range.
+    pub(crate) fn save_exr(&self, path: impl AsRef<Path>) -> Result<(), String> {
+        let path = path.as_ref();
+        if self.format != wgpu::TextureFormat::Rgba16Float {
+            return Err(format!("Can't save {:?} texture as EXR", self.format));
+        }
+        let channel = |bytes: &[u8]| f16::from_le_bytes([bytes[0], bytes[1]]);
+        let pixels: Vec<_> = self
+            .data
+            .chunks_exact(8)
+            .map(|px| {
+                (
+                    channel(&px[0..]),
+                    channel(&px[2..]),
+                    channel(&px[4..]),
+                    channel(&px[6..]),
+                )
+            })
+            .collect();
+        let width = self.width as usize;
+        exr::prelude::write_rgba_file(path, width, self.height as usize, |x, y| {
+            pixels[y * width + x]
+        })
+        .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+/// Reads `textures` back to the CPU in a single submission, blocking until the GPU is done.
+pub(crate) fn read_textures(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    textures: &[&wgpu::Texture],
+) -> Result<Vec<Capture>, String> {
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Capture Encoder"),
+    });
+    let staging: Vec<_> = textures
+        .iter()
+        .map(|texture| copy_texture_to_staging(device, &mut encoder, texture))
+        .collect();
+    queue.submit(Some(encoder.finish()));
+
+    textures
+        .iter()
+        .zip(staging)
+        .map(|(texture, (buffer, dims))| {
+            Ok(Capture {
+                width: texture.width(),
+                height: texture.height(),
+                format: texture.format(),
+                data: read_staging(device, &buffer, &dims)?,
+            })
+        })
+        .collect()
+}
+
+/// Milliseconds since the Unix epoch, used to give captures unique names.
+pub(crate) fn timestamp() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_millis())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capture(format: wgpu::TextureFormat, data: Vec<u8>) -> Capture {
+        Capture {
+            width: 2,
+            height: 1,
+            format,
+            data,
+        }
+    }
+
+    #[test]
+    fn black_ignores_alpha() {
+        let format = wgpu::TextureFormat::Rgba8Unorm;
+        assert!(capture(format, vec![0, 0, 0, 255, 0, 0, 0, 255]).is_black());
+        assert!(!capture(format, vec![0, 0, 0, 255, 0, 1, 0, 255]).is_black());
+        let format = wgpu::TextureFormat::Rgba16Float;
+        let mut data = vec![0; 16];
+        data[14..].copy_from_slice(&[0x00, 0x3c]);
+        assert!(capture(format, data.clone()).is_black());
+        data[9] = 0x3c;
+        assert!(!capture(format, data).is_black());
+    }
+}
@@ -0,0 +1,74 @@
+use std::time::Duration;
+
+/// Picks the render scale that brings the GPU time of the raycast close to a target.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct DynamicResolution {
+    target: Duration,
+    min_scale: f32,
+    max_scale: f32,
+}
+
+impl DynamicResolution {
+    pub(crate) fn new(target: Duration, min_scale: f32, max_scale: f32) -> Self {
+        Self {
+            target,
+            min_scale,
+            max_scale,
+        }
+    }
+
+    /// Scale for the next frames, given the current one and how long the raycast took with it.
+    pub(crate) fn next_scale(&self, scale: f32, gpu_time: Duration) -> f32 {
+        let ratio = self.target.as_secs_f32() / gpu_time.as_secs_f32().max(1e-6);
+        // Chasing noise would reallocate the backbuffer and restart accumulation all the time.
+        if (0.9..=1.1).contains(&ratio) {
+            return scale.clamp(self.min_scale, self.max_scale);
+        }
+        // The cost grows with the number of pixels, the square of the scale.
+        let next = scale * ratio.sqrt().clamp(0.75, 1.25);
+        let next = (next * 32.).round() / 32.;
+        next.clamp(self.min_scale, self.max_scale)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TARGET: Duration = Duration::from_millis(16);
+
+    fn controller() -> DynamicResolution {
+        DynamicResolution::new(TARGET, 0.25, 2.)
+    }
+
+    #[test]
+    fn small_deviations_keep_the_scale() {
+        let dynamic = controller();
+        assert_eq!(dynamic.next_scale(1., TARGET * 21 / 20), 1.);
+        assert_eq!(dynamic.next_scale(1., TARGET * 19 / 20), 1.);
+        // Even without a change, the scale is kept within bounds.
+        assert_eq!(dynamic.next_scale(3., TARGET), 2.);
+    }
+
+    #[test]
+    fn steps_are_limited() {
+        let dynamic = controller();
+        assert_eq!(dynamic.next_scale(1., TARGET / 16), 1.25);
+        assert_eq!(dynamic.next_scale(1., TARGET * 16), 0.75);
+    }
+
+    #[test]
+    fn scales_round_to_32nds() {
+        let dynamic = controller();
+        // Needs 1.21x the pixels, 1.1x the scale, which rounds to 35/32.
+        let gpu_time = TARGET.div_f64(1.21);
+        assert_eq!(dynamic.next_scale(1., gpu_time), 35. / 32.);
+    }
+
+    #[test]
+    fn scales_stay_within_bounds() {
+        let dynamic = controller();
+        assert_eq!(dynamic.next_scale(0.25, TARGET * 16), 0.25);
+        assert_eq!(dynamic.next_scale(2., TARGET / 16), 2.);
+    }
+}
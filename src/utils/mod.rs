@@ -1,5 +1,11 @@
+pub(crate) mod capture;
+pub(crate) mod dynamic_resolution;
 pub(crate) mod frame_counter;
 pub(crate) mod input;
+#[cfg(test)]
+pub(crate) mod test_dir;
+
+use pollster::FutureExt;
 
 use std::{
     collections::HashMap,
@@ -13,6 +19,17 @@ pub(crate) fn dispatch_optimal(len: u32, subgroup_size: u32) -> u32 {
     (len + padded_size) / subgroup_size
 }
 
+/// Runs `f` inside a validation error scope so that a broken shader
+/// or pipeline reports an error instead of taking the whole app down.
+pub(crate) fn validate<T>(device: &wgpu::Device, f: impl FnOnce() -> T) -> Result<T, wgpu::Error> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let res = f();
+    match device.pop_error_scope().block_on() {
+        Some(err) => Err(err),
+        None => Ok(res),
+    }
+}
+
 pub(crate) trait NonZeroSized: Sized {
     const SIZE: NonZeroU64 = unsafe { NonZeroU64::new_unchecked(std::mem::size_of::<Self>() as _) };
 }
@@ -0,0 +1,27 @@
+use std::path::PathBuf;
+
+/// A scratch folder in the system temp dir for tests that go through the
+/// file loaders. It is removed on drop, so failing tests clean up as well.
+pub(crate) struct TestDir(PathBuf);
+
+impl TestDir {
+    /// Creates a folder for the test `name` and writes `files` into it.
+    pub(crate) fn new(name: &str, files: &[(&str, &[u8])]) -> Self {
+        let dir = std::env::temp_dir().join(format!("vokselis_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (file, bytes) in files {
+            std::fs::write(dir.join(file), bytes).unwrap();
+        }
+        Self(dir)
+    }
+
+    pub(crate) fn join(&self, file: &str) -> PathBuf {
+        self.0.join(file)
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.0).ok();
+    }
+}
@@ -0,0 +1,86 @@
+use crate::utils::validate;
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher as _};
+use winit::event_loop::EventLoopProxy;
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc},
+    thread,
+    time::Duration,
+};
+
+/// Directory the shaders are hot-reloaded from unless `--shader-dir` points elsewhere.
+pub(crate) const SHADER_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/shaders");
+
+/// Editors tend to emit a burst of events per save, collect them before compiling.
+const DEBOUNCE: Duration = Duration::from_millis(50);
+
+/// Watches the shader folder and sends freshly compiled modules to the event loop.
+pub(crate) struct Watcher {
+    _watcher: RecommendedWatcher,
+}
+
+impl Watcher {
+    pub(crate) fn new(
+        device: Arc<wgpu::Device>,
+        proxy: EventLoopProxy<(PathBuf, wgpu::ShaderModule)>,
+        dir: &Path,
+    ) -> Result<Self, String> {
+        let (tx, rx) = mpsc::channel();
+        let mut watcher =
+            notify::recommended_watcher(move |res: notify::Result<notify::Event>| match res {
+                Ok(event) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) => {
+                    for path in event.paths {
+                        tx.send(path).ok();
+                    }
+                }
+                Ok(_) => {}
+                Err(err) => eprintln!("Shader watcher error: {}", err),
+            })
+            .map_err(|e| e.to_string())?;
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .map_err(|e| e.to_string())?;
+
+        thread::Builder::new()
+            .name("Shader Compiler".into())
+            .spawn(move || {
+                while let Ok(path) = rx.recv() {
+                    let mut changed = HashSet::from([path]);
+                    while let Ok(path) = rx.recv_timeout(DEBOUNCE) {
+                        changed.insert(path);
+                    }
+                    for path in changed {
+                        if path.extension().map_or(true, |ext| ext != "wgsl") {
+                            continue;
+                        }
+                        match compile_shader(&device, &path) {
+                            Ok(module) => {
+                                if proxy.send_event((path, module)).is_err() {
+                                    // Event loop is gone, nobody to reload shaders for.
+                                    return;
+                                }
+                            }
+                            Err(err) => eprintln!("Failed to compile {}:\n{}", path.display(), err),
+                        }
+                    }
+                }
+            })
+            .map_err(|e| e.to_string())?;
+
+        Ok(Self { _watcher: watcher })
+    }
+}
+
+fn compile_shader(device: &wgpu::Device, path: &Path) -> Result<wgpu::ShaderModule, String> {
+    let source = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    validate(device, || {
+        device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: path.file_name().and_then(|name| name.to_str()),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        })
+    })
+    .map_err(|e| e.to_string())
+}